@@ -0,0 +1,15 @@
+//! A progressive GPU path tracer built on wgpu.
+//!
+//! Build a [`Scene`], create a [`PathTracer`] on an existing device, then either call
+//! [`PathTracer::render_frame`] to trace and draw into a texture or [`PathTracer::render`] to
+//! trace offline, and fetch the image with [`PathTracer::read_output`].
+
+pub mod renderer;
+
+pub use renderer::{
+    math,
+    scene::{Environment, Geometry, Material, Node, Object, Primitive, Scene, Shape, Viewpoint},
+    furnace_scene, render_reference, Aov, BuiltinScene, CryptomatteLayer, DebugMode, DebugPath, FrameStats,
+    FurnaceReading, ImageComparison, FURNACE_SIZE, PathEvent,
+    PathTracer, PathVertex, Pick, RenderOutput, RenderSettings,
+};
@@ -1,174 +1,833 @@
 use {
-    anyhow::{Context, Result}, std::array, wgpu, winit::{
-        event::{DeviceEvent, Event, MouseScrollDelta, WindowEvent}, event_loop::{ControlFlow, EventLoop}, keyboard::KeyCode, window::{Window, WindowBuilder}
+    anyhow::{bail, Context, Result},
+    clap::{
+        builder::{PossibleValuesParser, TypedValueParser},
+        Args, Parser, Subcommand, ValueEnum,
+    },
+    code::{
+        furnace_scene, render_reference, BuiltinScene, FurnaceReading, ImageComparison, PathTracer,
+        RenderOutput, RenderSettings, Scene, FURNACE_SIZE,
+    },
+    std::{
+        io::Write,
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    },
+    winit::{
+        event::{DeviceEvent, ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent}, event_loop::{ControlFlow, EventLoop}, keyboard::{KeyCode, ModifiersState, PhysicalKey}, window::{Window, WindowBuilder}
     }
 };
-mod renderer;
 
-const WIDTH: u32 = 1920;
-const HEIGHT: u32 = 1200;
+mod ui;
 
+/// GPU path tracer. Without a subcommand the viewer opens the default built-in scene.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Open a scene in the interactive viewer.
+    View {
+        #[command(flatten)]
+        scene: SceneSource,
+        /// Present in HDR through an Rgba16Float swapchain if the display supports it.
+        #[arg(long)]
+        hdr: bool,
+        #[command(flatten)]
+        options: RenderOptions,
+    },
+    /// Render a scene offline and write it to an OpenEXR file.
+    Render {
+        #[command(flatten)]
+        scene: SceneSource,
+        /// Output file.
+        #[arg(short, long, value_parser = parse_exr_path)]
+        output: PathBuf,
+        /// Samples per pixel.
+        #[arg(long, default_value_t = 1024, value_parser = clap::value_parser!(u32).range(1..))]
+        spp: u32,
+        /// Write the accumulated samples without the spatial denoising filter.
+        #[arg(long)]
+        no_denoise: bool,
+        /// Trace on the CPU with the reference implementation of the integrator, which is never
+        /// denoised.
+        #[arg(long, conflicts_with = "no_denoise")]
+        cpu: bool,
+        /// Write the AOVs as well, as layers of the output file or as files next to it.
+        #[arg(long, num_args = 0..=1, default_missing_value = "layers", conflicts_with = "cpu")]
+        aovs: Option<AovOutput>,
+        /// Write Cryptomattes of the objects and materials as well, where `--aovs` puts the AOVs.
+        #[arg(long, conflicts_with = "cpu")]
+        cryptomatte: bool,
+        #[command(flatten)]
+        options: RenderOptions,
+    },
+    /// Render a scene on the GPU and on the CPU with the same seed and samples per pixel, and
+    /// report how far the GPU image is from the CPU reference.
+    Compare {
+        #[command(flatten)]
+        scene: SceneSource,
+        /// Samples per pixel.
+        #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u32).range(1..))]
+        spp: u32,
+        /// Write the absolute difference of every pixel to this file.
+        #[arg(long, value_parser = parse_exr_path)]
+        error_image: Option<PathBuf>,
+        #[command(flatten)]
+        options: RenderOptions,
+    },
+    /// Render a ball of each material of a scene alone in a uniform white environment and report
+    /// how much light it returns, which is more than its color for materials that gain energy.
+    Furnace {
+        #[command(flatten)]
+        scene: SceneSource,
+        /// Samples per pixel.
+        #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u32).range(1..))]
+        spp: u32,
+        /// Trace on the CPU with the reference implementation of the integrator.
+        #[arg(long)]
+        cpu: bool,
+    },
+}
+
+/// Where `render` writes the AOVs.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum AovOutput {
+    /// Layers of the output file, next to the beauty pass.
+    Layers,
+    /// Files named `<stem>.<aov name>.exr` next to the output file.
+    Files,
+}
+
+/// The scene to open: a file, or one of the scenes built into the tracer. The default built-in
+/// scene if neither is given.
+#[derive(Args)]
+#[group(multiple = false)]
+struct SceneSource {
+    /// TOML scene file.
+    scene: Option<PathBuf>,
+    /// Built-in scene.
+    #[arg(long, value_parser = builtin_parser())]
+    builtin: Option<BuiltinScene>,
+}
+
+/// Options shared by the viewer and offline rendering.
+#[derive(Args)]
+struct RenderOptions {
+    /// Image width in pixels.
+    #[arg(long, default_value_t = 1920, value_parser = clap::value_parser!(u32).range(1..))]
+    width: u32,
+    /// Image height in pixels.
+    #[arg(long, default_value_t = 1200, value_parser = clap::value_parser!(u32).range(1..))]
+    height: u32,
+    // The render settings below override the scene file's `[settings]` table.
+    /// Longest path traced per sample.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_bounces: Option<u32>,
+    /// Samples traced per pixel in each frame.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    samples: Option<u32>,
+    /// Most transparent surfaces a path passes through before it ends.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_transparent_bounces: Option<u32>,
+    /// Blur the displayed image with a box filter.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    anti_aliasing: Option<bool>,
+    /// Half the width of the anti-aliasing box, in pixels.
+    #[arg(long)]
+    anti_aliasing_radius: Option<u32>,
+    /// Extra weight of the center pixel in the anti-aliasing box.
+    #[arg(long, value_parser = parse_weight)]
+    anti_aliasing_weight: Option<f32>,
+    /// Random seed; renders with different seeds get independent noise.
+    #[arg(long)]
+    seed: Option<u32>,
+}
+
+impl RenderOptions {
+    /// Overrides the scene's settings with the ones given on the command line.
+    fn apply(&self, settings: &mut RenderSettings) {
+        let RenderOptions {
+            width: _,
+            height: _,
+            max_bounces,
+            samples,
+            max_transparent_bounces,
+            anti_aliasing,
+            anti_aliasing_radius,
+            anti_aliasing_weight,
+            seed,
+        } = *self;
+        settings.max_bounces = max_bounces.unwrap_or(settings.max_bounces);
+        settings.samples = samples.unwrap_or(settings.samples);
+        settings.max_transparent_bounces =
+            max_transparent_bounces.unwrap_or(settings.max_transparent_bounces);
+        settings.anti_aliasing = anti_aliasing.unwrap_or(settings.anti_aliasing);
+        settings.anti_aliasing_radius = anti_aliasing_radius.unwrap_or(settings.anti_aliasing_radius);
+        settings.anti_aliasing_weight = anti_aliasing_weight.unwrap_or(settings.anti_aliasing_weight);
+        settings.seed = seed.unwrap_or(settings.seed);
+    }
+
+    /// Rejects image sizes the device cannot allocate.
+    fn check_size(&self, device: &wgpu::Device) -> Result<()> {
+        let max = device.limits().max_texture_dimension_2d;
+        if self.width > max || self.height > max {
+            bail!("{}x{} is larger than the GPU supports ({max}x{max})", self.width, self.height);
+        }
+        Ok(())
+    }
+}
+
+fn parse_exr_path(arg: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(arg);
+    match path.extension() {
+        Some(extension) if extension.eq_ignore_ascii_case("exr") => Ok(path),
+        _ => Err("the output must be an .exr file".to_string()),
+    }
+}
+
+/// Accepts the names of the built-in scenes and lists them in the help.
+fn builtin_parser() -> impl TypedValueParser<Value = BuiltinScene> {
+    PossibleValuesParser::new(BuiltinScene::ALL.map(BuiltinScene::name))
+        .map(|name| BuiltinScene::from_name(&name).expect("a built-in scene name"))
+}
+
+fn parse_weight(arg: &str) -> Result<f32, String> {
+    match arg.parse::<f32>() {
+        Ok(weight) if weight >= 0.0 && weight.is_finite() => Ok(weight),
+        _ => Err("expected a non-negative number".to_string()),
+    }
+}
+
+impl SceneSource {
+    fn load(&self) -> Result<Scene> {
+        match (&self.scene, self.builtin) {
+            (Some(path), _) => Scene::load(path),
+            (None, builtin) => Ok(builtin.unwrap_or(BuiltinScene::Default).build()),
+        }
+    }
+}
+
+fn load_scene(source: &SceneSource, options: &RenderOptions) -> Result<Scene> {
+    let mut scene = source.load()?;
+    options.apply(&mut scene.settings);
+    Ok(scene)
+}
+
+/// Steps the render settings with the number keys: 1/2 max bounces, 3/4 samples per frame, 5/6
+/// transparent bounces, 7/8 anti-aliasing radius and 9/0 anti-aliasing weight. B toggles
+/// anti-aliasing. Returns whether `key` changed a setting.
+fn adjust_settings(settings: &mut RenderSettings, key: KeyCode) -> bool {
+    let halve = |value: u32| (value / 2).max(1);
+    let double = |value: u32| value.saturating_mul(2);
+    match key {
+        KeyCode::Digit1 => settings.max_bounces = halve(settings.max_bounces),
+        KeyCode::Digit2 => settings.max_bounces = double(settings.max_bounces),
+        KeyCode::Digit3 => settings.samples = halve(settings.samples),
+        KeyCode::Digit4 => settings.samples = double(settings.samples),
+        KeyCode::Digit5 => settings.max_transparent_bounces = halve(settings.max_transparent_bounces),
+        KeyCode::Digit6 => settings.max_transparent_bounces = double(settings.max_transparent_bounces),
+        KeyCode::Digit7 => settings.anti_aliasing_radius = settings.anti_aliasing_radius.saturating_sub(1),
+        KeyCode::Digit8 => settings.anti_aliasing_radius += 1,
+        KeyCode::Digit9 => settings.anti_aliasing_weight = (settings.anti_aliasing_weight - 1.0).max(0.0),
+        KeyCode::Digit0 => settings.anti_aliasing_weight += 1.0,
+        KeyCode::KeyB => settings.anti_aliasing = !settings.anti_aliasing,
+        _ => return false,
+    }
+    true
+}
 
 #[pollster::main]
 async fn main() -> Result<()> {
+    // Without a subcommand, behave like `view` with default options.
+    let command = match Cli::parse().command {
+        Some(command) => command,
+        None => Cli::parse_from(["", "view"]).command.context("no default command")?,
+    };
+    match command {
+        Command::View { scene, hdr, options } => {
+            // The built-in scene is saved to a new file rather than anywhere it came from.
+            let save_path = scene.scene.clone().unwrap_or_else(|| PathBuf::from("scene.toml"));
+            view(load_scene(&scene, &options)?, &save_path, hdr, &options).await
+        }
+        Command::Render {
+            scene,
+            output,
+            spp,
+            no_denoise,
+            cpu,
+            aovs,
+            cryptomatte,
+            options,
+        } => {
+            let scene = load_scene(&scene, &options)?;
+            let rendered = if cpu {
+                let viewpoint = scene.camera.unwrap_or_default();
+                render_reference(&scene, &viewpoint, [options.width, options.height], spp)
+            } else {
+                render_on_gpu(scene, spp, !no_denoise, aovs.is_some(), cryptomatte, &options).await?
+            };
+            match aovs {
+                Some(AovOutput::Files) => rendered.save_exr_files(&output)?,
+                Some(AovOutput::Layers) | None => rendered.save_exr(&output)?,
+            }
+            println!("saved {}", output.display());
+            Ok(())
+        }
+        Command::Compare {
+            scene,
+            spp,
+            error_image,
+            options,
+        } => compare(load_scene(&scene, &options)?, spp, error_image.as_deref(), &options).await,
+        Command::Furnace { scene, spp, cpu } => {
+            furnace(&scene.load()?, spp, cpu).await
+        }
+    }
+}
+
+async fn furnace(scene: &Scene, spp: u32, cpu: bool) -> Result<()> {
+    let readings = if cpu {
+        FurnaceReading::measure_all(&scene.materials, spp)
+    } else {
+        let (device, queue, status) = connect_headless().await?;
+        let mut renderer = PathTracer::new(
+            &device,
+            &queue,
+            BuiltinScene::Furnace.build(),
+            FURNACE_SIZE,
+            FURNACE_SIZE,
+            wgpu::TextureFormat::Rgba8Unorm,
+        )?;
+        renderer.denoise = false;
+        let mut readings = Vec::new();
+        for material in &scene.materials {
+            renderer.set_scene(furnace_scene(material))?;
+            accumulate(&mut renderer, &device, &status, spp)?;
+            readings.push(FurnaceReading::new(material, &renderer.read_output()?));
+        }
+        eprintln!();
+        readings
+    };
+    println!("white furnace, environment radiance 1:");
+    for reading in &readings {
+        // Noise and the silhouette pixels left out leave some slack.
+        let verdict = match (reading.gain(), reading.loss()) {
+            (gain, _) if gain > 1e-3 => format!(", gains {gain:.4}"),
+            (_, loss) if loss > 1e-3 => format!(", loses {loss:.4}"),
+            _ => String::new(),
+        };
+        println!("{reading}{verdict}");
+    }
+    Ok(())
+}
+
+async fn compare(scene: Scene, spp: u32, error_image: Option<&Path>, options: &RenderOptions) -> Result<()> {
+    let start = Instant::now();
+    let viewpoint = scene.camera.unwrap_or_default();
+    let reference = render_reference(&scene, &viewpoint, [options.width, options.height], spp);
+    eprintln!("traced the reference on the CPU in {:.1?}", start.elapsed());
+    // Denoising would blur the GPU image, which the reference is not.
+    let rendered = render_on_gpu(scene, spp, false, false, false, options).await?;
+    let comparison = ImageComparison::new(&rendered, &reference)?;
+    println!("{comparison}");
+    if let Some(path) = error_image {
+        comparison.error_image().save_exr(path)?;
+        println!("saved {}", path.display());
+    }
+    Ok(())
+}
+
+/// Renders `scene` offline on the GPU with at least `spp` samples per pixel.
+async fn render_on_gpu(
+    scene: Scene,
+    spp: u32,
+    denoise: bool,
+    aovs: bool,
+    cryptomatte: bool,
+    options: &RenderOptions,
+) -> Result<RenderOutput> {
+    let (device, queue, status) = connect_headless().await?;
+    options.check_size(&device)?;
+    // Nothing is displayed, so the target format does not matter.
+    let mut renderer = PathTracer::new(
+        &device,
+        &queue,
+        scene,
+        options.width,
+        options.height,
+        wgpu::TextureFormat::Rgba8Unorm,
+    )?;
+    renderer.denoise = denoise;
+    renderer.set_aovs_enabled(aovs);
+    renderer.set_cryptomatte_enabled(cryptomatte)?;
+    accumulate(&mut renderer, &device, &status, spp)?;
+    eprintln!();
+    eprintln!("{}", renderer.stats());
+    renderer.read_output()
+}
+
+/// Traces frames until the image holds at least `spp` samples per pixel.
+fn accumulate(renderer: &mut PathTracer, device: &wgpu::Device, status: &DeviceStatus, spp: u32) -> Result<()> {
+    // With the camera still, every frame adds `samples` samples per pixel to the accumulated
+    // image.
+    let samples = renderer.settings.samples;
+    let frames = spp.div_ceil(samples);
+    for frame in 1..=frames {
+        renderer.render();
+        device.poll(wgpu::PollType::Wait)?;
+        if status.lost.load(Ordering::Relaxed) {
+            bail!("lost the GPU device while rendering");
+        }
+        eprint!("\rsample {}/{}", frame * samples, frames * samples);
+        std::io::stderr().flush()?;
+    }
+    if status.errors.load(Ordering::Relaxed) {
+        bail!("the GPU reported errors while rendering");
+    }
+    Ok(())
+}
+
+const TITLE: &str = "GPU Path Tracer";
+
+/// How often the performance stats in the window title are refreshed.
+const TITLE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Opens `scene` in a window. Ctrl+S saves it to `save_path` with the current camera and settings.
+async fn view(scene: Scene, save_path: &Path, hdr: bool, options: &RenderOptions) -> Result<()> {
+    let (width, height) = (options.width, options.height);
     let event_loop = EventLoop::new()?;
-    let window_size = winit::dpi::PhysicalSize::new(WIDTH, HEIGHT);
+    let window_size = winit::dpi::PhysicalSize::new(width, height);
     let window = WindowBuilder::new()  
         .with_inner_size(window_size)
         .with_resizable(true)
-        .with_title("GPU Path Tracer".to_string())
+        .with_title(TITLE)
         .build(&event_loop)?;
-    window.set_cursor_grab(winit::window::CursorGrabMode::Locked);
-    let (device, queue, surface) = connect_to_gpu(&window).await?;
-    let mut renderer = renderer::PathTracer::new(device, queue);
+    let _ = window.set_cursor_grab(winit::window::CursorGrabMode::Locked);
+    let mut gpu = Gpu::connect(&window, hdr).await?;
+    options.check_size(&gpu.device)?;
+    let mut renderer = PathTracer::new(&gpu.device, &gpu.queue, scene, width, height, gpu.format())?;
+    let mut overlay = ui::Overlay::new(&gpu.device, gpu.format());
+    // Set when the viewer has to close because of an error.
+    let mut fatal: Option<anyhow::Error> = None;
+
+    let mut last_title = Instant::now();
 
     let mut prev: [f32; 2] = [-1.0,-1.0];
-    let mut mouseSens = 0.0005;
-    window.set_cursor_grab(winit::window::CursorGrabMode::Locked);
+    let mut modifiers = ModifiersState::empty();
+    let _ = window.set_cursor_grab(winit::window::CursorGrabMode::Locked);
 
     // TODO: initialize renderer
 
     event_loop.run(|event, control_handle| {
         
-        window.set_cursor_grab(winit::window::CursorGrabMode::Locked);
+        // The overlay needs a free cursor; otherwise the mouse turns the camera.
+        if !overlay.visible {
+            let _ = window.set_cursor_grab(winit::window::CursorGrabMode::Locked);
+        }
         control_handle.set_control_flow(ControlFlow::Poll);
+        // Rotation speed in radians per pixel, slower when zoomed in.
+        let mouse_sens = (0.0005*10.0)/renderer.fov;
         match event {
+            Event::WindowEvent { event, .. } if overlay.on_event(&window, &event) => {}
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::CloseRequested => control_handle.exit(),
+                WindowEvent::Resized(size) => gpu.reconfigure(size),
                 WindowEvent::RedrawRequested => {
                     //😊
-                    let frame: wgpu::SurfaceTexture = surface
-                        .get_current_texture()
-                        .expect("failed to get current texture");
-
+                    if gpu.status.lost.load(Ordering::Relaxed) {
+                        let reconnected = pollster::block_on(gpu.reconnect())
+                            .and_then(|()| recreate_renderer(&renderer, &gpu));
+                        match reconnected {
+                            Ok(new) => {
+                                println!("reconnected to the GPU");
+                                renderer = new;
+                                overlay.reconnect(&gpu.device, gpu.format());
+                            }
+                            Err(err) => {
+                                fatal = Some(err.context("failed to recover from losing the GPU device"));
+                                control_handle.exit();
+                                return;
+                            }
+                        }
+                    }
+                    let frame = match gpu.next_frame(window.inner_size()) {
+                        Ok(Some(frame)) => frame,
+                        Ok(None) => {
+                            window.request_redraw();
+                            return;
+                        }
+                        Err(err) => {
+                            fatal = Some(err);
+                            control_handle.exit();
+                            return;
+                        }
+                    };
 
                     let render_target = frame.texture.create_view(&wgpu::wgt::TextureViewDescriptor::default());
                     renderer.render_frame(&render_target);
+                    if let Err(err) = overlay.draw(&window, &gpu.device, &gpu.queue, &render_target, &mut renderer) {
+                        eprintln!("{err:#}");
+                    }
+                    match renderer.take_debug_path() {
+                        Ok(Some(path)) => print!("{path}"),
+                        Ok(None) => {}
+                        Err(err) => eprintln!("{err:#}"),
+                    }
 
                     frame.present();
+                    if last_title.elapsed() >= TITLE_INTERVAL {
+                        window.set_title(&format!("{TITLE} - {}", renderer.stats()));
+                        last_title = Instant::now();
+                    }
                     window.request_redraw();
                 },
-                WindowEvent::KeyboardInput { device_id, event, is_synthetic } =>{
+                WindowEvent::ModifiersChanged(new) => modifiers = new.state(),
+                // Shortcuts with Ctrl leave the camera where it is.
+                WindowEvent::KeyboardInput { event, .. } if modifiers.control_key() => {
+                    if event.state != ElementState::Pressed || event.repeat {
+                        return;
+                    }
+                    let edited = match event.physical_key {
+                        PhysicalKey::Code(KeyCode::KeyZ) if modifiers.shift_key() => overlay.redo(&mut renderer),
+                        PhysicalKey::Code(KeyCode::KeyZ) => overlay.undo(&mut renderer),
+                        PhysicalKey::Code(KeyCode::KeyY) => overlay.redo(&mut renderer),
+                        PhysicalKey::Code(KeyCode::KeyS) => {
+                            match renderer.current_scene().save(save_path) {
+                                Ok(()) => println!("saved {}", save_path.display()),
+                                Err(err) => eprintln!("{err:#}"),
+                            }
+                            Ok(())
+                        }
+                        _ => Ok(()),
+                    };
+                    if let Err(err) = edited {
+                        eprintln!("{err:#}");
+                    }
+                }
+                WindowEvent::KeyboardInput { event, .. } =>{
                     let key = event.physical_key;
-                    if(key == KeyCode::ArrowRight){
+                    if key == KeyCode::ArrowRight {
                         renderer.camera.moves(0.03,0.00,0.0);
                     }
-                    if(key == KeyCode::ArrowLeft){
+                    if key == KeyCode::ArrowLeft {
                         renderer.camera.moves(-0.03,0.00,0.0);
                     }
-                    if(key == KeyCode::ArrowUp){
+                    if key == KeyCode::ArrowUp {
                         renderer.camera.moves(-0.00,0.03,0.0);
                     }
-                    if(key == KeyCode::ArrowDown){
+                    if key == KeyCode::ArrowDown {
                         renderer.camera.moves(-0.00,-0.03,0.0);
                     }
 
-                    if(key == KeyCode::KeyQ){
+                    if key == KeyCode::KeyQ {
                         renderer.camera.shift(0.00,0.03,0.0);
                     }
-                    if(key == KeyCode::KeyA){
+                    if key == KeyCode::KeyA {
                         renderer.camera.shift(-0.03,0.00,0.0);
                     }
-                    if(key == KeyCode::KeyE){
+                    if key == KeyCode::KeyE {
                         renderer.camera.shift(0.00,-0.03,0.00);
                     }
-                    if(key == KeyCode::KeyD){
+                    if key == KeyCode::KeyD {
                         renderer.camera.shift(0.03,0.00,0.00);
                     }
-                    if(key == KeyCode::KeyS){
+                    if key == KeyCode::KeyS {
                         renderer.camera.shift(0.00,0.00,0.03);
                     }
-                    if(key == KeyCode::KeyW){
+                    if key == KeyCode::KeyW {
                         renderer.camera.shift(0.00,0.00,-0.03);
                     }
+                    if key == KeyCode::Tab && event.state == ElementState::Pressed && !event.repeat {
+                        overlay.visible = !overlay.visible;
+                        let grab = if overlay.visible {
+                            winit::window::CursorGrabMode::None
+                        } else {
+                            winit::window::CursorGrabMode::Locked
+                        };
+                        let _ = window.set_cursor_grab(grab);
+                        window.set_cursor_visible(overlay.visible);
+                        // Start turning from wherever the cursor is when the overlay closes.
+                        prev = [-1.0, -1.0];
+                    }
+                    if key == KeyCode::KeyF && event.state == ElementState::Pressed && !event.repeat {
+                        renderer.denoise = !renderer.denoise;
+                    }
+                    if key == KeyCode::KeyO && event.state == ElementState::Pressed && !event.repeat {
+                        renderer.set_aovs_enabled(!renderer.aovs_enabled());
+                    }
+                    if key == KeyCode::KeyV && event.state == ElementState::Pressed && !event.repeat {
+                        renderer.debug_mode = renderer.debug_mode.next();
+                        println!("debug view: {}", renderer.debug_mode.name());
+                    }
+                    if key == KeyCode::KeyP && event.state == ElementState::Pressed && !event.repeat {
+                        renderer.set_path_overlay_enabled(!renderer.path_overlay_enabled());
+                    }
+                    if key == KeyCode::KeyC
+                        && event.state == ElementState::Pressed
+                        && !event.repeat
+                        && let Err(err) = renderer.set_cryptomatte_enabled(!renderer.cryptomatte_enabled())
+                    {
+                        eprintln!("{err:#}");
+                    }
+                    if let PhysicalKey::Code(code) = key
+                        && event.state == ElementState::Pressed
+                        && adjust_settings(&mut renderer.settings, code)
+                    {
+                        println!("{:?}", renderer.settings);
+                    }
+                    if (key == KeyCode::F12 || key == KeyCode::F11) && event.state == ElementState::Pressed && !event.repeat {
+                        let path = std::path::Path::new("render.exr");
+                        let saved = renderer.read_output().and_then(|output| {
+                            if key == KeyCode::F12 {
+                                output.save_exr(path)
+                            } else {
+                                output.save_exr_files(path)
+                            }
+                        });
+                        match saved {
+                            Ok(()) => println!("saved {}", path.display()),
+                            Err(err) => eprintln!("{err:#}"),
+                        }
+                    }
                     
                     
                     
                 },
-                WindowEvent::CursorMoved { device_id, position } =>{
-                    if((prev[0] - -1.0).abs() < 0.0001){
+                // The cursor is locked, so clicking logs the path under the crosshair.
+                WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Left, .. }
+                    if !overlay.visible =>
+                {
+                    renderer.debug_pixel(width / 2, height / 2);
+                }
+                WindowEvent::CursorMoved { position, .. } if !overlay.visible =>{
+                    if (prev[0] - -1.0).abs() < 0.0001 {
                         prev = [position.x as f32, position.y as f32];
                     }
                     
-                    renderer.camera.rotate((position.y as f32 - prev[1])*mouseSens,(position.x as f32 - prev[0])*mouseSens);
+                    renderer.camera.rotate((position.y as f32 - prev[1])*mouse_sens,(position.x as f32 - prev[0])*mouse_sens);
                     renderer.camera.set_w();
                     
                     prev = [position.x as f32, position.y as f32];
                 }
                 _ => (),
             },
-            Event::DeviceEvent { event, .. } => match event {
-                DeviceEvent::MouseWheel { delta } => {
-                    match delta{
-                        MouseScrollDelta::LineDelta(x, y) => {
-                            renderer.fov = (renderer.fov + y as f32 * renderer.fov).clamp(0.25, 100000.00);
-                            mouseSens = (0.0005*10.0)/renderer.fov;
-                            
-                        }
-                        MouseScrollDelta::PixelDelta(pos) => {
-                            // Do nothing
-                        }
+            Event::DeviceEvent { event: DeviceEvent::MouseWheel { delta }, .. } if !overlay.wants_pointer() => {
+                match delta{
+                    MouseScrollDelta::LineDelta(_, y) => {
+                        renderer.fov = (renderer.fov + y * renderer.fov).clamp(0.25, 100000.00);
+                        
                     }
+                    MouseScrollDelta::PixelDelta(_) => {
+                        // Do nothing
+                    }
+                }
 
-                },
-                _ => {},
             },
             _ => (),
         }
     })?;
-    Ok(())
+    match fatal {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
 }
 
-async fn connect_to_gpu(
-    window: &Window
-) -> Result<(wgpu::Device, wgpu::Queue, wgpu::Surface)> {
-    use wgpu::TextureFormat::{Bgra8Unorm, Rgba8Unorm};
-
-    // Create an "instance" of wgpu. This is the entry-point to the API.
-    let instance = wgpu::Instance::default();
-
-    // Create a drawable "surface" that is associated with the window.
-    let surface = instance.create_surface(window)?;
-
-    // Request a GPU that is compatible with the surface. If the system has multiple GPUs then
-    // pick the high performance one.
+/// Connects to a GPU for offline rendering, without a window.
+async fn connect_headless() -> Result<(wgpu::Device, wgpu::Queue, Arc<DeviceStatus>)> {
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::from_env_or_default());
     let adapter = instance
         .request_adapter(&wgpu::RequestAdapterOptions {
             power_preference: wgpu::PowerPreference::HighPerformance,
             force_fallback_adapter: false,
-            compatible_surface: Some(&surface),
+            compatible_surface: None,
         })
         .await
-        .context("failed to find a compatible adapter")?;
+        .context("failed to find an adapter")?;
+    request_device(&adapter).await
+}
 
-    // Connect to the GPU. "device" represents the connection to the GPU and allows us to create
-    // resources like buffers, textures, and pipelines. "queue" represents the command queue that
-    // we use to submit commands to the GPU.
+/// The viewer's connection to the GPU, with what it takes to reconfigure the surface and to
+/// reconnect after the device is lost.
+struct Gpu<'window> {
+    adapter: wgpu::Adapter,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    status: Arc<DeviceStatus>,
+    surface: wgpu::Surface<'window>,
+    config: wgpu::SurfaceConfiguration,
+}
+
+impl<'window> Gpu<'window> {
+    async fn connect(window: &'window Window, hdr: bool) -> Result<Gpu<'window>> {
+        // Create an "instance" of wgpu. This is the entry-point to the API.
+        let instance = wgpu::Instance::default();
+
+        // Create a drawable "surface" that is associated with the window.
+        let surface = instance.create_surface(window)?;
+
+        // Request a GPU that is compatible with the surface. If the system has multiple GPUs then
+        // pick the high performance one.
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter: false,
+                compatible_surface: Some(&surface),
+            })
+            .await
+            .context("failed to find a compatible adapter")?;
+
+        // Connect to the GPU. "device" represents the connection to the GPU and allows us to
+        // create resources like buffers, textures, and pipelines. "queue" represents the command
+        // queue that we use to submit commands to the GPU.
+        let (device, queue, status) = request_device(&adapter).await?;
+
+        // Configure the texture memory backing the surface. Our renderer will draw to a surface
+        // texture every frame.
+        let caps = surface.get_capabilities(&adapter);
+        let format = surface_format(&caps.formats, hdr).context("the surface supports no formats")?;
+        let size = window.inner_size();
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::AutoVsync,
+            alpha_mode: caps.alpha_modes[0],
+            view_formats: vec![],
+            desired_maximum_frame_latency: 3,
+        };
+        surface.configure(&device, &config);
+
+        Ok(Gpu {
+            adapter,
+            device,
+            queue,
+            status,
+            surface,
+            config,
+        })
+    }
+
+    fn format(&self) -> wgpu::TextureFormat {
+        self.config.format
+    }
+
+    /// Sets the surface up again after it was lost or went out of date, e.g. because the window
+    /// was resized.
+    fn reconfigure(&mut self, size: winit::dpi::PhysicalSize<u32>) {
+        if size.width > 0 && size.height > 0 {
+            self.config.width = size.width;
+            self.config.height = size.height;
+        }
+        self.surface.configure(&self.device, &self.config);
+    }
+
+    /// Replaces a lost device with a new one on the same adapter.
+    async fn reconnect(&mut self) -> Result<()> {
+        let (device, queue, status) = request_device(&self.adapter).await?;
+        self.device = device;
+        self.queue = queue;
+        self.status = status;
+        self.surface.configure(&self.device, &self.config);
+        Ok(())
+    }
+
+    /// The next surface texture to draw into, or `None` to skip this frame. Recoverable surface
+    /// errors are handled here.
+    fn next_frame(&mut self, size: winit::dpi::PhysicalSize<u32>) -> Result<Option<wgpu::SurfaceTexture>> {
+        match self.surface.get_current_texture() {
+            Ok(frame) => Ok(Some(frame)),
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                self.reconfigure(size);
+                Ok(None)
+            }
+            Err(wgpu::SurfaceError::Timeout) => Ok(None),
+            Err(wgpu::SurfaceError::Other) => {
+                eprintln!("failed to get the next frame");
+                Ok(None)
+            }
+            Err(err @ wgpu::SurfaceError::OutOfMemory) => Err(err.into()),
+        }
+    }
+}
+
+/// Picks the swapchain format: Rgba16Float when HDR is asked for and available, otherwise an sRGB
+/// format so that the hardware does the encoding, otherwise whatever the surface prefers. The
+/// tracer adapts its output transform to the format.
+fn surface_format(formats: &[wgpu::TextureFormat], hdr: bool) -> Option<wgpu::TextureFormat> {
+    if hdr {
+        if formats.contains(&wgpu::TextureFormat::Rgba16Float) {
+            return Some(wgpu::TextureFormat::Rgba16Float);
+        }
+        eprintln!("the display does not support HDR, falling back to SDR");
+    }
+    formats
+        .iter()
+        .find(|format| format.is_srgb())
+        .or_else(|| formats.first())
+        .copied()
+}
+
+/// What a device reported through its callbacks.
+#[derive(Default)]
+struct DeviceStatus {
+    errors: AtomicBool,
+    lost: AtomicBool,
+}
+
+/// Connects to the adapter's device and logs its uncaptured errors instead of panicking.
+async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue, Arc<DeviceStatus>)> {
     let (device, queue) = adapter
-        .request_device(&wgpu::DeviceDescriptor::default())
+        .request_device(&wgpu::DeviceDescriptor {
+            // Pass timings are shown when the adapter can measure them.
+            required_features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+            ..Default::default()
+        })
         .await
         .context("failed to connect to the GPU")?;
+    let status = Arc::new(DeviceStatus::default());
+    let errors = status.clone();
+    device.on_uncaptured_error(Box::new(move |error| {
+        eprintln!("GPU error: {error}");
+        errors.errors.store(true, Ordering::Relaxed);
+    }));
+    let lost = status.clone();
+    device.set_device_lost_callback(move |reason, message| {
+        // Dropping a device after reconnecting reports it as destroyed.
+        if reason != wgpu::DeviceLostReason::Destroyed {
+            eprintln!("lost the GPU device: {message}");
+            lost.lost.store(true, Ordering::Relaxed);
+        }
+    });
+    Ok((device, queue, status))
+}
 
-    // Configure the texture memory backing the surface. Our renderer will draw to a surface
-    // texture every frame.
-    let caps = surface.get_capabilities(&adapter);
-    let format = caps
-        .formats
-        .into_iter()
-        .find(|it| matches!(it, Rgba8Unorm | Bgra8Unorm))
-        .context("could not find preferred texture format (Rgba8Unorm or Bgra8Unorm)")?;
-    let size = window.inner_size();
-    let config = wgpu::SurfaceConfiguration {
-        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-        format,
-        width: size.width,
-        height: size.height,
-        present_mode: wgpu::PresentMode::AutoVsync,
-        alpha_mode: caps.alpha_modes[0],
-        view_formats: vec![],
-        desired_maximum_frame_latency: 3,
-    };
-    surface.configure(&device, &config);
-
-    Ok((device, queue, surface))
+/// A new tracer on `gpu` that carries over the scene, camera and options of `renderer`.
+fn recreate_renderer(renderer: &PathTracer, gpu: &Gpu) -> Result<PathTracer> {
+    let mut new = PathTracer::new(
+        &gpu.device,
+        &gpu.queue,
+        renderer.scene().clone(),
+        renderer.width(),
+        renderer.height(),
+        gpu.format(),
+    )?;
+    new.camera = renderer.camera;
+    new.fov = renderer.fov;
+    new.denoise = renderer.denoise;
+    new.debug_mode = renderer.debug_mode;
+    new.settings = renderer.settings;
+    new.set_aovs_enabled(renderer.aovs_enabled());
+    new.set_cryptomatte_enabled(renderer.cryptomatte_enabled())?;
+    new.set_path_overlay_enabled(renderer.path_overlay_enabled());
+    Ok(new)
 }
@@ -1,44 +1,123 @@
-use wgpu::wgc::device;
-
-use crate::renderer::math::{Camera, CameraUniforms};
-
 use {
-    bytemuck::{Pod, Zeroable}, std::panic::set_hook, wgpu::{self, PipelineCompilationOptions, PipelineLayout, wgc::binding_model::CreatePipelineLayoutError}
+    anyhow::{anyhow, bail, Context, Result},
+    bytemuck::{Pod, Zeroable},
+    wgpu::{self, PipelineCompilationOptions},
 };
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
-mod math;
+mod aov;
+mod builtin;
+mod bvh;
+mod compare;
+mod cryptomatte;
+mod debug;
+mod denoise;
+mod furnace;
+mod hot_reload;
+pub mod math;
+mod path_debug;
+mod pick;
+mod profiler;
+mod reference;
+pub mod scene;
+mod settings;
+
+use aov::AovTargets;
+use cryptomatte::Cryptomatte;
+pub use aov::{Aov, RenderOutput};
+pub use builtin::BuiltinScene;
+pub use compare::ImageComparison;
+pub use cryptomatte::CryptomatteLayer;
+pub use debug::DebugMode;
+pub use furnace::{furnace_scene, FurnaceReading, FURNACE_SIZE};
+pub use path_debug::{DebugPath, PathEvent, PathVertex};
+pub use pick::Pick;
+pub use profiler::FrameStats;
+pub use reference::render_reference;
+pub use settings::RenderSettings;
 
 
+/// Progressive path tracer rendering a fixed-size image with an existing wgpu device.
 pub struct PathTracer {
     device: wgpu::Device,
     queue: wgpu::Queue,
-    pipeline: wgpu::RenderPipeline,
+    width: u32,
+    height: u32,
+    shader_module: wgpu::ShaderModule,
+    /// Set in debug builds to reload the shader when its source changes.
+    shader_watcher: Option<hot_reload::ShaderWatcher>,
+    target_format: wgpu::TextureFormat,
+    uniform_layout: wgpu::BindGroupLayout,
+    trace_pipeline: wgpu::ComputePipeline,
+    display_pipeline: wgpu::RenderPipeline,
     uniforms: Uniforms,
     uniform_buffer: wgpu::Buffer,
-    bind_group1: wgpu::BindGroup,
-    bind_group2: wgpu::BindGroup,
+    settings_buffer: wgpu::Buffer,
+    environment_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    /// Primitives, BVH nodes and instances of the flattened scene.
+    scene_buffers: [wgpu::Buffer; 3],
+    trace_bind_groups: [wgpu::BindGroup; 2],
+    trace_layout: wgpu::BindGroupLayout,
+    display_layout: wgpu::BindGroupLayout,
+    radiance_view: wgpu::TextureView,
+    gbuffer_views: [wgpu::TextureView; 2],
+    aov_targets: AovTargets,
+    aov_uniform_buffer: wgpu::Buffer,
+    aov_samples: u32,
+    aovs_enabled: bool,
+    denoiser: denoise::Denoiser,
+    cryptomatte: Option<Cryptomatte>,
+    path_debugger: path_debug::PathDebugger,
+    profiler: profiler::Profiler,
+    scene: scene::Scene,
+    prev_camera: math::CameraUniforms,
+    prev_fov: f32,
+    prev_debug_mode: DebugMode,
+    prev_settings: RenderSettings,
+    /// Which of the ping-ponged textures hold the most recently rendered frame.
+    current: usize,
     frame_count: u32,
-    last_fps_instant: Instant,
     pub camera: math::Camera,
     pub fov: f32,
+    pub denoise: bool,
+    pub debug_mode: DebugMode,
+    pub settings: RenderSettings,
+    /// Index of the object outlined in the displayed image.
+    pub selection: Option<usize>,
 }
 
 
+/// Mirrors `AovUniforms` in shaders.wgsl.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct AovUniforms {
+    enabled: u32,
+    samples: u32,
+    _pad: [u32; 2],
+}
+
+/// Mirrors `Uniforms` in shaders.wgsl, which defines the layout. The tests below fail if the
+/// field offsets drift apart.
 #[derive(Copy, Clone, Pod, Zeroable)]
 #[repr(C)]
 struct Uniforms {
     width: u32,
     height: u32,
-    time:f32,
-    _pad: f32,
-    camera: math::Camera,
-    _pad2:f32
+    /// Zoom factor of the camera; larger values narrow the view.
+    fov: f32,
+    /// Frame index offset by the seed, hashed into the random numbers.
+    frame: u32,
+    camera: math::CameraUniforms,
+    debug_mode: u32,
+    /// Object to outline, or -1.
+    selected: i32,
+    /// Pixel whose first sample is logged by the path debugger, or -1.
+    debug_pixel: [i32; 2],
 }
 
 impl PathTracer {
-    fn create_sample_texture(device: &wgpu::Device, width: u32, height: u32) -> (wgpu::TextureDescriptor, wgpu::Texture, wgpu::Texture) {
+    fn create_sample_texture(device: &wgpu::Device, label: &'static str, width: u32, height: u32) -> (wgpu::TextureDescriptor<'static>, wgpu::Texture, wgpu::Texture) {
     let desc = wgpu::TextureDescriptor {
-        label: Some("radiance samples"),
+        label: Some(label),
         size: wgpu::Extent3d {
             width,
             height,
@@ -48,7 +127,7 @@ impl PathTracer {
         sample_count: 1,
         dimension: wgpu::TextureDimension::D2,
         format: wgpu::TextureFormat::Rgba32Float,
-        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
         view_formats: &[],
     };
     let a1 = device.create_texture(&desc);
@@ -57,27 +136,49 @@ impl PathTracer {
     }
 
 
-    pub fn new(device: wgpu::Device, queue: wgpu::Queue) -> PathTracer {
-        device.on_uncaptured_error(Box::new(|error| {
-            panic!("Aborting due to an error: {}", error);
-        }));
-
-        let shader_module = compile_shader_module((&device));
-        let pe = create_pipeline_layout(&device, &shader_module);
-        let layout = pe.1;
-        let pipeline = pe.0;
-         let now = SystemTime::now();
-        let duration = now.duration_since(UNIX_EPOCH)
-            .expect("Time went backwards");
-        let mils = duration.as_secs_f32();
-        let mut cams = math::Camera::new(math::Vec4::new(4.0,0.0,-1.0, 0.0));
+    /// Creates a tracer for `scene` rendering `width` x `height` images, displayed into textures
+    /// of `target_format`. The device and queue are shared with the caller.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: scene::Scene,
+        width: u32,
+        height: u32,
+        target_format: wgpu::TextureFormat,
+    ) -> Result<PathTracer> {
+        let (device, queue) = (device.clone(), queue.clone());
+        // Nothing may return early between pushing the error scope and popping it, or the scope
+        // would stay open and swallow the caller's errors, so the shader is validated first.
+        validate_shader(SHADER_SOURCE)?;
+        // Collect validation errors here rather than in the device's uncaptured error handler, so
+        // that they come back as an `Err`.
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader_module = create_shader_module(&device);
+        let layouts = create_bind_group_layouts(&device);
+        let trace_pipeline =
+            create_trace_pipeline(&device, &shader_module, &layouts.uniforms, &layouts.trace);
+        let display_pipeline = create_display_pipeline(
+            &device,
+            &shader_module,
+            &layouts.uniforms,
+            &layouts.display,
+            target_format,
+        );
+        let camera = math::Camera::look_at(
+            math::Vec4::new(0., -0.0, 0.0,0.0),
+            math::Vec4::new(0., -0.0, -3.0,0.0),
+            math::Vec4::new(0., 1., 0.0,0.0),
+        );
+        let fov = 10.0;
         let uniforms = Uniforms {
-            width: 1920,
-            height: 1200,
-            time: 0.0,
-            _pad: 1.0,
-            camera:cams,
-            _pad2: 10.0
+            width,
+            height,
+            fov,
+            frame: 0,
+            camera: *camera.uniforms(),
+            debug_mode: DebugMode::Off as u32,
+            selected: -1,
+            debug_pixel: [-1, -1],
         };
         let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("uniforms"),
@@ -85,136 +186,514 @@ impl PathTracer {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
-        let storage_texture1 = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Storage Texture"),
-            size: wgpu::Extent3d {
-                width: 1920,
-                height: 1200,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
-            view_formats: &[],
+        let settings_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("render settings"),
+            size: std::mem::size_of::<settings::GpuSettings>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
-        let storage_texture_view1 = storage_texture1.create_view(&wgpu::TextureViewDescriptor::default());
-
-        let storage_texture2 = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Storage Texture"),
-            size: wgpu::Extent3d {
-                width: 1920,
-                height: 1200,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
-            view_formats: &[],
+        let environment_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("environment"),
+            size: std::mem::size_of::<scene::GpuEnvironment>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
-        let storage_texture_view2 = storage_texture2.create_view(&wgpu::TextureViewDescriptor::default());
-
-
+        queue.write_buffer(&environment_buffer, 0, bytemuck::bytes_of(&scene.environment.to_gpu()));
+        let scene_buffers = create_scene_buffers(&device, &queue, &bvh::FlatScene::new(&scene));
+        let uniform_bind_group = create_uniform_bind_group(
+            &device,
+            &layouts.uniforms,
+            &uniform_buffer,
+            &scene_buffers,
+            &settings_buffer,
+            &environment_buffer,
+        );
 
-        let bind_group1 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: &uniform_buffer,
-                    offset: 0,
-                    size: None,
-                }),
-                
-            }, 
-            wgpu::BindGroupEntry{
-                binding: 1, 
-                resource:wgpu::BindingResource::TextureView(&storage_texture_view1)
-            },
-            wgpu::BindGroupEntry{
-                binding: 2, 
-                resource:wgpu::BindingResource::TextureView(&storage_texture_view2)
-            }],
+        // The tracer writes one noisy estimate per frame along with a normal/depth G-buffer. The
+        // G-buffer alternates between two textures so the denoiser can compare against the
+        // previous frame.
+        let (radiance_desc, gbuffer1, gbuffer2) =
+            Self::create_sample_texture(&device, "G-buffer", width, height);
+        let radiance = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("radiance samples"),
+            ..radiance_desc
         });
+        let radiance_view = radiance.create_view(&wgpu::TextureViewDescriptor::default());
+        let gbuffer_views = [gbuffer1, gbuffer2].map(|t| t.create_view(&wgpu::TextureViewDescriptor::default()));
 
-        let bind_group2 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: &uniform_buffer,
-                    offset: 0,
-                    size: None,
-                }),
-                
-            }, 
-            wgpu::BindGroupEntry{
-                binding: 1, 
-                resource:wgpu::BindingResource::TextureView(&storage_texture_view2)
-            },
-            wgpu::BindGroupEntry{
-                binding: 2, 
-                resource:wgpu::BindingResource::TextureView(&storage_texture_view1)
-            }],
+        let aov_targets = AovTargets::new(&device, 1, 1);
+        let aov_uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("AOV uniforms"),
+            size: std::mem::size_of::<AovUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
+        let path_debugger = path_debug::PathDebugger::new(
+            &device,
+            &shader_module,
+            &layouts.uniforms,
+            target_format,
+        );
+        let profiler = profiler::Profiler::new(
+            &device,
+            &queue,
+            width.div_ceil(8) * height.div_ceil(8),
+        );
+        let trace_bind_groups = create_trace_bind_groups(
+            &device,
+            &layouts.trace,
+            &radiance_view,
+            &gbuffer_views,
+            &aov_targets,
+            &aov_uniform_buffer,
+            path_debugger.buffer(),
+            profiler.ray_counts(),
+        );
 
-        let camera = math::Camera::look_at(
-            math::Vec4::new(0., -0.0, 0.0,0.0),
-            math::Vec4::new(0., -0.0, -3.0,0.0),
-            math::Vec4::new(0., 1., 0.0,0.0),
+        let denoiser = denoise::Denoiser::new(
+            &device,
+            &shader_module,
+            &layouts.uniforms,
+            &radiance_view,
+            &gbuffer_views,
+            width,
+            height,
         );
-        let fov = 10.0;
 
-        // TODO: initialize GPU resources
+        if let Some(err) = pollster::block_on(device.pop_error_scope()) {
+            bail!("failed to set up the path tracer: {err}");
+        }
 
-        PathTracer {
+        let settings = scene.settings;
+        let mut tracer = PathTracer {
             device,
             queue,
-            pipeline,
+            width,
+            height,
+            shader_module,
+            shader_watcher: if cfg!(debug_assertions) {
+                hot_reload::ShaderWatcher::new()
+            } else {
+                None
+            },
+            target_format,
+            uniform_layout: layouts.uniforms,
+            trace_pipeline,
+            display_pipeline,
             uniforms,
             uniform_buffer,
-            bind_group1,
-            bind_group2,
+            settings_buffer,
+            environment_buffer,
+            uniform_bind_group,
+            scene_buffers,
+            trace_bind_groups,
+            trace_layout: layouts.trace,
+            display_layout: layouts.display,
+            radiance_view,
+            gbuffer_views,
+            aov_targets,
+            aov_uniform_buffer,
+            aov_samples: 0,
+            aovs_enabled: false,
+            denoiser,
+            cryptomatte: None,
+            path_debugger,
+            profiler,
+            scene,
+            prev_camera: *camera.uniforms(),
+            prev_fov: fov,
+            prev_debug_mode: DebugMode::Off,
+            prev_settings: settings,
+            current: 0,
             frame_count:0,
-            last_fps_instant:Instant::now(),
             camera,
-            fov
+            fov,
+            denoise: true,
+            debug_mode: DebugMode::Off,
+            settings,
+            selection: None,
+        };
+        if let Some(viewpoint) = tracer.scene.camera {
+            tracer.set_viewpoint(viewpoint);
+        }
+        Ok(tracer)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn scene(&self) -> &scene::Scene {
+        &self.scene
+    }
+
+    /// Replaces the scene, switches to its render settings and camera and restarts accumulation.
+    pub fn set_scene(&mut self, scene: scene::Scene) -> Result<()> {
+        self.settings = scene.settings;
+        if let Some(viewpoint) = scene.camera {
+            self.set_viewpoint(viewpoint);
+        }
+        self.selection = None;
+        self.update_scene(scene)
+    }
+
+    /// Replaces the scene like `set_scene` but keeps the current render settings, for editing the
+    /// scene while it renders.
+    pub fn update_scene(&mut self, scene: scene::Scene) -> Result<()> {
+        self.queue.write_buffer(
+            &self.environment_buffer,
+            0,
+            bytemuck::bytes_of(&scene.environment.to_gpu()),
+        );
+        let flat = bvh::FlatScene::new(&scene);
+        let contents = scene_contents(&flat);
+        let same_sizes = self
+            .scene_buffers
+            .iter()
+            .zip(&contents)
+            .all(|(buffer, (_, bytes))| buffer.size() == bytes.len() as u64);
+        if same_sizes {
+            for (buffer, (_, bytes)) in self.scene_buffers.iter().zip(contents) {
+                self.queue.write_buffer(buffer, 0, bytes);
+            }
+        } else {
+            self.scene_buffers = create_scene_buffers(&self.device, &self.queue, &flat);
+            self.uniform_bind_group = create_uniform_bind_group(
+                &self.device,
+                &self.uniform_layout,
+                &self.uniform_buffer,
+                &self.scene_buffers,
+                &self.settings_buffer,
+                &self.environment_buffer,
+            );
+        }
+        let renamed = !scene.same_names(&self.scene);
+        self.scene = scene;
+        if self.cryptomatte.is_some() && renamed {
+            // The Cryptomatte ID table is built from the names in the scene.
+            self.cryptomatte = None;
+            self.set_cryptomatte_enabled(true)?;
+        }
+        self.frame_count = 0;
+        Ok(())
+    }
+
+    /// The scene with the current camera and render settings, as `set_scene` restores them.
+    pub fn current_scene(&self) -> scene::Scene {
+        scene::Scene {
+            settings: self.settings,
+            camera: Some(self.viewpoint()),
+            ..self.scene.clone()
+        }
+    }
+
+    pub fn viewpoint(&self) -> scene::Viewpoint {
+        scene::Viewpoint {
+            position: self.camera.origin(),
+            pitch: self.camera.pitch(),
+            yaw: self.camera.yaw(),
+            zoom: self.fov,
+        }
+    }
+
+    pub fn set_viewpoint(&mut self, viewpoint: scene::Viewpoint) {
+        self.camera.set_origin(viewpoint.position);
+        self.camera
+            .rotate(viewpoint.pitch - self.camera.pitch(), viewpoint.yaw - self.camera.yaw());
+        self.camera.set_w();
+        self.fov = viewpoint.zoom;
+    }
+
+    pub fn aovs_enabled(&self) -> bool {
+        self.aovs_enabled
+    }
+
+    /// Starts or stops writing AOVs. Their textures are only allocated while enabled.
+    pub fn set_aovs_enabled(&mut self, enabled: bool) {
+        if enabled == self.aovs_enabled {
+            return;
         }
+        let (width, height) = if enabled { (self.width, self.height) } else { (1, 1) };
+        self.aov_targets = AovTargets::new(&self.device, width, height);
+        self.trace_bind_groups = create_trace_bind_groups(
+            &self.device,
+            &self.trace_layout,
+            &self.radiance_view,
+            &self.gbuffer_views,
+            &self.aov_targets,
+            &self.aov_uniform_buffer,
+            self.path_debugger.buffer(),
+            self.profiler.ray_counts(),
+        );
+        self.aov_samples = 0;
+        self.aovs_enabled = enabled;
+    }
+
+    pub fn cryptomatte_enabled(&self) -> bool {
+        self.cryptomatte.is_some()
+    }
+
+    /// Starts or stops accumulating Cryptomatte coverage. Its buffers are only allocated while
+    /// enabled, and enabling fails if the device cannot hold them.
+    pub fn set_cryptomatte_enabled(&mut self, enabled: bool) -> Result<()> {
+        if enabled == self.cryptomatte_enabled() {
+            return Ok(());
+        }
+        self.cryptomatte = if enabled {
+            Some(Cryptomatte::new(
+                &self.device,
+                &self.shader_module,
+                &self.uniform_layout,
+                &self.scene,
+                self.width,
+                self.height,
+            )?)
+        } else {
+            None
+        };
+        Ok(())
     }
+
+    /// Logs every bounce of the first sample of pixel (`x`, `y`) during the next frame. Fetch the
+    /// result with `take_debug_path` after rendering it.
+    pub fn debug_pixel(&mut self, x: u32, y: u32) {
+        self.path_debugger.request((x, y));
+    }
+
+    /// The path logged by the last frame, if a pixel was requested for it. Waits for the GPU.
+    pub fn take_debug_path(&mut self) -> Result<Option<DebugPath>> {
+        let device = &self.device;
+        let queue = &self.queue;
+        self.path_debugger
+            .take_path(&self.scene, |buffer| read_buffer(device, queue, buffer))
+    }
+
+    /// The object under pixel position (`x`, `y`) of the image, found by tracing the same
+    /// primary ray as the shader on the CPU.
+    pub fn pick(&self, x: f32, y: f32) -> Option<Pick> {
+        pick::pick(&self.scene, &self.camera, self.fov, [self.width, self.height], [x, y])
+    }
+
+    /// Where `point` appears in the image, in pixels, or `None` if it is behind the camera.
+    pub fn project(&self, point: math::Vec4) -> Option<[f32; 2]> {
+        pick::project(&self.camera, self.fov, [self.width, self.height], point)
+    }
+
+    /// Timings and throughput of recent frames. They lag a frame or two behind, since they are
+    /// read back from the GPU without waiting for it.
+    pub fn stats(&self) -> FrameStats {
+        self.profiler.stats()
+    }
+
+    pub fn path_overlay_enabled(&self) -> bool {
+        self.path_debugger.show_overlay
+    }
+
+    /// Draws the last logged path as a line over the image.
+    pub fn set_path_overlay_enabled(&mut self, enabled: bool) {
+        self.path_debugger.show_overlay = enabled;
+    }
+
+    /// Whether the denoiser's a-trous filter runs. Debug visualizations are only accumulated, as
+    /// blurring them would hide what they show.
+    fn spatial_filter(&self) -> bool {
+        self.denoise && self.debug_mode == DebugMode::Off
+    }
+
+    /// Traces one more frame and draws the accumulated image into `target`, which must have the
+    /// format the tracer was created with.
     pub fn render_frame(&mut self, target: &wgpu::TextureView) {
-        
+        self.reload_shader();
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("render frame"),
             });
-        let now = SystemTime::now();
-        let duration = now.duration_since(UNIX_EPOCH).unwrap();
-        let uniforms = Uniforms {
-            width: 1920,
-            height: 1200,
-            time: self.fov,
-            _pad: self.frame_count as f32,
-            camera: self.camera,
-            _pad2: self.fov,
+        self.encode_trace(&mut encoder);
+        self.encode_display(
+            &mut encoder,
+            target,
+            self.profiler.render_pass(profiler::Pass::Display),
+        );
+        self.profiler.end_frame(&mut encoder, true);
+        self.queue.submit(Some(encoder.finish()));
+        self.profiler.frame_submitted();
+    }
+
+    /// Traces one more frame without drawing it anywhere, for rendering offline with
+    /// `read_output`.
+    pub fn render(&mut self) {
+        self.reload_shader();
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("render frame"),
+            });
+        self.encode_trace(&mut encoder);
+        self.profiler.end_frame(&mut encoder, false);
+        self.queue.submit(Some(encoder.finish()));
+        self.profiler.frame_submitted();
+    }
+
+    /// Draws the most recent frame into `target` again without tracing.
+    pub fn display(&self, target: &wgpu::TextureView) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("display frame"),
+            });
+        self.encode_display(&mut encoder, target, None);
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    /// Swaps in the shader from disk if the watcher saw a valid edit. Should wgpu still reject
+    /// it, for example because the bindings changed, the error is logged and the current shader
+    /// stays.
+    fn reload_shader(&mut self) {
+        let Some(source) = self.shader_watcher.as_mut().and_then(|watcher| watcher.poll()) else {
+            return;
         };
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader_module = self.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("reloaded shaders"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        self.set_shader(&shader_module);
+        match pollster::block_on(self.device.pop_error_scope()) {
+            None => {
+                println!("reloaded shaders");
+                self.shader_module = shader_module;
+                self.frame_count = 0;
+            }
+            Some(err) => {
+                eprintln!("failed to reload shaders: {err}");
+                let current = self.shader_module.clone();
+                self.set_shader(&current);
+            }
+        }
+    }
+
+    /// Rebuilds every pipeline from `shader_module`.
+    fn set_shader(&mut self, shader_module: &wgpu::ShaderModule) {
+        self.trace_pipeline =
+            create_trace_pipeline(&self.device, shader_module, &self.uniform_layout, &self.trace_layout);
+        self.display_pipeline = create_display_pipeline(
+            &self.device,
+            shader_module,
+            &self.uniform_layout,
+            &self.display_layout,
+            self.target_format,
+        );
+        self.denoiser.set_shader(&self.device, shader_module);
+        if let Some(cryptomatte) = &mut self.cryptomatte {
+            cryptomatte.set_shader(&self.device, shader_module);
+        }
+        self.path_debugger.set_shader(&self.device, shader_module);
+    }
+
+    fn encode_trace(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        self.uniforms.fov = self.fov;
+        // The seed shifts the frame index that the shader hashes into its random numbers.
+        self.uniforms.frame = self.frame_count.wrapping_add(self.settings.seed) % 1000000;
+        self.uniforms.camera = *self.camera.uniforms();
+        self.uniforms.debug_mode = self.debug_mode as u32;
+        self.uniforms.debug_pixel = self.path_debugger.begin_frame();
+        self.uniforms.selected = self.selection.map_or(-1, |index| index as i32);
         self.queue.write_buffer(
         &self.uniform_buffer,
         0,
-        bytemuck::bytes_of(&uniforms),
+        bytemuck::bytes_of(&self.uniforms),
     );
+        self.queue.write_buffer(
+            &self.settings_buffer,
+            0,
+            bytemuck::bytes_of(&self.settings.to_gpu()),
+        );
+        // Even and odd frames swap which G-buffer and history textures are current.
+        let cur = (self.frame_count % 2) as usize;
+        let history = denoise::History {
+            camera: self.prev_camera,
+            fov: self.prev_fov,
+            moved: self.prev_camera != *self.camera.uniforms() || self.prev_fov != self.fov,
+            // Debug visualizations replace the radiance samples, so switching modes invalidates
+            // the history.
+            reset: self.frame_count == 0
+                || self.prev_debug_mode != self.debug_mode
+                || self.prev_settings != self.settings,
+        };
+        self.prev_camera = *self.camera.uniforms();
+        self.prev_fov = self.fov;
+        self.prev_debug_mode = self.debug_mode;
+        self.prev_settings = self.settings;
+        self.current = cur;
+        if history.moved || history.reset {
+            self.aov_samples = 0;
+        }
+        self.profiler.begin_frame(
+            &self.device,
+            encoder,
+            self.width as u64 * self.height as u64 * self.settings.samples as u64,
+            self.settings.samples,
+            history.moved || history.reset,
+        );
+        let aov_uniforms = AovUniforms {
+            enabled: self.aovs_enabled as u32,
+            samples: self.aov_samples,
+            _pad: [0; 2],
+        };
+        self.queue.write_buffer(&self.aov_uniform_buffer, 0, bytemuck::bytes_of(&aov_uniforms));
+        self.aov_samples = self.aov_samples.saturating_add(1);
         self.frame_count += 1;
         self.frame_count %= 1000000;
 
+        let mut trace_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("path tracer"),
+            timestamp_writes: self.profiler.compute_pass(profiler::Pass::Trace),
+        });
+        trace_pass.set_pipeline(&self.trace_pipeline);
+        trace_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+        trace_pass.set_bind_group(1, &self.trace_bind_groups[cur], &[]);
+        trace_pass.dispatch_workgroups(self.width.div_ceil(8), self.height.div_ceil(8), 1);
+        drop(trace_pass);
+
+        if let Some(cryptomatte) = &mut self.cryptomatte {
+            cryptomatte.encode(
+                &self.queue,
+                encoder,
+                &self.uniform_bind_group,
+                history.moved || history.reset,
+            );
+        }
+
+        self.denoiser.encode(
+            &self.queue,
+            encoder,
+            &self.uniform_bind_group,
+            cur,
+            &history,
+            self.spatial_filter(),
+            self.profiler.compute_pass(profiler::Pass::Denoise),
+        );
+    }
+
+    fn encode_display(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites>,
+    ) {
+        let display_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("display"),
+            layout: &self.display_layout,
+            entries: &[view_entry(0, self.denoiser.output(self.current, self.spatial_filter()))],
+        });
 
-        
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("path tracer render pass"),
+            label: Some("display render pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                 view: target,
                 depth_slice: None,
@@ -224,51 +703,269 @@ impl PathTracer {
                     store: wgpu::StoreOp::Store,
                 },
             })],
+            timestamp_writes,
             ..Default::default()
         });
-        
-        render_pass.set_pipeline(&self.pipeline);
-        if(self.frame_count%2 == 1){
-            render_pass.set_bind_group(0, &self.bind_group1, &[]);
-        }
-        else{
-            render_pass.set_bind_group(0, &self.bind_group2, &[]);
-        }
-        
-        
+
+        render_pass.set_pipeline(&self.display_pipeline);
+        render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+        render_pass.set_bind_group(1, &display_bind_group, &[]);
+
         // Draw 1 instance of a polygon with 3 vertices.
         render_pass.draw(0..6, 0..1);
+        self.path_debugger.draw_overlay(&mut render_pass, &self.uniform_bind_group);
 
         // End the render pass by consuming the object.
         drop(render_pass);
+    }
+}
+
+impl PathTracer {
+    /// Reads the most recently rendered frame back from the GPU, including the AOVs if they are
+    /// enabled.
+    pub fn read_output(&self) -> Result<RenderOutput> {
+        let beauty = self.read_texture(self.denoiser.output_texture(self.current, self.spatial_filter()), 0, 0)?;
+        let mut aovs = Vec::new();
+        if self.aovs_enabled {
+            for aov in Aov::ALL {
+                let (texture, layer, component) = self.aov_targets.source(aov, self.current);
+                aovs.push((aov, self.read_texture(texture, layer, component)?));
+            }
+        }
+        let cryptomattes = match &self.cryptomatte {
+            Some(cryptomatte) => cryptomatte.read(|buffer| read_buffer(&self.device, &self.queue, buffer))?,
+            None => Vec::new(),
+        };
+        Ok(RenderOutput {
+            width: self.width,
+            height: self.height,
+            beauty,
+            aovs,
+            cryptomattes,
+        })
+    }
+
+    /// Copies one layer of an RGBA32F texture to the CPU, shifting the texel components left so
+    /// that `first_component` comes first.
+    fn read_texture(&self, texture: &wgpu::Texture, layer: u32, first_component: usize) -> Result<Vec<[f32; 4]>> {
+        let (width, height) = (texture.width(), texture.height());
+        let unpadded_bytes_per_row = width * 16;
+        let bytes_per_row = unpadded_bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback"),
+            size: (bytes_per_row * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("readback"),
+            });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: layer },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(Some(encoder.finish()));
 
-        let command_buffer = encoder.finish();
-        self.queue.submit(Some(command_buffer));
+        let data = map_readback(&self.device, &buffer)?;
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for row in data.chunks(bytes_per_row as usize) {
+            let texels: &[[f32; 4]] = bytemuck::cast_slice(&row[..unpadded_bytes_per_row as usize]);
+            pixels.extend(texels.iter().map(|texel| {
+                let mut pixel = [0.0; 4];
+                pixel[..4 - first_component].copy_from_slice(&texel[first_component..]);
+                pixel
+            }));
+        }
+        Ok(pixels)
     }
 }
 
-fn compile_shader_module(device: &wgpu::Device) -> wgpu::ShaderModule {
-    use std::borrow::Cow;
+/// Copies a whole storage buffer to the CPU.
+fn read_buffer(device: &wgpu::Device, queue: &wgpu::Queue, source: &wgpu::Buffer) -> Result<Vec<u8>> {
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback"),
+        size: source.size(),
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("readback"),
+    });
+    encoder.copy_buffer_to_buffer(source, 0, &buffer, 0, source.size());
+    queue.submit(Some(encoder.finish()));
+    Ok(map_readback(device, &buffer)?.to_vec())
+}
+
+/// Waits for the GPU to finish writing `buffer` and maps it for reading.
+fn map_readback<'a>(device: &wgpu::Device, buffer: &'a wgpu::Buffer) -> Result<wgpu::BufferView<'a>> {
+    let slice = buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| {});
+    device
+        .poll(wgpu::PollType::Wait)
+        .context("failed to read back from the GPU")?;
+    Ok(slice.get_mapped_range())
+}
+
+/// Labels and contents of the scene buffers, in the order of `PathTracer::scene_buffers`.
+fn scene_contents(flat: &bvh::FlatScene) -> [(&'static str, &[u8]); 3] {
+    [
+        ("primitives", bytemuck::cast_slice(&flat.primitives)),
+        ("BVH nodes", bytemuck::cast_slice(&flat.nodes)),
+        ("instances", bytemuck::cast_slice(&flat.instances)),
+    ]
+}
 
-    let code = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders.wgsl"));
+fn create_scene_buffers(device: &wgpu::Device, queue: &wgpu::Queue, flat: &bvh::FlatScene) -> [wgpu::Buffer; 3] {
+    scene_contents(flat).map(|(label, bytes)| {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: bytes.len() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&buffer, 0, bytes);
+        buffer
+    })
+}
+
+fn create_uniform_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    [primitives, bvh_nodes, instances]: &[wgpu::Buffer; 3],
+    settings_buffer: &wgpu::Buffer,
+    environment_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("uniforms"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: primitives.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: settings_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: environment_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: bvh_nodes.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: instances.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_trace_bind_groups(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    radiance_view: &wgpu::TextureView,
+    gbuffer_views: &[wgpu::TextureView; 2],
+    aov_targets: &AovTargets,
+    aov_uniform_buffer: &wgpu::Buffer,
+    path_log: &wgpu::Buffer,
+    ray_counts: &wgpu::Buffer,
+) -> [wgpu::BindGroup; 2] {
+    [0, 1].map(|cur| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("path tracer outputs"),
+            layout,
+            entries: &[
+                view_entry(0, radiance_view),
+                view_entry(1, &gbuffer_views[cur]),
+                view_entry(2, &aov_targets.geometry_view),
+                view_entry(3, &aov_targets.lighting_views[cur]),
+                view_entry(4, &aov_targets.lighting_views[1 - cur]),
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: aov_uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: path_log.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: ray_counts.as_entire_binding(),
+                },
+            ],
+        })
+    })
+}
+
+/// Path of the shader source, for error messages and hot reloading.
+const SHADER_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders.wgsl");
+
+/// The shader source built into the tracer, which `validate_shader` should accept before it is
+/// compiled.
+const SHADER_SOURCE: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders.wgsl"));
+
+fn create_shader_module(device: &wgpu::Device) -> wgpu::ShaderModule {
     device.create_shader_module(wgpu::ShaderModuleDescriptor {
         label: None,
-        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(code)),
+        source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(SHADER_SOURCE)),
     })
 }
 
+/// Parses and validates WGSL with naga. Errors point at the offending file and line.
+fn validate_shader(source: &str) -> Result<()> {
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|err| anyhow!(err.emit_to_string_with_path(source, SHADER_PATH)))?;
+    naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::default())
+        .validate(&module)
+        .map_err(|err| anyhow!(err.emit_to_string_with_path(source, SHADER_PATH)))?;
+    Ok(())
+}
 
-fn create_pipeline_layout(
-    device: &wgpu::Device,
-    shader_module: &wgpu::ShaderModule,
-) -> (wgpu::RenderPipeline, wgpu::BindGroupLayout) {
-    let bind_group_layout =
+
+struct BindGroupLayouts {
+    uniforms: wgpu::BindGroupLayout,
+    trace: wgpu::BindGroupLayout,
+    display: wgpu::BindGroupLayout,
+}
+
+fn create_bind_group_layouts(device: &wgpu::Device) -> BindGroupLayouts {
+    let uniforms =
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: None,
+            label: Some("uniforms"),
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    visibility: wgpu::ShaderStages::COMPUTE
+                        | wgpu::ShaderStages::VERTEX
+                        | wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -276,40 +973,84 @@ fn create_pipeline_layout(
                     },
                     count: None,
                 },
+                // The display pass traces primary rays to outline the selected object.
                 wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::StorageTexture {
-                        access: wgpu::StorageTextureAccess::ReadOnly,
-                        format: wgpu::TextureFormat::Rgba8Unorm,
-                        view_dimension: wgpu::TextureViewDimension::D2,
+                    visibility: wgpu::ShaderStages::COMPUTE | wgpu::ShaderStages::FRAGMENT,
+                    ..storage_buffer_entry(1, true)
                 },
-                count: None,
+                wgpu::BindGroupLayoutEntry {
+                    visibility: wgpu::ShaderStages::COMPUTE | wgpu::ShaderStages::FRAGMENT,
+                    ..uniform_entry(2)
                 },
+                uniform_entry(3),
                 wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::StorageTexture {
-                        access: wgpu::StorageTextureAccess::WriteOnly,
-                        format: wgpu::TextureFormat::Rgba8Unorm, 
-                        view_dimension: wgpu::TextureViewDimension::D2,
+                    visibility: wgpu::ShaderStages::COMPUTE | wgpu::ShaderStages::FRAGMENT,
+                    ..storage_buffer_entry(4, true)
+                },
+                wgpu::BindGroupLayoutEntry {
+                    visibility: wgpu::ShaderStages::COMPUTE | wgpu::ShaderStages::FRAGMENT,
+                    ..storage_buffer_entry(5, true)
                 },
-                count: None,
-                }
             ],
         });
-    
-
+    let trace =
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("path tracer outputs"),
+            entries: &[
+                storage_texture_entry(0),
+                storage_texture_entry(1),
+                texture_array_entry(storage_texture_entry(2)),
+                texture_array_entry(storage_texture_entry(3)),
+                texture_array_entry(sampled_texture_entry(4)),
+                uniform_entry(5),
+                storage_buffer_entry(6, false),
+                storage_buffer_entry(7, false),
+            ],
+        });
+    let display =
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("display"),
+            entries: &[sampled_texture_entry(0)],
+        });
+    BindGroupLayouts { uniforms, trace, display }
+}
 
-        
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-    label: Some("Pipeline Layout"),
-    bind_group_layouts: &[&bind_group_layout],
-    push_constant_ranges: &[],
+fn create_trace_pipeline(
+    device: &wgpu::Device,
+    shader_module: &wgpu::ShaderModule,
+    uniform_layout: &wgpu::BindGroupLayout,
+    trace_layout: &wgpu::BindGroupLayout,
+) -> wgpu::ComputePipeline {
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("path tracer"),
+        bind_group_layouts: &[uniform_layout, trace_layout],
+        push_constant_ranges: &[],
     });
-    let pipeline: wgpu::RenderPipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
         label: Some("path tracer"),
         layout: Some(&pipeline_layout),
+        module: shader_module,
+        entry_point: Some("path_tracer_cs"),
+        compilation_options: PipelineCompilationOptions::default(),
+        cache: None,
+    })
+}
+
+fn create_display_pipeline(
+    device: &wgpu::Device,
+    shader_module: &wgpu::ShaderModule,
+    uniform_layout: &wgpu::BindGroupLayout,
+    display_layout: &wgpu::BindGroupLayout,
+    target_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("display"),
+        bind_group_layouts: &[uniform_layout, display_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("display"),
+        layout: Some(&pipeline_layout),
         primitive: wgpu::PrimitiveState {
             topology: wgpu::PrimitiveTopology::TriangleList,
             front_face: wgpu::FrontFace::Ccw,
@@ -322,21 +1063,175 @@ fn create_pipeline_layout(
             buffers: &[],
             compilation_options: PipelineCompilationOptions::default(),
         },
-        
+
         fragment: Some(wgpu::FragmentState {
             module: shader_module,
-            entry_point: Some("path_tracer_fs"),
+            entry_point: Some("display_fs"),
             targets: &[Some(wgpu::ColorTargetState {
-                format: wgpu::TextureFormat::Bgra8Unorm,
+                format: target_format,
                 blend: None,
                 write_mask: wgpu::ColorWrites::ALL,
             })],
-            compilation_options: PipelineCompilationOptions::default(),
+            compilation_options: PipelineCompilationOptions {
+                constants: &output_constants(target_format),
+                ..Default::default()
+            },
         }),
         depth_stencil: None,
         multisample: wgpu::MultisampleState::default(),
         multiview: None,
         cache: None,
-    });
-    return (pipeline, bind_group_layout);
+    })
+}
+
+/// Pipeline constants selecting how the display passes encode colors for `target_format`. The
+/// values match the `OUTPUT_*` constants in shaders.wgsl.
+fn output_constants(target_format: wgpu::TextureFormat) -> [(&'static str, f64); 1] {
+    let transform = if target_format == wgpu::TextureFormat::Rgba16Float {
+        2
+    } else if target_format.is_srgb() {
+        1
+    } else {
+        0
+    };
+    [("OUTPUT_TRANSFORM", transform as f64)]
+}
+
+fn sampled_texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE | wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+fn storage_texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format: wgpu::TextureFormat::Rgba32Float,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        },
+        count: None,
+    }
+}
+
+fn uniform_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+fn storage_buffer_entry(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+/// Turns a 2D texture layout entry into the equivalent 2D array entry.
+fn texture_array_entry(mut entry: wgpu::BindGroupLayoutEntry) -> wgpu::BindGroupLayoutEntry {
+    match &mut entry.ty {
+        wgpu::BindingType::Texture { view_dimension, .. }
+        | wgpu::BindingType::StorageTexture { view_dimension, .. } => {
+            *view_dimension = wgpu::TextureViewDimension::D2Array;
+        }
+        _ => {}
+    }
+    entry
+}
+
+fn view_entry(binding: u32, view: &wgpu::TextureView) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding,
+        resource: wgpu::BindingResource::TextureView(view),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::mem::{offset_of, size_of},
+    };
+
+    /// Checks that `Uniforms`-style mirrors of a WGSL struct have the same members at the same
+    /// offsets and the same size as the shader's struct.
+    pub(super) fn assert_matches_shader(name: &str, size: usize, fields: &[(&str, usize)]) {
+        let module = naga::front::wgsl::parse_str(include_str!("shaders.wgsl")).unwrap();
+        let (members, span) = module
+            .types
+            .iter()
+            .find_map(|(_, ty)| match &ty.inner {
+                naga::TypeInner::Struct { members, span } if ty.name.as_deref() == Some(name) => {
+                    Some((members, *span))
+                }
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("no struct {name} in shaders.wgsl"));
+        let shader: Vec<(&str, usize)> = members
+            .iter()
+            .map(|member| (member.name.as_deref().unwrap_or(""), member.offset as usize))
+            .collect();
+        assert_eq!(shader, fields, "members of {name}");
+        assert_eq!(span as usize, size, "size of {name}");
+    }
+
+    #[test]
+    fn shader_is_valid() {
+        if let Err(err) = validate_shader(include_str!("shaders.wgsl")) {
+            panic!("{err}");
+        }
+    }
+
+    #[test]
+    fn uniforms_layout() {
+        assert_matches_shader(
+            "Uniforms",
+            size_of::<Uniforms>(),
+            &[
+                ("width", offset_of!(Uniforms, width)),
+                ("height", offset_of!(Uniforms, height)),
+                ("fov", offset_of!(Uniforms, fov)),
+                ("frame", offset_of!(Uniforms, frame)),
+                ("camera", offset_of!(Uniforms, camera)),
+                ("debug_mode", offset_of!(Uniforms, debug_mode)),
+                ("selected", offset_of!(Uniforms, selected)),
+                ("debug_pixel", offset_of!(Uniforms, debug_pixel)),
+            ],
+        );
+    }
+
+    #[test]
+    fn aov_uniforms_layout() {
+        assert_matches_shader(
+            "AovUniforms",
+            size_of::<AovUniforms>(),
+            &[
+                ("enabled", offset_of!(AovUniforms, enabled)),
+                ("samples", offset_of!(AovUniforms, samples)),
+                ("_pad", offset_of!(AovUniforms, _pad)),
+            ],
+        );
+    }
 }
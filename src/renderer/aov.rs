@@ -0,0 +1,257 @@
+use {
+    anyhow::{Context, Result},
+    exr::prelude::{
+        read_first_rgba_layer_from_file, AnyChannel, AnyChannels, Encoding, FlatSamples, Image,
+        Layer, LayerAttributes, SmallVec, WritableImage,
+    },
+    std::path::{Path, PathBuf},
+};
+
+use super::cryptomatte::CryptomatteLayer;
+
+/// Geometry AOVs come from the first hit of a pixel and are written as is each frame. Lighting
+/// AOVs are noisy and get accumulated over frames like the beauty pass.
+const GEOMETRY_LAYERS: u32 = 4;
+const LIGHTING_LAYERS: u32 = 4;
+
+/// An arbitrary output variable written by the tracer alongside the beauty pass.
+///
+/// The four lighting AOVs partition the noisy beauty pass: rendered with `--no-denoise`,
+/// `direct + indirect + specular + emission` adds up to the beauty color in every pixel. The
+/// denoiser filters only the beauty, so with it on they add up to it only on average. Background
+/// pixels count as emission and have an ID and depth of -1.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Aov {
+    Albedo,
+    Normal,
+    Position,
+    Depth,
+    DirectDiffuse,
+    IndirectDiffuse,
+    Specular,
+    Emission,
+    ObjectId,
+    MaterialId,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 10] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Position,
+        Aov::Depth,
+        Aov::DirectDiffuse,
+        Aov::IndirectDiffuse,
+        Aov::Specular,
+        Aov::Emission,
+        Aov::ObjectId,
+        Aov::MaterialId,
+    ];
+
+    /// Layer name used in EXR channel names and file names.
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Position => "position",
+            Aov::Depth => "depth",
+            Aov::DirectDiffuse => "diffuse_direct",
+            Aov::IndirectDiffuse => "diffuse_indirect",
+            Aov::Specular => "specular",
+            Aov::Emission => "emission",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+        }
+    }
+
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Depth => &["Z"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            _ => &["R", "G", "B"],
+        }
+    }
+
+    /// Where the tracer stores the AOV: whether it is a lighting AOV, the texture array layer,
+    /// and the first texel component.
+    fn location(self) -> (bool, u32, usize) {
+        match self {
+            Aov::Albedo => (false, 0, 0),
+            Aov::Normal => (false, 1, 0),
+            Aov::Position => (false, 2, 0),
+            Aov::Depth => (false, 2, 3),
+            Aov::ObjectId => (false, 3, 0),
+            Aov::MaterialId => (false, 3, 1),
+            Aov::DirectDiffuse => (true, 0, 0),
+            Aov::IndirectDiffuse => (true, 1, 0),
+            Aov::Specular => (true, 2, 0),
+            Aov::Emission => (true, 3, 0),
+        }
+    }
+}
+
+/// Texture arrays the tracer writes AOVs into. The lighting array alternates between two
+/// textures so each frame can blend its samples into the previous frame's accumulation.
+pub(super) struct AovTargets {
+    geometry: wgpu::Texture,
+    lighting: [wgpu::Texture; 2],
+    pub geometry_view: wgpu::TextureView,
+    pub lighting_views: [wgpu::TextureView; 2],
+}
+
+impl AovTargets {
+    /// AOVs take eight full-resolution RGBA32F layers, so the tracer allocates a 1x1 placeholder
+    /// until they are enabled.
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> AovTargets {
+        let create = |label, layers| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: layers,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            })
+        };
+        let array_view = |texture: &wgpu::Texture| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                ..Default::default()
+            })
+        };
+        let geometry = create("geometry AOVs", GEOMETRY_LAYERS);
+        let lighting = [
+            create("lighting AOVs", LIGHTING_LAYERS),
+            create("lighting AOVs", LIGHTING_LAYERS),
+        ];
+        AovTargets {
+            geometry_view: array_view(&geometry),
+            lighting_views: [array_view(&lighting[0]), array_view(&lighting[1])],
+            geometry,
+            lighting,
+        }
+    }
+
+    /// The texture and array layer holding `aov` for the frame `cur`, and the first component.
+    pub fn source(&self, aov: Aov, cur: usize) -> (&wgpu::Texture, u32, usize) {
+        let (lighting, layer, component) = aov.location();
+        let texture = if lighting { &self.lighting[cur] } else { &self.geometry };
+        (texture, layer, component)
+    }
+}
+
+/// A frame read back from the GPU: the linear (not tone mapped) beauty pass plus the AOVs and
+/// Cryptomattes, if they were enabled. AOV pixels hold `aov.channels().len()` meaningful
+/// components.
+pub struct RenderOutput {
+    pub width: u32,
+    pub height: u32,
+    pub beauty: Vec<[f32; 4]>,
+    pub aovs: Vec<(Aov, Vec<[f32; 4]>)>,
+    pub cryptomattes: Vec<CryptomatteLayer>,
+}
+
+impl RenderOutput {
+    /// Reads the beauty pass of an EXR written by `save_exr`, or the RGBA channels of any other
+    /// EXR, with alpha 1 where it has none. AOVs and Cryptomattes are not read back.
+    pub fn load_exr(path: &Path) -> Result<RenderOutput> {
+        let image = read_first_rgba_layer_from_file(
+            path,
+            |resolution, _| (resolution.width(), vec![[0.0; 4]; resolution.area()]),
+            |(width, pixels), position, (r, g, b, a): (f32, f32, f32, f32)| {
+                pixels[position.y() * *width + position.x()] = [r, g, b, a];
+            },
+        )
+        .with_context(|| format!("failed to read {}", path.display()))?;
+        let size = image.layer_data.size;
+        Ok(RenderOutput {
+            width: size.width() as u32,
+            height: size.height() as u32,
+            beauty: image.layer_data.channel_data.pixels.1,
+            aovs: Vec::new(),
+            cryptomattes: Vec::new(),
+        })
+    }
+
+    /// Writes the beauty pass as `R`, `G`, `B`, `A` and every AOV as a `<name>.<channel>` layer
+    /// of a single multi-layer EXR. Cryptomattes follow the Cryptomatte convention, with their
+    /// manifests in the header.
+    pub fn save_exr(&self, path: &Path) -> Result<()> {
+        let mut channels = exr_channels("", &BEAUTY_CHANNELS, &self.beauty);
+        for (aov, pixels) in &self.aovs {
+            channels.extend(exr_channels(&format!("{}.", aov.name()), aov.channels(), pixels));
+        }
+        let mut attributes = LayerAttributes::default();
+        for cryptomatte in &self.cryptomattes {
+            channels.extend(cryptomatte.exr_channels());
+            attributes.other.extend(cryptomatte.exr_attributes());
+        }
+        self.write_exr(path, channels, attributes)
+    }
+
+    /// Writes the beauty pass to `path` and each AOV and Cryptomatte to its own file next to it,
+    /// named `<stem>.<aov name>.exr` or `<stem>.<Cryptomatte name>.exr`.
+    pub fn save_exr_files(&self, path: &Path) -> Result<()> {
+        let beauty = exr_channels("", &BEAUTY_CHANNELS, &self.beauty);
+        self.write_exr(path, beauty, LayerAttributes::default())?;
+        for (aov, pixels) in &self.aovs {
+            let channels = exr_channels("", aov.channels(), pixels);
+            self.write_exr(&sibling_path(path, aov.name()), channels, LayerAttributes::default())?;
+        }
+        for cryptomatte in &self.cryptomattes {
+            let mut attributes = LayerAttributes::default();
+            attributes.other.extend(cryptomatte.exr_attributes());
+            let path = sibling_path(path, &cryptomatte.name);
+            self.write_exr(&path, cryptomatte.exr_channels(), attributes)?;
+        }
+        Ok(())
+    }
+
+    fn write_exr(
+        &self,
+        path: &Path,
+        channels: Vec<AnyChannel<FlatSamples>>,
+        attributes: LayerAttributes,
+    ) -> Result<()> {
+        let layer = Layer::new(
+            (self.width as usize, self.height as usize),
+            attributes,
+            Encoding::FAST_LOSSLESS,
+            AnyChannels::sort(SmallVec::from_vec(channels)),
+        );
+        Image::from_layer(layer)
+            .write()
+            .to_file(path)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+const BEAUTY_CHANNELS: [&str; 4] = ["R", "G", "B", "A"];
+
+/// One EXR channel per name, taking consecutive pixel components.
+fn exr_channels(prefix: &str, names: &[&str], pixels: &[[f32; 4]]) -> Vec<AnyChannel<FlatSamples>> {
+    names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let samples = pixels.iter().map(|p| p[i]).collect();
+            AnyChannel::new(format!("{prefix}{name}").as_str(), FlatSamples::F32(samples))
+        })
+        .collect()
+}
+
+/// `render.exr` becomes `render.<suffix>.exr`.
+pub(super) fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{stem}.{suffix}.{extension}"))
+}
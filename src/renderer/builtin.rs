@@ -0,0 +1,289 @@
+use super::{
+    math::{Vec3, Vec4},
+    scene::{Environment, Material, Primitive, Scene, Shape, Viewpoint},
+    settings::RenderSettings,
+};
+
+/// Scenes built procedurally in Rust, reproducible without a scene file. Each one faces down the
+/// negative z axis from its camera, which is the only direction the tracer's `camera_dir` keeps
+/// undistorted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BuiltinScene {
+    /// The spheres on a green ground that the viewer opens by default.
+    Default,
+    /// A box with red and green side walls lit by an area light in the ceiling, holding a mirror
+    /// ball and a glass ball.
+    CornellBox,
+    /// A white diffuse ball in a uniform white environment, which should vanish into it.
+    Furnace,
+    /// Balls stepping through reflectivity, transparency and refractive index.
+    MaterialGrid,
+    /// Glass balls on a white floor focusing a small bright lamp into caustics.
+    Caustics,
+    /// The final scene of "Ray Tracing in One Weekend": hundreds of small random balls around
+    /// three large ones.
+    OneWeekend,
+}
+
+impl BuiltinScene {
+    pub const ALL: [BuiltinScene; 6] = [
+        BuiltinScene::Default,
+        BuiltinScene::CornellBox,
+        BuiltinScene::Furnace,
+        BuiltinScene::MaterialGrid,
+        BuiltinScene::Caustics,
+        BuiltinScene::OneWeekend,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BuiltinScene::Default => "default",
+            BuiltinScene::CornellBox => "cornell-box",
+            BuiltinScene::Furnace => "furnace",
+            BuiltinScene::MaterialGrid => "material-grid",
+            BuiltinScene::Caustics => "caustics",
+            BuiltinScene::OneWeekend => "one-weekend",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<BuiltinScene> {
+        BuiltinScene::ALL.into_iter().find(|scene| scene.name() == name)
+    }
+
+    pub fn build(self) -> Scene {
+        match self {
+            BuiltinScene::Default => Scene::default_scene(),
+            BuiltinScene::CornellBox => cornell_box(),
+            BuiltinScene::Furnace => furnace(),
+            BuiltinScene::MaterialGrid => material_grid(),
+            BuiltinScene::Caustics => caustics(),
+            BuiltinScene::OneWeekend => one_weekend(),
+        }
+    }
+}
+
+/// The box spans -1 to 1 in x and z and 0 to 2 in y, open towards the camera.
+fn cornell_box() -> Scene {
+    let mut scene = Scene::new();
+    let white = scene.add_material(Material::new("white", gray(0.73), 0.0));
+    let red = scene.add_material(Material::new("red", Vec4::new(0.65, 0.05, 0.05, 1.0), 0.0));
+    let green = scene.add_material(Material::new("green", Vec4::new(0.12, 0.45, 0.15, 1.0), 0.0));
+    let lamp = scene.add_material(Material {
+        emission: Vec4::new(17.0, 14.0, 11.0, 0.0),
+        ..Material::new("lamp", gray(1.0), 0.0)
+    });
+    let mirror = scene.add_material(Material::new("mirror", gray(0.95), 1.0));
+    let glass = scene.add_material(glass("glass", 1.5));
+
+    let mut quad = |name: &str, corner: Vec3, u: Vec3, v: Vec3, material: usize| {
+        scene.add_primitive(Primitive::new(name, Shape::Quad { corner, u, v }, material));
+    };
+    let (x, y, z) = (Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0), Vec3::new(0.0, 0.0, -2.0));
+    quad("left wall", Vec3::new(-1.0, 0.0, 1.0), y, z, red);
+    quad("right wall", Vec3::new(1.0, 0.0, 1.0), y, z, green);
+    quad("back wall", Vec3::new(-1.0, 0.0, -1.0), x, y, white);
+    quad("floor", Vec3::new(-1.0, 0.0, 1.0), x, z, white);
+    quad("ceiling", Vec3::new(-1.0, 2.0, 1.0), x, z, white);
+    // Just below the ceiling, so that the ceiling does not hide it.
+    quad("lamp", Vec3::new(-0.3, 1.999, 0.3), 0.3 * x, 0.3 * z, lamp);
+    scene.add_primitive(Primitive::sphere("mirror ball", Vec3::new(-0.45, 0.35, -0.4), 0.35, mirror));
+    scene.add_primitive(Primitive::sphere("glass ball", Vec3::new(0.45, 0.35, 0.3), 0.35, glass));
+
+    scene.environment = Environment { intensity: 0.0, ..Environment::default() };
+    scene.settings = RenderSettings { max_bounces: 16, ..RenderSettings::default() };
+    scene.camera = Some(Viewpoint {
+        position: Vec4::new(0.0, 1.0, 4.4, 0.0),
+        pitch: 0.0,
+        yaw: 0.0,
+        zoom: 1.5,
+    });
+    scene
+}
+
+fn furnace() -> Scene {
+    let mut scene = Scene::new();
+    let white = scene.add_material(Material::new("white", gray(1.0), 0.0));
+    scene.add_primitive(Primitive::sphere("ball", Vec3::new(0.0, 0.0, -3.0), 1.0, white));
+    scene.environment = Environment {
+        nadir: Vec4::new(1.0, 1.0, 1.0, 0.0),
+        zenith: Vec4::new(1.0, 1.0, 1.0, 0.0),
+        intensity: 1.0,
+    };
+    scene.camera = Some(Viewpoint {
+        position: Vec4::zero(),
+        pitch: 0.0,
+        yaw: 0.0,
+        zoom: 1.0,
+    });
+    scene
+}
+
+/// The top row steps a gold ball from diffuse to mirror, the middle one makes a blue ball more
+/// transparent and the bottom one raises the refractive index of clear glass.
+fn material_grid() -> Scene {
+    let mut scene = Scene::new();
+    let ground = scene.add_material(Material::new("ground", gray(0.5), 0.0));
+    scene.add_primitive(ground_plane("ground", ground));
+
+    const STEPS: usize = 5;
+    type Step = fn(f32) -> Material;
+    let rows: [(&str, f32, Step); 3] = [
+        ("reflections", 2.2, |t| Material::new("", Vec4::new(1.0, 0.78, 0.34, 1.0), t)),
+        ("transparency", 1.3, |t| Material {
+            transparency: 0.6 + 0.4 * t,
+            refractive_index: 1.5,
+            ..Material::new("", Vec4::new(0.3, 0.6, 1.0, 1.0), 1.0)
+        }),
+        ("refractive index", 0.4, |t| glass("", 1.0 + 1.4 * t)),
+    ];
+    for (property, y, material) in rows {
+        for step in 0..STEPS {
+            let t = step as f32 / (STEPS - 1) as f32;
+            let name = format!("{property} {}", step + 1);
+            let material = scene.add_material(Material { name: name.clone(), ..material(t) });
+            let center = Vec3::new(step as f32 - 2.0, y, 0.0);
+            scene.add_primitive(Primitive::sphere(&name, center, 0.4, material));
+        }
+    }
+    scene.camera = Some(Viewpoint {
+        position: Vec4::new(0.0, 1.3, 7.0, 0.0),
+        pitch: 0.0,
+        yaw: 0.0,
+        zoom: 1.8,
+    });
+    scene
+}
+
+fn caustics() -> Scene {
+    let mut scene = Scene::new();
+    let floor = scene.add_material(Material::new("floor", gray(0.9), 0.0));
+    let lamp = scene.add_material(Material {
+        emission: Vec4::new(40.0, 36.0, 30.0, 0.0),
+        ..Material::new("lamp", gray(1.0), 0.0)
+    });
+    let dense_glass = scene.add_material(glass("dense glass", 1.9));
+    let glass = scene.add_material(glass("glass", 1.5));
+
+    scene.add_primitive(ground_plane("floor", floor));
+    scene.add_primitive(Primitive::sphere("lamp", Vec3::new(0.0, 3.0, -1.0), 0.3, lamp));
+    scene.add_primitive(Primitive::sphere("left ball", Vec3::new(-0.8, 0.5, -1.0), 0.5, glass));
+    scene.add_primitive(Primitive::sphere("right ball", Vec3::new(0.8, 0.5, -1.0), 0.5, dense_glass));
+    scene.add_primitive(Primitive::sphere("lens", Vec3::new(0.0, 1.7, -1.0), 0.35, glass));
+
+    scene.environment = Environment { intensity: 0.02, ..Environment::default() };
+    scene.settings = RenderSettings { max_bounces: 16, ..RenderSettings::default() };
+    scene.camera = Some(Viewpoint {
+        position: Vec4::new(0.0, 1.5, 3.0, 0.0),
+        pitch: 15f32.to_radians(),
+        yaw: 0.0,
+        zoom: 1.5,
+    });
+    scene
+}
+
+/// The book looks from (13, 2, 3) at the origin with a 20 degree vertical field of view. The
+/// scene is turned about the y axis to put that camera on the positive z axis.
+fn one_weekend() -> Scene {
+    let from = Vec3::new(13.0, 2.0, 3.0);
+    let distance = from.x().hypot(from.z());
+    let (sin, cos) = (-from.x() / distance, from.z() / distance);
+    let turn = |p: Vec3| Vec3::new(p.x() * cos + p.z() * sin, p.y(), p.z() * cos - p.x() * sin);
+
+    let mut scene = Scene::new();
+    let add = |scene: &mut Scene, name: String, center: Vec3, radius: f32, material: Material| {
+        let material = scene.add_material(Material { name: name.clone(), ..material });
+        scene.add_primitive(Primitive::sphere(&name, turn(center), radius, material));
+    };
+    let ground = scene.add_material(Material::new("ground", gray(0.5), 0.0));
+    scene.add_primitive(ground_plane("ground", ground));
+
+    let mut random = Random(0x2545_f491);
+    for a in -11..11 {
+        for b in -11..11 {
+            let choice = random.next();
+            let center = Vec3::new(a as f32 + 0.9 * random.next(), 0.2, b as f32 + 0.9 * random.next());
+            if (center - Vec3::new(4.0, 0.2, 0.0)).length() <= 0.9 {
+                continue;
+            }
+            let material = if choice < 0.8 {
+                let albedo = Vec4::new(random.next() * random.next(), random.next() * random.next(), random.next() * random.next(), 1.0);
+                Material::new("", albedo, 0.0)
+            } else if choice < 0.95 {
+                // The tracer blends the mirror direction with a diffuse one; a blend of 1 - fuzz
+                // blurs reflections like the book's fuzz.
+                let albedo = Vec4::new(0.5 + 0.5 * random.next(), 0.5 + 0.5 * random.next(), 0.5 + 0.5 * random.next(), 1.0);
+                Material::new("", albedo, 1.0 - 0.5 * random.next())
+            } else {
+                glass("", 1.5)
+            };
+            add(&mut scene, format!("ball {a} {b}"), center, 0.2, material);
+        }
+    }
+    add(&mut scene, "glass ball".to_string(), Vec3::new(0.0, 1.0, 0.0), 1.0, glass("", 1.5));
+    add(&mut scene, "diffuse ball".to_string(), Vec3::new(-4.0, 1.0, 0.0), 1.0, Material::new("", Vec4::new(0.4, 0.2, 0.1, 1.0), 0.0));
+    add(&mut scene, "metal ball".to_string(), Vec3::new(4.0, 1.0, 0.0), 1.0, Material::new("", Vec4::new(0.7, 0.6, 0.5, 1.0), 1.0));
+
+    scene.settings = RenderSettings { max_bounces: 50, ..RenderSettings::default() };
+    scene.camera = Some(Viewpoint {
+        position: Vec4::new(0.0, from.y(), distance, 0.0),
+        // Down towards the origin.
+        pitch: (from.y() / distance).atan(),
+        yaw: 0.0,
+        // `camera_dir` turns a screen offset of 1 / zoom by half its angle, so a vertical half
+        // angle of 10 degrees takes 1 / tan(20 degrees).
+        zoom: 1.0 / 20f32.to_radians().tan(),
+    });
+    scene
+}
+
+/// Clear glass, which the tracer refracts through when more than half transparent.
+fn glass(name: &str, refractive_index: f32) -> Material {
+    Material {
+        transparency: 1.0,
+        refractive_index,
+        ..Material::new(name, gray(1.0), 1.0)
+    }
+}
+
+/// An opaque color, as the scene file reads one.
+fn gray(value: f32) -> Vec4 {
+    Vec4::new(value, value, value, 1.0)
+}
+
+/// The plane y = 0, facing up.
+fn ground_plane(name: &str, material: usize) -> Primitive {
+    Primitive::new(name, Shape::Plane { point: Vec3::zero(), normal: Vec3::new(0.0, 1.0, 0.0) }, material)
+}
+
+/// A xorshift generator, so that the random scenes come out the same everywhere.
+struct Random(u32);
+
+impl Random {
+    /// Uniform in [0, 1).
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::renderer::bvh::FlatScene};
+
+    #[test]
+    fn builtin_scenes() {
+        for builtin in BuiltinScene::ALL {
+            assert_eq!(BuiltinScene::from_name(builtin.name()), Some(builtin));
+            let scene = builtin.build();
+            assert!(scene.objects().iter().all(|object| object.primitives.iter().all(|primitive| primitive.material < scene.materials.len())));
+            // Every primitive makes it into the BVH.
+            assert_eq!(FlatScene::new(&scene).primitives.len(), scene.primitives.len(), "{}", builtin.name());
+            // Survives a scene file, but for the camera angles saved in degrees.
+            let loaded = Scene::from_toml(&scene.to_toml().unwrap()).unwrap();
+            assert_eq!(Scene { camera: scene.camera, ..loaded }, scene, "{}", builtin.name());
+        }
+        assert_eq!(BuiltinScene::from_name("cornell"), None);
+    }
+}
@@ -0,0 +1,305 @@
+use {
+    bytemuck::{Pod, Zeroable},
+    std::collections::HashMap,
+};
+
+use super::{
+    math::{Aabb, Mat4, Vec3, Vec4},
+    scene::{GpuPrimitive, Primitive, Scene, Shape},
+};
+
+/// Most items in a leaf. Larger leaves make smaller trees but test more items per leaf.
+const MAX_LEAF_ITEMS: usize = 2;
+
+/// Half the side of the box that stands in for the bounds of an infinite plane. Far beyond any
+/// scene, yet finite, so that transforming it and the slab test in `hits_box` stay clear of NaNs.
+const PLANE_EXTENT: f32 = 1e30;
+
+/// Mirrors `BvhNode` in shaders.wgsl. Inner nodes have a `count` of 0 and their children at
+/// `first` and `first + 1`; leaves hold `count` items starting at `first`.
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub(super) struct GpuBvhNode {
+    pub min: [f32; 3],
+    pub first: u32,
+    pub max: [f32; 3],
+    pub count: u32,
+}
+
+/// Mirrors `Instance` in shaders.wgsl: one object, drawing the bottom-level tree at `root` with a
+/// transform.
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub(super) struct GpuInstance {
+    pub to_world: Mat4,
+    pub to_local: Mat4,
+    pub root: u32,
+    /// Object ID reported for hits, which leaf order does not keep.
+    pub object: u32,
+    _pad: [u32; 2],
+}
+
+/// The scene flattened for the shader. `nodes` starts with the top-level tree over `instances`,
+/// rooted at node 0, followed by one bottom-level tree per shape over its primitives. Every object
+/// becomes an instance, but the primitives and tree of a geometry are stored once however many
+/// nodes draw it.
+pub(super) struct FlatScene {
+    pub primitives: Vec<GpuPrimitive>,
+    pub nodes: Vec<GpuBvhNode>,
+    pub instances: Vec<GpuInstance>,
+}
+
+impl FlatScene {
+    pub fn new(scene: &Scene) -> FlatScene {
+        let objects = scene.objects();
+        let bounds: Vec<Aabb> = objects
+            .iter()
+            .map(|object| primitives_bounds(object.primitives).transformed(&object.transform))
+            .collect();
+        let mut nodes = Vec::new();
+        let order = build(&bounds, &mut nodes);
+
+        let mut flat = FlatScene {
+            primitives: Vec::new(),
+            nodes,
+            instances: Vec::with_capacity(order.len()),
+        };
+        // Bottom-level trees by the address of the primitives they hold, so that nodes drawing the
+        // same geometry share one.
+        let mut roots = HashMap::new();
+        for id in order {
+            let object = &objects[id];
+            let root = *roots
+                .entry(object.primitives.as_ptr())
+                .or_insert_with(|| flat.add_shape(scene, object.primitives));
+            flat.instances.push(GpuInstance {
+                to_world: object.transform,
+                // Zero scales are rejected on load, so only degenerate transforms built in code
+                // fail to invert. They draw nothing.
+                to_local: object.transform.inverse().unwrap_or(Mat4::from_cols(
+                    Vec4::zero(),
+                    Vec4::zero(),
+                    Vec4::zero(),
+                    Vec4::new(f32::NAN, f32::NAN, f32::NAN, 1.0),
+                )),
+                root,
+                object: id as u32,
+                _pad: [0; 2],
+            });
+        }
+
+        // The shader indexes the first primitive and instance unconditionally.
+        if flat.primitives.is_empty() {
+            flat.primitives.push(Zeroable::zeroed());
+        }
+        if flat.instances.is_empty() {
+            flat.instances.push(Zeroable::zeroed());
+        }
+        flat
+    }
+
+    /// Appends a bottom-level tree over `primitives` and returns its root.
+    fn add_shape(&mut self, scene: &Scene, primitives: &[Primitive]) -> u32 {
+        let bounds: Vec<Aabb> = primitives.iter().map(|primitive| shape_bounds(&primitive.shape)).collect();
+        let root = self.nodes.len() as u32;
+        let first_primitive = self.primitives.len() as u32;
+        let start = self.nodes.len();
+        let order = build(&bounds, &mut self.nodes);
+        // Leaves of the new tree point into this shape's range of the primitive buffer.
+        for node in &mut self.nodes[start..] {
+            if node.count > 0 {
+                node.first += first_primitive;
+            }
+        }
+        self.primitives
+            .extend(order.into_iter().map(|primitive| primitives[primitive].to_gpu(&scene.materials)));
+        root
+    }
+}
+
+fn shape_bounds(shape: &Shape) -> Aabb {
+    let around = |center: Vec3, extent: Vec3| Aabb {
+        min: center - extent,
+        max: center + extent,
+    };
+    // Along each axis the rim of a disk reaches as far as the disk is tilted away from it.
+    let rim = |normal: Vec3, radius: f32| {
+        let normal = normal.normalized();
+        let reach = |axis: usize| radius * (1.0 - normal.get(axis) * normal.get(axis)).max(0.0).sqrt();
+        Vec3::new(reach(0), reach(1), reach(2))
+    };
+    match *shape {
+        Shape::Sphere { center, radius } => around(center, Vec3::all(radius)),
+        Shape::Plane { .. } => around(Vec3::zero(), Vec3::all(PLANE_EXTENT)),
+        Shape::Quad { corner, u, v } => [corner + u, corner + v, corner + u + v]
+            .into_iter()
+            .fold(Aabb::from_point(corner), |aabb, point| aabb.union(&Aabb::from_point(point))),
+        Shape::Disk { center, normal, radius } => around(center, rim(normal, radius)),
+        Shape::Box { min, max } => Aabb { min, max },
+        Shape::Cylinder { base, axis, radius } => {
+            around(base, rim(axis, radius)).union(&around(base + axis, rim(axis, radius)))
+        }
+        Shape::Cone { base, axis, base_radius, top_radius } => {
+            around(base, rim(axis, base_radius)).union(&around(base + axis, rim(axis, top_radius)))
+        }
+        Shape::Capsule { base, axis, radius } => {
+            around(base, Vec3::all(radius)).union(&around(base + axis, Vec3::all(radius)))
+        }
+        Shape::Torus { center, axis, major_radius, minor_radius } => {
+            around(center, rim(axis, major_radius) + Vec3::all(minor_radius))
+        }
+    }
+}
+
+fn primitives_bounds(primitives: &[Primitive]) -> Aabb {
+    primitives
+        .iter()
+        .map(|primitive| shape_bounds(&primitive.shape))
+        .fold(Aabb::empty(), |a, b| a.union(&b))
+}
+
+/// Appends a tree over items with `bounds` to `nodes` and returns the order the items must be
+/// stored in, so that each leaf covers a contiguous range of them. Leaf ranges start at 0; inner
+/// nodes point at their children in `nodes`. An empty tree is a single empty leaf.
+fn build(bounds: &[Aabb], nodes: &mut Vec<GpuBvhNode>) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..bounds.len()).collect();
+    let root = nodes.len();
+    nodes.push(Zeroable::zeroed());
+    split(bounds, &mut order, 0, root, nodes);
+    order
+}
+
+/// Fills in node `index` for the items `order[first..]`, splitting them at the median of their
+/// centers along the axis where the centers spread the most.
+fn split(bounds: &[Aabb], order: &mut [usize], first: usize, index: usize, nodes: &mut Vec<GpuBvhNode>) {
+    let aabb = order
+        .iter()
+        .fold(Aabb::empty(), |aabb, &item| aabb.union(&bounds[item]));
+    let (min, max) = if aabb.is_empty() {
+        // `hits_box` never enters a box with its min above its max.
+        ([f32::MAX; 3], [f32::MIN; 3])
+    } else {
+        (aabb.min.into(), aabb.max.into())
+    };
+    if order.len() <= MAX_LEAF_ITEMS {
+        nodes[index] = GpuBvhNode {
+            min,
+            first: first as u32,
+            max,
+            count: order.len() as u32,
+        };
+        return;
+    }
+
+    let centers = order.iter().fold(Aabb::empty(), |aabb, &item| {
+        aabb.union(&Aabb::from_point(bounds[item].center()))
+    });
+    let extent = centers.max - centers.min;
+    let axis = (0..3)
+        .max_by(|&a, &b| extent.get(a).total_cmp(&extent.get(b)))
+        .unwrap_or(0);
+    let middle = order.len() / 2;
+    order.select_nth_unstable_by(middle, |&a, &b| {
+        bounds[a].center().get(axis).total_cmp(&bounds[b].center().get(axis))
+    });
+
+    let left = nodes.len();
+    nodes.push(Zeroable::zeroed());
+    nodes.push(Zeroable::zeroed());
+    nodes[index] = GpuBvhNode {
+        min,
+        first: left as u32,
+        max,
+        count: 0,
+    };
+    let (lower, upper) = order.split_at_mut(middle);
+    split(bounds, lower, first, left, nodes);
+    split(bounds, upper, first + middle, left + 1, nodes);
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::renderer::{
+            math::{Quat, Transform},
+            scene::{Geometry, Node},
+        },
+        std::mem::{offset_of, size_of},
+    };
+
+    #[test]
+    fn gpu_bvh_node_layout() {
+        crate::renderer::tests::assert_matches_shader(
+            "BvhNode",
+            size_of::<GpuBvhNode>(),
+            &[
+                ("min", offset_of!(GpuBvhNode, min)),
+                ("first", offset_of!(GpuBvhNode, first)),
+                ("max", offset_of!(GpuBvhNode, max)),
+                ("count", offset_of!(GpuBvhNode, count)),
+            ],
+        );
+    }
+
+    #[test]
+    fn gpu_instance_layout() {
+        crate::renderer::tests::assert_matches_shader(
+            "Instance",
+            size_of::<GpuInstance>(),
+            &[
+                ("to_world", offset_of!(GpuInstance, to_world)),
+                ("to_local", offset_of!(GpuInstance, to_local)),
+                ("root", offset_of!(GpuInstance, root)),
+                ("object", offset_of!(GpuInstance, object)),
+                ("_pad", offset_of!(GpuInstance, _pad)),
+            ],
+        );
+    }
+
+    #[test]
+    fn instances_share_geometry() {
+        let mut scene = Scene::default_scene();
+        let pebble = scene.add_geometry(Geometry {
+            name: "pebble".to_string(),
+            primitives: (0..5)
+                .map(|i| Primitive::sphere("part", Vec3::new(i as f32, 0.0, 0.0), 0.5, 0))
+                .collect(),
+        });
+        let pile = scene.add_node(Node::new("pile", Transform::identity(), None));
+        for i in 0..1000 {
+            let transform = Transform {
+                translation: Vec3::new(0.0, i as f32, 0.0),
+                rotation: Quat::from_euler(0.0, i as f32, 0.0),
+                ..Transform::identity()
+            };
+            scene.add_node(Node {
+                parent: Some(pile),
+                ..Node::new("pebble", transform, Some(pebble))
+            });
+        }
+        let flat = FlatScene::new(&scene);
+        assert_eq!(flat.instances.len(), scene.primitives.len() + 1000);
+        assert_eq!(flat.primitives.len(), scene.primitives.len() + 5);
+
+        // Every object appears once in the top-level leaves, and every leaf lies within its
+        // parent.
+        let mut seen = vec![false; flat.instances.len()];
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = flat.nodes[index];
+            if node.count == 0 {
+                for child in [node.first as usize, node.first as usize + 1] {
+                    let inner = flat.nodes[child];
+                    assert!((0..3).all(|a| inner.min[a] >= node.min[a] && inner.max[a] <= node.max[a]));
+                    stack.push(child);
+                }
+            } else {
+                for instance in &flat.instances[node.first as usize..(node.first + node.count) as usize] {
+                    assert!(!std::mem::replace(&mut seen[instance.object as usize], true));
+                }
+            }
+        }
+        assert!(seen.into_iter().all(|seen| seen));
+    }
+}
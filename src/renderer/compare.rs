@@ -0,0 +1,178 @@
+use {
+    anyhow::{bail, Result},
+    std::fmt,
+};
+
+use super::aov::RenderOutput;
+
+/// Per-pixel differences between the beauty passes of a render and a reference of the same
+/// size, over the RGB channels. Pixels where either image is NaN or infinite are counted
+/// separately and left out of the averages.
+#[derive(Debug, Clone)]
+pub struct ImageComparison {
+    pub width: u32,
+    pub height: u32,
+    /// Absolute difference of every pixel, row by row.
+    pub errors: Vec<[f32; 3]>,
+    /// Root of the mean squared channel difference.
+    pub rmse: f32,
+    /// Mean absolute channel difference.
+    pub mean_absolute: f32,
+    /// Mean squared difference relative to the squared reference value, which weighs errors in
+    /// dark regions like those in bright ones.
+    pub relative_mse: f32,
+    /// Largest channel difference and the pixel it is in.
+    pub max: f32,
+    pub max_pixel: [u32; 2],
+    pub non_finite: usize,
+}
+
+impl ImageComparison {
+    pub fn new(image: &RenderOutput, reference: &RenderOutput) -> Result<ImageComparison> {
+        if [image.width, image.height] != [reference.width, reference.height] {
+            bail!(
+                "cannot compare a {}x{} image with a {}x{} reference",
+                image.width,
+                image.height,
+                reference.width,
+                reference.height
+            );
+        }
+        let mut comparison = ImageComparison {
+            width: image.width,
+            height: image.height,
+            errors: Vec::with_capacity(image.beauty.len()),
+            rmse: 0.0,
+            mean_absolute: 0.0,
+            relative_mse: 0.0,
+            max: 0.0,
+            max_pixel: [0, 0],
+            non_finite: 0,
+        };
+        let (mut squared, mut absolute, mut relative, mut channels) = (0.0f64, 0.0f64, 0.0f64, 0usize);
+        for (i, (pixel, expected)) in image.beauty.iter().zip(&reference.beauty).enumerate() {
+            let error: [f32; 3] = std::array::from_fn(|c| (pixel[c] - expected[c]).abs());
+            comparison.errors.push(error);
+            if !error.iter().all(|e| e.is_finite()) {
+                comparison.non_finite += 1;
+                continue;
+            }
+            for (c, &e) in error.iter().enumerate() {
+                squared += (e * e) as f64;
+                absolute += e as f64;
+                // The offset keeps black reference pixels from dominating.
+                relative += (e * e) as f64 / (expected[c] * expected[c] + 0.01) as f64;
+                channels += 1;
+                if e > comparison.max {
+                    comparison.max = e;
+                    comparison.max_pixel = [i as u32 % image.width, i as u32 / image.width];
+                }
+            }
+        }
+        if channels > 0 {
+            comparison.rmse = (squared / channels as f64).sqrt() as f32;
+            comparison.mean_absolute = (absolute / channels as f64) as f32;
+            comparison.relative_mse = (relative / channels as f64) as f32;
+        }
+        Ok(comparison)
+    }
+
+    /// Fraction of the finite pixels with a channel differing by more than `threshold`.
+    pub fn fraction_above(&self, threshold: f32) -> f32 {
+        let finite = self.errors.len() - self.non_finite;
+        if finite == 0 {
+            return 0.0;
+        }
+        let above = self
+            .errors
+            .iter()
+            .filter(|error| error.iter().all(|e| e.is_finite()) && error.iter().any(|&e| e > threshold))
+            .count();
+        above as f32 / finite as f32
+    }
+
+    /// The largest channel difference of the pixel at `percentile` (0 to 100) when the finite
+    /// pixels are sorted by it.
+    pub fn percentile(&self, percentile: f32) -> f32 {
+        let mut errors: Vec<f32> = self
+            .errors
+            .iter()
+            .map(|error| error.iter().copied().fold(0.0, f32::max))
+            .filter(|e| e.is_finite())
+            .collect();
+        if errors.is_empty() {
+            return 0.0;
+        }
+        errors.sort_unstable_by(f32::total_cmp);
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * (errors.len() - 1) as f32).round();
+        errors[rank as usize]
+    }
+
+    /// The absolute differences as an image, to be saved like a render.
+    pub fn error_image(&self) -> RenderOutput {
+        RenderOutput {
+            width: self.width,
+            height: self.height,
+            beauty: self.errors.iter().map(|&[r, g, b]| [r, g, b, 1.0]).collect(),
+            aovs: Vec::new(),
+            cryptomattes: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for ImageComparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "RMSE {:.6}, mean absolute error {:.6}, relative MSE {:.6}", self.rmse, self.mean_absolute, self.relative_mse)?;
+        writeln!(f, "largest error {:.6} at pixel {:?}", self.max, self.max_pixel)?;
+        writeln!(
+            f,
+            "per-pixel error: median {:.6}, 99th percentile {:.6}",
+            self.percentile(50.0),
+            self.percentile(99.0)
+        )?;
+        write!(
+            f,
+            "pixels off by more than 0.01: {:.2}%, by more than 0.1: {:.2}%; NaN or infinite: {}",
+            self.fraction_above(0.01) * 100.0,
+            self.fraction_above(0.1) * 100.0,
+            self.non_finite
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(beauty: Vec<[f32; 4]>) -> RenderOutput {
+        RenderOutput {
+            width: 2,
+            height: beauty.len() as u32 / 2,
+            beauty,
+            aovs: Vec::new(),
+            cryptomattes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn metrics() {
+        let reference = image(vec![[0.5; 4]; 4]);
+        assert_eq!(ImageComparison::new(&reference, &reference).unwrap().rmse, 0.0);
+
+        let mut beauty = vec![[0.5; 4]; 4];
+        beauty[1] = [0.5, 0.9, 0.5, 0.0];
+        beauty[3] = [f32::NAN, 0.5, 0.5, 1.0];
+        let comparison = ImageComparison::new(&image(beauty), &reference).unwrap();
+        // One channel of the nine finite ones is off by 0.4; alpha does not count.
+        assert!((comparison.rmse - (0.16f32 / 9.0).sqrt()).abs() < 1e-6);
+        assert!((comparison.mean_absolute - 0.4 / 9.0).abs() < 1e-6);
+        assert!((comparison.max - 0.4).abs() < 1e-6);
+        assert_eq!(comparison.max_pixel, [1, 0]);
+        assert_eq!(comparison.non_finite, 1);
+        assert!((comparison.fraction_above(0.1) - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(comparison.percentile(50.0), 0.0);
+        assert!((comparison.percentile(100.0) - 0.4).abs() < 1e-6);
+
+        assert!(ImageComparison::new(&image(vec![[0.5; 4]; 2]), &reference).is_err());
+    }
+}
@@ -0,0 +1,458 @@
+use {
+    anyhow::{bail, Result},
+    bytemuck::{Pod, Zeroable},
+    exr::prelude::{AnyChannel, AttributeValue, FlatSamples, Text},
+    wgpu::{self, PipelineCompilationOptions},
+};
+
+use super::{scene::Scene, storage_buffer_entry, uniform_entry};
+
+/// Number of ID/coverage pairs kept per pixel, written as three RGBA channel groups.
+pub const RANKS: usize = 6;
+
+/// Jittered primary rays cast per pixel each frame. Coverage converges over frames while the
+/// camera stands still.
+const RAYS_PER_FRAME: u32 = 4;
+
+/// Bytes per pixel of one rank buffer: `RANKS` pairs of (id, weight).
+const RANK_BYTES: u64 = RANKS as u64 * 8;
+
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct CryptoUniforms {
+    rays: u32,
+    seed: u32,
+    reset: u32,
+    _pad: u32,
+}
+
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct Rank {
+    id: u32,
+    weight: f32,
+}
+
+/// The two Cryptomatte types the tracer produces, in the order of the rank buffers.
+const TYPES: [&str; 2] = ["CryptoObject", "CryptoMaterial"];
+
+/// Accumulates, for every pixel, how much of it each object and material covers. IDs are the
+/// MurmurHash3 of the names in the scene, so they stay the same between renders and files.
+pub(super) struct Cryptomatte {
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::ComputePipeline,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    rank_buffers: [wgpu::Buffer; 2],
+    manifests: [Vec<(String, u32)>; 2],
+    /// Rays accumulated per pixel since the last reset.
+    rays: u32,
+    seed: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Cryptomatte {
+    pub fn new(
+        device: &wgpu::Device,
+        shader_module: &wgpu::ShaderModule,
+        uniform_layout: &wgpu::BindGroupLayout,
+        scene: &Scene,
+        width: u32,
+        height: u32,
+    ) -> Result<Cryptomatte> {
+        let size = width as u64 * height as u64 * RANK_BYTES;
+        let limit = device.limits().max_storage_buffer_binding_size as u64;
+        if size > limit {
+            bail!("Cryptomatte needs {size} byte storage buffers but the device allows {limit}");
+        }
+
+        let rank_buffers = TYPES.map(|label| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
+        });
+
+        let objects = scene.objects();
+        let names = [
+            objects.iter().map(|object| object.name).collect::<Vec<_>>(),
+            scene.materials.iter().map(|material| material.name.as_str()).collect(),
+        ];
+        // The IDs hits contribute, by object ID and by material index.
+        let object_ids = ids_buffer(device, "Cryptomatte object IDs", &names[0]);
+        let material_ids = ids_buffer(device, "Cryptomatte material IDs", &names[1]);
+
+        let manifests = names.map(|names| manifest(names.into_iter()));
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cryptomatte uniforms"),
+            size: std::mem::size_of::<CryptoUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Cryptomatte"),
+            entries: &[
+                storage_buffer_entry(0, false),
+                storage_buffer_entry(1, false),
+                storage_buffer_entry(2, true),
+                uniform_entry(3),
+                storage_buffer_entry(4, true),
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Cryptomatte"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: rank_buffers[0].as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: rank_buffers[1].as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: object_ids.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: material_ids.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Cryptomatte"),
+            bind_group_layouts: &[uniform_layout, &layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(device, shader_module, &pipeline_layout);
+
+        Ok(Cryptomatte {
+            pipeline_layout,
+            pipeline,
+            uniform_buffer,
+            bind_group,
+            rank_buffers,
+            manifests,
+            rays: 0,
+            seed: 0,
+            width,
+            height,
+        })
+    }
+
+    /// Rebuilds the pipeline from a reloaded shader module.
+    pub fn set_shader(&mut self, device: &wgpu::Device, shader_module: &wgpu::ShaderModule) {
+        self.pipeline = create_pipeline(device, shader_module, &self.pipeline_layout);
+    }
+
+    /// Records one accumulation pass. `reset` drops the coverage gathered so far, e.g. after the
+    /// camera moved.
+    pub fn encode(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        uniform_bind_group: &wgpu::BindGroup,
+        reset: bool,
+    ) {
+        if reset {
+            self.rays = 0;
+        }
+        let uniforms = CryptoUniforms {
+            rays: RAYS_PER_FRAME,
+            seed: self.seed,
+            reset: (self.rays == 0) as u32,
+            _pad: 0,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+        self.rays = self.rays.saturating_add(RAYS_PER_FRAME);
+        self.seed = self.seed.wrapping_add(RAYS_PER_FRAME);
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Cryptomatte"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, uniform_bind_group, &[]);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.dispatch_workgroups(self.width.div_ceil(8), self.height.div_ceil(8), 1);
+    }
+
+    /// Turns the accumulated ranks into mattes, reading each rank buffer with `read_buffer`.
+    pub fn read(
+        &self,
+        read_buffer: impl Fn(&wgpu::Buffer) -> Result<Vec<u8>>,
+    ) -> Result<Vec<CryptomatteLayer>> {
+        let total = self.rays.max(1) as f32;
+        TYPES
+            .iter()
+            .zip(&self.rank_buffers)
+            .zip(&self.manifests)
+            .map(|((name, buffer), manifest)| {
+                let data = read_buffer(buffer)?;
+                let ranks: Vec<Rank> = bytemuck::pod_collect_to_vec(&data);
+                let pixels = ranks
+                    .chunks_exact(RANKS)
+                    .map(|pixel| {
+                        let mut sorted = [(0.0, 0.0); RANKS];
+                        for (out, rank) in sorted.iter_mut().zip(pixel) {
+                            *out = (f32::from_bits(rank.id), rank.weight / total);
+                        }
+                        sorted.sort_by(|a, b| b.1.total_cmp(&a.1));
+                        sorted
+                    })
+                    .collect();
+                Ok(CryptomatteLayer {
+                    name: name.to_string(),
+                    manifest: manifest.clone(),
+                    pixels,
+                })
+            })
+            .collect()
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    shader_module: &wgpu::ShaderModule,
+    pipeline_layout: &wgpu::PipelineLayout,
+) -> wgpu::ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Cryptomatte"),
+        layout: Some(pipeline_layout),
+        module: shader_module,
+        entry_point: Some("cryptomatte_cs"),
+        compilation_options: PipelineCompilationOptions::default(),
+        cache: None,
+    })
+}
+
+/// One Cryptomatte type of a frame, such as `CryptoObject`.
+pub struct CryptomatteLayer {
+    pub name: String,
+    /// Every name that can appear in the matte and its ID.
+    pub manifest: Vec<(String, u32)>,
+    /// Per pixel, the IDs as floats paired with their coverage, most coverage first. Unused ranks
+    /// have zero coverage.
+    pub pixels: Vec<[(f32, f32); RANKS]>,
+}
+
+impl CryptomatteLayer {
+    /// The `<name>00` .. `<name>02` channels: each RGBA group holds two (ID, coverage) ranks.
+    pub fn exr_channels(&self) -> Vec<AnyChannel<FlatSamples>> {
+        let mut channels = Vec::new();
+        for rank in 0..RANKS {
+            let (group, second) = (rank / 2, rank % 2 == 1);
+            let (id_channel, coverage_channel) = if second { ("B", "A") } else { ("R", "G") };
+            let ids = self.pixels.iter().map(|p| p[rank].0).collect();
+            let coverage = self.pixels.iter().map(|p| p[rank].1).collect();
+            let prefix = format!("{}{group:02}", self.name);
+            channels.push(AnyChannel::new(
+                format!("{prefix}.{id_channel}").as_str(),
+                FlatSamples::F32(ids),
+            ));
+            channels.push(AnyChannel::new(
+                format!("{prefix}.{coverage_channel}").as_str(),
+                FlatSamples::F32(coverage),
+            ));
+        }
+        channels
+    }
+
+    /// The `cryptomatte/<key>/...` header attributes that let compositors find the layer and map
+    /// IDs back to names.
+    pub fn exr_attributes(&self) -> Vec<(Text, AttributeValue)> {
+        let key = &format!("{:08x}", murmur3_32(self.name.as_bytes(), 0))[..7];
+        let text = |value: &str| AttributeValue::Text(Text::from_slice_unchecked(value.as_bytes()));
+        let entries: Vec<String> = self
+            .manifest
+            .iter()
+            .map(|(name, id)| format!("{}:\"{id:08x}\"", json_string(name)))
+            .collect();
+        [
+            ("name", self.name.clone()),
+            ("hash", "MurmurHash3_32".to_string()),
+            ("conversion", "uint32_to_float32".to_string()),
+            ("manifest", format!("{{{}}}", entries.join(","))),
+        ]
+        .into_iter()
+        .map(|(field, value)| {
+            (Text::new_or_panic(format!("cryptomatte/{key}/{field}")), text(&value))
+        })
+        .collect()
+    }
+}
+
+/// The ID Cryptomatte assigns to `name`: its MurmurHash3 with the exponent kept away from
+/// denormals, infinities and NaNs, so that it survives being stored as a float.
+pub fn cryptomatte_id(name: &str) -> u32 {
+    let hash = murmur3_32(name.as_bytes(), 0);
+    let exponent = ((hash >> 23) & 0xff).clamp(1, 254);
+    (hash & !(0xff << 23)) | (exponent << 23)
+}
+
+/// MurmurHash3_x86_32.
+pub fn murmur3_32(key: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    let mix = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+    let mut hash = seed;
+    let mut blocks = key.chunks_exact(4);
+    for block in &mut blocks {
+        hash ^= mix(u32::from_le_bytes([block[0], block[1], block[2], block[3]]));
+        hash = hash.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let k = tail
+            .iter()
+            .enumerate()
+            .fold(0, |k, (i, &byte)| k | (byte as u32) << (8 * i));
+        hash ^= mix(k);
+    }
+
+    hash ^= key.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^ (hash >> 16)
+}
+
+/// A storage buffer with the Cryptomatte ID of each of `names`.
+fn ids_buffer(device: &wgpu::Device, label: &str, names: &[&str]) -> wgpu::Buffer {
+    let ids: Vec<u32> = names.iter().map(|name| cryptomatte_id(name)).collect();
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: (ids.len().max(1) * 4) as u64,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: true,
+    });
+    buffer.slice(..).get_mapped_range_mut()[..ids.len() * 4].copy_from_slice(bytemuck::cast_slice(&ids));
+    buffer.unmap();
+    buffer
+}
+
+/// Unique names with their IDs, in scene order.
+fn manifest<'a>(names: impl Iterator<Item = &'a str>) -> Vec<(String, u32)> {
+    let mut manifest: Vec<(String, u32)> = Vec::new();
+    for name in names {
+        if !manifest.iter().any(|(existing, _)| existing == name) {
+            manifest.push((name.to_string(), cryptomatte_id(name)));
+        }
+    }
+    manifest
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn murmur3_matches_reference_vectors() {
+        let vectors: [(&[u8], u32); 9] = [
+            (b"", 0),
+            (b"\0\0\0\0", 0x2362_f9de),
+            (b"\xff\xff\xff\xff", 0x7629_3b50),
+            (b"\x21\x43\x65\x87", 0xf55b_516b),
+            // One to three bytes past the last whole block.
+            (b"\x21\x43\x65", 0x7e4a_8634),
+            (b"\x21\x43", 0xa0f7_b07a),
+            (b"\x21", 0x7266_1cf4),
+            (b"Hello, world!", 0xc036_3e43),
+            (b"The quick brown fox jumps over the lazy dog", 0x2e4f_f723),
+        ];
+        for (key, hash) in vectors {
+            assert_eq!(murmur3_32(key, 0), hash, "{key:?}");
+        }
+    }
+
+    #[test]
+    fn ids_are_normal_floats() {
+        let names: Vec<String> = (0..10_000).map(|i| format!("object {i}")).collect();
+        // Some of these hash to a denormal, infinite or NaN bit pattern that has to be moved.
+        let exponent = |name: &String| (murmur3_32(name.as_bytes(), 0) >> 23) & 0xff;
+        assert!(names.iter().any(|name| exponent(name) == 0));
+        assert!(names.iter().any(|name| exponent(name) == 0xff));
+        for name in &names {
+            assert!(f32::from_bits(cryptomatte_id(name)).is_normal(), "{name}");
+        }
+    }
+
+    #[test]
+    fn ids_match_the_specification() {
+        // The example manifest in the Cryptomatte specification.
+        assert_eq!(cryptomatte_id("default"), 0x42c9_679f);
+        // An ID whose hash is already a normal float is the hash itself.
+        assert_eq!(cryptomatte_id("hello"), murmur3_32(b"hello", 0));
+    }
+
+    #[test]
+    fn manifest_keeps_the_first_of_each_name() {
+        let manifest = manifest(["wall", "ball", "wall", "floor"].into_iter());
+        let unique: Vec<&str> = manifest.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(unique, ["wall", "ball", "floor"]);
+        assert!(manifest.iter().all(|(name, id)| *id == cryptomatte_id(name)));
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(json_string("plain"), r#""plain""#);
+        assert_eq!(json_string(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(json_string(r"C:\scene"), r#""C:\\scene""#);
+        assert_eq!(json_string("a\tb\n"), r#""a\u0009b\u000a""#);
+    }
+
+    #[test]
+    fn header_attributes_are_keyed_by_the_layer_hash() {
+        let layer = CryptomatteLayer {
+            name: "CryptoObject".to_string(),
+            manifest: manifest(["default", r#""quoted""#].into_iter()),
+            pixels: Vec::new(),
+        };
+        let attributes: Vec<(String, String)> = layer
+            .exr_attributes()
+            .into_iter()
+            .map(|(key, value)| match value {
+                AttributeValue::Text(text) => (key.to_string(), text.to_string()),
+                other => panic!("{key} is not text: {other:?}"),
+            })
+            .collect();
+        // The first 7 hex digits of the MurmurHash3 of "CryptoObject", 3ae39a58.
+        let quoted = cryptomatte_id(r#""quoted""#);
+        let expected = [
+            ("cryptomatte/3ae39a5/name", "CryptoObject".to_string()),
+            ("cryptomatte/3ae39a5/hash", "MurmurHash3_32".to_string()),
+            ("cryptomatte/3ae39a5/conversion", "uint32_to_float32".to_string()),
+            ("cryptomatte/3ae39a5/manifest", format!(r#"{{"default":"42c9679f","\"quoted\"":"{quoted:08x}"}}"#)),
+        ];
+        let expected: Vec<(String, String)> = expected.map(|(key, value)| (key.to_string(), value)).into();
+        assert_eq!(attributes, expected);
+    }
+}
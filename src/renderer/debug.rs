@@ -0,0 +1,58 @@
+/// What the viewer shows instead of the shaded image. The values match the `DEBUG_*` constants in
+/// shaders.wgsl.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[repr(u32)]
+pub enum DebugMode {
+    #[default]
+    Off = 0,
+    /// Geometric normal of the first hit, mapped from [-1, 1] to [0, 1].
+    Normals = 1,
+    /// Distance to the first hit as a heat map.
+    HitDistance = 2,
+    /// Material color of the first hit.
+    Albedo = 3,
+    /// Surfaces hit before the path ended, as a heat map.
+    BounceHeatMap = 4,
+    /// A distinct color per primitive.
+    PrimitiveId = 5,
+    /// NaN samples in magenta and infinite samples in yellow over a gray image.
+    NanInf = 6,
+    /// Per-sample luminance standard deviation tracked by the denoiser's temporal pass, as a heat
+    /// map.
+    Variance = 7,
+    /// Surface coordinates of the first hit, wrapped to [0, 1] in red and green.
+    Uv = 8,
+}
+
+impl DebugMode {
+    pub const ALL: [DebugMode; 9] = [
+        DebugMode::Off,
+        DebugMode::Normals,
+        DebugMode::HitDistance,
+        DebugMode::Albedo,
+        DebugMode::BounceHeatMap,
+        DebugMode::PrimitiveId,
+        DebugMode::NanInf,
+        DebugMode::Variance,
+        DebugMode::Uv,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DebugMode::Off => "off",
+            DebugMode::Normals => "normals",
+            DebugMode::HitDistance => "hit distance",
+            DebugMode::Albedo => "albedo",
+            DebugMode::BounceHeatMap => "bounce heat map",
+            DebugMode::PrimitiveId => "primitive ID",
+            DebugMode::NanInf => "NaN/Inf",
+            DebugMode::Variance => "variance",
+            DebugMode::Uv => "UV",
+        }
+    }
+
+    /// The mode after this one, wrapping around to `Off`.
+    pub fn next(self) -> DebugMode {
+        DebugMode::ALL[(self as usize + 1) % DebugMode::ALL.len()]
+    }
+}
@@ -0,0 +1,295 @@
+use {
+    bytemuck::{Pod, Zeroable},
+    wgpu::{self, PipelineCompilationOptions},
+};
+
+use super::{math::CameraUniforms, sampled_texture_entry, storage_texture_entry, uniform_entry, view_entry};
+
+/// Number of a-trous wavelet iterations. Iteration `i` samples its 5x5 footprint with a step
+/// of `2^i` pixels.
+const ATROUS_ITERATIONS: usize = 5;
+
+/// History length used while the camera moves. A short history keeps ghosting in check; once the
+/// camera stops the cap is lifted and samples accumulate indefinitely.
+const MOVING_HISTORY_CAP: f32 = 8.0;
+const STATIC_HISTORY_CAP: f32 = 1_000_000.0;
+
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct TemporalUniforms {
+    prev_camera: CameraUniforms,
+    prev_fov: f32,
+    history_cap: f32,
+    reset: u32,
+    _pad: u32,
+}
+
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct AtrousUniforms {
+    step: i32,
+    _pad: [i32; 3],
+}
+
+/// The camera state of the frame before the one being denoised.
+pub struct History {
+    pub camera: CameraUniforms,
+    pub fov: f32,
+    pub moved: bool,
+    pub reset: bool,
+}
+
+/// SVGF-style denoiser: reprojects the previous frame's accumulated color and luminance moments
+/// into the current frame, then runs an edge-avoiding a-trous filter steered by the variance.
+pub struct Denoiser {
+    temporal_pipeline_layout: wgpu::PipelineLayout,
+    atrous_pipeline_layout: wgpu::PipelineLayout,
+    temporal_pipeline: wgpu::ComputePipeline,
+    atrous_pipeline: wgpu::ComputePipeline,
+    temporal_uniform_buffer: wgpu::Buffer,
+    temporal_bind_groups: [wgpu::BindGroup; 2],
+    atrous_bind_groups: [Vec<wgpu::BindGroup>; 2],
+    color_textures: [wgpu::Texture; 2],
+    filter_textures: [wgpu::Texture; 2],
+    color_views: [wgpu::TextureView; 2],
+    filter_views: [wgpu::TextureView; 2],
+    width: u32,
+    height: u32,
+}
+
+impl Denoiser {
+    /// `radiance` is the tracer's noisy output and `gbuffers` the pair of normal/depth textures it
+    /// alternates between; frame parity `cur` says which one holds the current frame.
+    pub fn new(
+        device: &wgpu::Device,
+        shader_module: &wgpu::ShaderModule,
+        uniform_layout: &wgpu::BindGroupLayout,
+        radiance: &wgpu::TextureView,
+        gbuffers: &[wgpu::TextureView; 2],
+        width: u32,
+        height: u32,
+    ) -> Denoiser {
+        let (_, color0, color1) =
+            super::PathTracer::create_sample_texture(device, "denoiser history", width, height);
+        let (_, moments0, moments1) =
+            super::PathTracer::create_sample_texture(device, "denoiser moments", width, height);
+        let (_, filter0, filter1) =
+            super::PathTracer::create_sample_texture(device, "denoiser filter", width, height);
+        let color_textures = [color0, color1];
+        let filter_textures = [filter0, filter1];
+        let color_views = color_textures.each_ref().map(|t| t.create_view(&Default::default()));
+        let moments_views = [moments0, moments1].map(|t| t.create_view(&Default::default()));
+        let filter_views = filter_textures.each_ref().map(|t| t.create_view(&Default::default()));
+
+        let temporal_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("temporal reprojection"),
+            entries: &[
+                sampled_texture_entry(0),
+                sampled_texture_entry(1),
+                sampled_texture_entry(2),
+                sampled_texture_entry(3),
+                sampled_texture_entry(4),
+                storage_texture_entry(5),
+                storage_texture_entry(6),
+                uniform_entry(7),
+            ],
+        });
+        let atrous_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("a-trous filter"),
+            entries: &[
+                sampled_texture_entry(0),
+                sampled_texture_entry(1),
+                storage_texture_entry(2),
+                uniform_entry(3),
+            ],
+        });
+        let temporal_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("temporal reprojection"),
+            bind_group_layouts: &[uniform_layout, &temporal_layout],
+            push_constant_ranges: &[],
+        });
+        let atrous_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("a-trous filter"),
+            bind_group_layouts: &[uniform_layout, &atrous_layout],
+            push_constant_ranges: &[],
+        });
+        let temporal_pipeline =
+            create_compute_pipeline(device, shader_module, "temporal_cs", &temporal_pipeline_layout);
+        let atrous_pipeline =
+            create_compute_pipeline(device, shader_module, "atrous_cs", &atrous_pipeline_layout);
+
+        let temporal_uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("temporal uniforms"),
+            size: std::mem::size_of::<TemporalUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let temporal_bind_groups = [0, 1].map(|cur| {
+            let prev = 1 - cur;
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("temporal reprojection"),
+                layout: &temporal_layout,
+                entries: &[
+                    view_entry(0, radiance),
+                    view_entry(1, &gbuffers[cur]),
+                    view_entry(2, &gbuffers[prev]),
+                    view_entry(3, &color_views[prev]),
+                    view_entry(4, &moments_views[prev]),
+                    view_entry(5, &color_views[cur]),
+                    view_entry(6, &moments_views[cur]),
+                    wgpu::BindGroupEntry {
+                        binding: 7,
+                        resource: temporal_uniform_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        });
+
+        // Each iteration gets its own small uniform buffer holding its step size so that all of
+        // them can be recorded into a single command buffer.
+        let atrous_uniform_buffers: Vec<wgpu::Buffer> = (0..ATROUS_ITERATIONS)
+            .map(|i| {
+                let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("a-trous uniforms"),
+                    size: std::mem::size_of::<AtrousUniforms>() as u64,
+                    usage: wgpu::BufferUsages::UNIFORM,
+                    mapped_at_creation: true,
+                });
+                let uniforms = AtrousUniforms { step: 1 << i, _pad: [0; 3] };
+                buffer
+                    .slice(..)
+                    .get_mapped_range_mut()
+                    .copy_from_slice(bytemuck::bytes_of(&uniforms));
+                buffer.unmap();
+                buffer
+            })
+            .collect();
+
+        // Iteration 0 reads the temporally integrated color, the rest ping-pong between the two
+        // filter textures.
+        let atrous_bind_groups = [0, 1].map(|cur| {
+            (0..ATROUS_ITERATIONS)
+                .map(|i| {
+                    let input = if i == 0 {
+                        &color_views[cur]
+                    } else {
+                        &filter_views[(i - 1) % 2]
+                    };
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("a-trous filter"),
+                        layout: &atrous_layout,
+                        entries: &[
+                            view_entry(0, input),
+                            view_entry(1, &gbuffers[cur]),
+                            view_entry(2, &filter_views[i % 2]),
+                            wgpu::BindGroupEntry {
+                                binding: 3,
+                                resource: atrous_uniform_buffers[i].as_entire_binding(),
+                            },
+                        ],
+                    })
+                })
+                .collect()
+        });
+
+        Denoiser {
+            temporal_pipeline_layout,
+            atrous_pipeline_layout,
+            temporal_pipeline,
+            atrous_pipeline,
+            temporal_uniform_buffer,
+            temporal_bind_groups,
+            atrous_bind_groups,
+            color_textures,
+            filter_textures,
+            color_views,
+            filter_views,
+            width,
+            height,
+        }
+    }
+
+    /// Rebuilds the pipelines from a reloaded shader module.
+    pub fn set_shader(&mut self, device: &wgpu::Device, shader_module: &wgpu::ShaderModule) {
+        self.temporal_pipeline =
+            create_compute_pipeline(device, shader_module, "temporal_cs", &self.temporal_pipeline_layout);
+        self.atrous_pipeline =
+            create_compute_pipeline(device, shader_module, "atrous_cs", &self.atrous_pipeline_layout);
+    }
+
+    /// Records the temporal pass, and the spatial filter if `filter` is set, for the frame whose
+    /// G-buffer is `gbuffers[cur]`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        uniform_bind_group: &wgpu::BindGroup,
+        cur: usize,
+        history: &History,
+        filter: bool,
+        timestamp_writes: Option<wgpu::ComputePassTimestampWrites>,
+    ) {
+        let uniforms = TemporalUniforms {
+            prev_camera: history.camera,
+            prev_fov: history.fov,
+            history_cap: if history.moved { MOVING_HISTORY_CAP } else { STATIC_HISTORY_CAP },
+            reset: history.reset as u32,
+            _pad: 0,
+        };
+        queue.write_buffer(&self.temporal_uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("denoiser"),
+            timestamp_writes,
+        });
+        let groups = (self.width.div_ceil(8), self.height.div_ceil(8));
+        pass.set_bind_group(0, uniform_bind_group, &[]);
+
+        pass.set_pipeline(&self.temporal_pipeline);
+        pass.set_bind_group(1, &self.temporal_bind_groups[cur], &[]);
+        pass.dispatch_workgroups(groups.0, groups.1, 1);
+
+        if filter {
+            pass.set_pipeline(&self.atrous_pipeline);
+            for bind_group in &self.atrous_bind_groups[cur] {
+                pass.set_bind_group(1, bind_group, &[]);
+                pass.dispatch_workgroups(groups.0, groups.1, 1);
+            }
+        }
+    }
+
+    /// The texture holding the final denoised color of the frame `cur`.
+    pub fn output(&self, cur: usize, filter: bool) -> &wgpu::TextureView {
+        if filter {
+            &self.filter_views[(ATROUS_ITERATIONS - 1) % 2]
+        } else {
+            &self.color_views[cur]
+        }
+    }
+
+    pub fn output_texture(&self, cur: usize, filter: bool) -> &wgpu::Texture {
+        if filter {
+            &self.filter_textures[(ATROUS_ITERATIONS - 1) % 2]
+        } else {
+            &self.color_textures[cur]
+        }
+    }
+}
+
+fn create_compute_pipeline(
+    device: &wgpu::Device,
+    shader_module: &wgpu::ShaderModule,
+    entry_point: &str,
+    pipeline_layout: &wgpu::PipelineLayout,
+) -> wgpu::ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(entry_point),
+        layout: Some(pipeline_layout),
+        module: shader_module,
+        entry_point: Some(entry_point),
+        compilation_options: PipelineCompilationOptions::default(),
+        cache: None,
+    })
+}
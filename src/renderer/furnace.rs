@@ -0,0 +1,140 @@
+use std::fmt;
+
+use super::{
+    aov::RenderOutput,
+    builtin::BuiltinScene,
+    pick::pick,
+    reference::render_reference,
+    scene::{Material, Scene},
+};
+
+/// Side of the square images rendered for the furnace test, in pixels.
+pub const FURNACE_SIZE: u32 = 48;
+
+/// What a material returns in the white furnace: a ball of it alone in an environment of uniform
+/// radiance 1. Whatever direction a path leaves the ball in, it finds the same light, so the ball
+/// returns exactly the fraction of light its surface keeps: its color, if it conserves energy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FurnaceReading {
+    pub material: String,
+    /// What the ball should return: the material's color, or its emission if it emits.
+    pub expected: [f32; 3],
+    /// Average radiance over the pixels the ball covers.
+    pub returned: [f32; 3],
+}
+
+impl FurnaceReading {
+    /// Measures `material` in an image of `furnace_scene(material)` rendered at `FURNACE_SIZE`.
+    pub fn new(material: &Material, image: &RenderOutput) -> FurnaceReading {
+        let scene = furnace_scene(material);
+        let viewpoint = scene.camera.unwrap_or_default();
+        let camera = viewpoint.camera();
+        let size = [image.width, image.height];
+        let covered = |x: i64, y: i64| {
+            let inside = (0..image.width as i64).contains(&x) && (0..image.height as i64).contains(&y);
+            inside && pick(&scene, &camera, viewpoint.zoom, size, [x as f32 + 0.5, y as f32 + 0.5]).is_some()
+        };
+
+        let (mut sum, mut pixels) = ([0.0f64; 3], 0);
+        for (i, pixel) in image.beauty.iter().enumerate() {
+            let (x, y) = ((i as u32 % image.width) as i64, (i as u32 / image.width) as i64);
+            // Pixels on the silhouette may partly show the environment.
+            if [(0, 0), (-1, 0), (1, 0), (0, -1), (0, 1)].iter().all(|(dx, dy)| covered(x + dx, y + dy)) {
+                sum.iter_mut().zip(pixel).for_each(|(sum, &value)| *sum += value as f64);
+                pixels += 1;
+            }
+        }
+        let emission = material.emission.xyz();
+        let expected = if emission == Default::default() { material.color.xyz() } else { emission };
+        FurnaceReading {
+            material: material.name.clone(),
+            expected: expected.into(),
+            returned: sum.map(|sum| (sum / pixels.max(1) as f64) as f32),
+        }
+    }
+
+    /// Runs the furnace test for each of `materials` on the CPU with the reference tracer.
+    pub fn measure_all(materials: &[Material], spp: u32) -> Vec<FurnaceReading> {
+        materials
+            .iter()
+            .map(|material| {
+                let scene = furnace_scene(material);
+                let viewpoint = scene.camera.unwrap_or_default();
+                let image = render_reference(&scene, &viewpoint, [FURNACE_SIZE; 2], spp);
+                FurnaceReading::new(material, &image)
+            })
+            .collect()
+    }
+
+    /// The largest amount by which a channel returns more than expected, or 0 if none does.
+    pub fn gain(&self) -> f32 {
+        (0..3).map(|c| self.returned[c] - self.expected[c]).fold(0.0, f32::max)
+    }
+
+    /// The largest amount by which a channel returns less than expected, or 0 if none does.
+    pub fn loss(&self) -> f32 {
+        (0..3).map(|c| self.expected[c] - self.returned[c]).fold(0.0, f32::max)
+    }
+}
+
+impl fmt::Display for FurnaceReading {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [r, g, b] = self.returned;
+        let [er, eg, eb] = self.expected;
+        write!(f, "{}: returned [{r:.4}, {g:.4}, {b:.4}], expected [{er:.4}, {eg:.4}, {eb:.4}]", self.material)
+    }
+}
+
+/// The furnace scene with its ball made of `material`.
+pub fn furnace_scene(material: &Material) -> Scene {
+    let mut scene = BuiltinScene::Furnace.build();
+    scene.materials[0] = material.clone();
+    scene
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::renderer::math::Vec4};
+
+    /// One material of each kind the tracer tells apart, in `color`.
+    fn materials(color: Vec4) -> Vec<Material> {
+        let transparent = |name: &str, transparency: f32, refractive_index: f32| Material {
+            transparency,
+            refractive_index,
+            ..Material::new(name, color, 1.0)
+        };
+        vec![
+            Material::new("diffuse", color, 0.0),
+            Material::new("glossy", color, 0.3),
+            Material::new("shiny", color, 0.7),
+            Material::new("mirror", color, 1.0),
+            // Reflects like an opaque surface below half transparency.
+            Material { transparency: 0.4, refractive_index: 1.5, ..Material::new("translucent", color, 0.0) },
+            transparent("tinted glass", 0.6, 1.5),
+            transparent("glass", 1.0, 1.5),
+            transparent("dense glass", 1.0, 2.4),
+            transparent("clear", 1.0, 1.0),
+        ]
+    }
+
+    #[test]
+    fn white_materials_lose_nothing() {
+        for reading in FurnaceReading::measure_all(&materials(Vec4::all(1.0)), 4) {
+            assert!(reading.gain() < 1e-4 && reading.loss() < 1e-4, "{reading}");
+        }
+    }
+
+    #[test]
+    fn materials_return_their_color() {
+        for reading in FurnaceReading::measure_all(&materials(Vec4::new(0.8, 0.5, 0.2, 1.0)), 4) {
+            assert!(reading.gain() < 1e-4 && reading.loss() < 1e-4, "{reading}");
+        }
+    }
+
+    #[test]
+    fn emitters_return_their_emission() {
+        let lamp = Material { emission: Vec4::new(2.0, 1.0, 0.5, 0.0), ..Material::new("lamp", Vec4::all(1.0), 0.0) };
+        let [reading] = &FurnaceReading::measure_all(&[lamp], 1)[..] else { unreachable!() };
+        assert_eq!(reading.returned, [2.0, 1.0, 0.5]);
+    }
+}
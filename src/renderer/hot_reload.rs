@@ -0,0 +1,59 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use super::{validate_shader, SHADER_PATH};
+
+/// How often the shader file's modification time is checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Watches shaders.wgsl in the source tree so that edits show up without restarting. Used in
+/// debug builds only; release builds keep the shader compiled into the binary.
+pub(super) struct ShaderWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    /// Returns `None` when the source tree is not around, as for a binary run on another machine.
+    pub fn new() -> Option<ShaderWatcher> {
+        let path = PathBuf::from(SHADER_PATH);
+        let modified = std::fs::metadata(&path).and_then(|metadata| metadata.modified()).ok()?;
+        Some(ShaderWatcher {
+            path,
+            modified: Some(modified),
+            last_poll: Instant::now(),
+        })
+    }
+
+    /// The new shader source if the file changed since the last call and naga accepts it. Parse
+    /// and validation errors are logged, and the caller keeps its current shader.
+    pub fn poll(&mut self) -> Option<String> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+        let modified = std::fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok();
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+
+        let source = match std::fs::read_to_string(&self.path) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("failed to read {}: {err}", self.path.display());
+                return None;
+            }
+        };
+        match validate_shader(&source) {
+            Ok(()) => Some(source),
+            Err(err) => {
+                eprintln!("{err:#}");
+                None
+            }
+        }
+    }
+}
@@ -1,12 +1,10 @@
 
-use std::{num::NonZeroI16, ops::{Add, Mul, Sub}};
-
 use {
     bytemuck::{Pod, Zeroable},
     std::ops,
 };
 
-#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+#[derive(Debug, Copy, Clone, PartialEq, Pod, Zeroable)]
 #[repr(C)]
 pub struct Vec4([f32; 4]);
 
@@ -170,23 +168,665 @@ impl Vec4 {
         self.z() * rhs.z() +
         self.w() * rhs.w()
     }
+    /// The cross product of the first three components, as a direction with w of 0.
     pub fn cross(&self, rhs: &Vec4) -> Vec4 {
-    Vec4([
-        self.y() * rhs.z() - self.z() * rhs.y(),
-        self.z() * rhs.x() - self.x() * rhs.z(),
-        self.x() * rhs.y() - self.y() * rhs.x(),
-        self.w()
-    ])
+        self.xyz().cross(&rhs.xyz()).extend(0.0)
     }
     pub fn normalized(self) -> Vec4 {
         self * self.length().recip()
     }
+
+    /// Component-wise minimum.
+    pub fn min(&self, rhs: &Vec4) -> Vec4 {
+        Vec4([
+            self.x().min(rhs.x()),
+            self.y().min(rhs.y()),
+            self.z().min(rhs.z()),
+            self.w().min(rhs.w()),
+        ])
+    }
+
+    /// Component-wise maximum.
+    pub fn max(&self, rhs: &Vec4) -> Vec4 {
+        Vec4([
+            self.x().max(rhs.x()),
+            self.y().max(rhs.y()),
+            self.z().max(rhs.z()),
+            self.w().max(rhs.w()),
+        ])
+    }
+
+    /// Component by index, 0 to 3 for x to w.
+    pub fn get(&self, index: usize) -> f32 {
+        self.0[index]
+    }
+
+    /// The first three components.
+    pub fn xyz(&self) -> Vec3 {
+        Vec3([self.x(), self.y(), self.z()])
+    }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct Vec3([f32; 3]);
 
+impl Default for Vec3 {
+    fn default() -> Self {
+        Self::zero()
+    }
+}
 
+impl From<[f32; 3]> for Vec3 {
+    fn from(v: [f32; 3]) -> Self {
+        Vec3(v)
+    }
+}
 
-#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+impl From<Vec3> for [f32; 3] {
+    fn from(v: Vec3) -> Self {
+        v.0
+    }
+}
+
+impl_binary_op!(Add : add => (lhs: Vec3, rhs: Vec3) -> Vec3 {
+    Vec3([lhs.x() + rhs.x(), lhs.y() + rhs.y(), lhs.z() + rhs.z()])
+});
+
+impl_binary_op!(Sub : sub => (lhs: Vec3, rhs: Vec3) -> Vec3 {
+    Vec3([lhs.x() - rhs.x(), lhs.y() - rhs.y(), lhs.z() - rhs.z()])
+});
+
+// Component-wise, as for colors.
+impl_binary_op!(Mul : mul => (lhs: Vec3, rhs: Vec3) -> Vec3 {
+    Vec3([lhs.x() * rhs.x(), lhs.y() * rhs.y(), lhs.z() * rhs.z()])
+});
+
+impl_binary_op!(Mul : mul => (lhs: Vec3, rhs: f32) -> Vec3 {
+    Vec3([lhs.x() * rhs, lhs.y() * rhs, lhs.z() * rhs])
+});
+
+impl_binary_op!(Mul : mul => (lhs: f32, rhs: Vec3) -> Vec3 {
+    Vec3([rhs.x() * lhs, rhs.y() * lhs, rhs.z() * lhs])
+});
+
+impl_binary_op!(Div : div => (lhs: Vec3, rhs: f32) -> Vec3 {
+    Vec3([lhs.x() / rhs, lhs.y() / rhs, lhs.z() / rhs])
+});
+
+impl ops::AddAssign for Vec3 {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl ops::SubAssign for Vec3 {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl ops::MulAssign<f32> for Vec3 {
+    fn mul_assign(&mut self, rhs: f32) {
+        *self = *self * rhs;
+    }
+}
+
+impl ops::DivAssign<f32> for Vec3 {
+    fn div_assign(&mut self, rhs: f32) {
+        *self = *self / rhs;
+    }
+}
+
+impl ops::Neg for Vec3 {
+    type Output = Vec3;
+    fn neg(self) -> Self::Output {
+        Vec3([-self.x(), -self.y(), -self.z()])
+    }
+}
+
+impl Vec3 {
+    pub fn new(x: f32, y: f32, z: f32) -> Vec3 {
+        Vec3([x, y, z])
+    }
+
+    pub fn all(v: f32) -> Vec3 {
+        Vec3([v, v, v])
+    }
+
+    pub fn zero() -> Vec3 {
+        Vec3([0., 0., 0.])
+    }
+
+    #[inline(always)]
+    pub fn x(&self) -> f32 { self.0[0] }
+    #[inline(always)]
+    pub fn y(&self) -> f32 { self.0[1] }
+    #[inline(always)]
+    pub fn z(&self) -> f32 { self.0[2] }
+
+    pub fn length(&self) -> f32 {
+        self.length_squared().sqrt()
+    }
+
+    pub fn length_squared(&self) -> f32 {
+        self.dot(self)
+    }
+
+    pub fn dot(&self, rhs: &Vec3) -> f32 {
+        self.x() * rhs.x() + self.y() * rhs.y() + self.z() * rhs.z()
+    }
+
+    pub fn cross(&self, rhs: &Vec3) -> Vec3 {
+        Vec3([
+            self.y() * rhs.z() - self.z() * rhs.y(),
+            self.z() * rhs.x() - self.x() * rhs.z(),
+            self.x() * rhs.y() - self.y() * rhs.x(),
+        ])
+    }
+
+    pub fn normalized(self) -> Vec3 {
+        self * self.length().recip()
+    }
+
+    /// Component-wise minimum.
+    pub fn min(&self, rhs: &Vec3) -> Vec3 {
+        Vec3([self.x().min(rhs.x()), self.y().min(rhs.y()), self.z().min(rhs.z())])
+    }
+
+    /// Component-wise maximum.
+    pub fn max(&self, rhs: &Vec3) -> Vec3 {
+        Vec3([self.x().max(rhs.x()), self.y().max(rhs.y()), self.z().max(rhs.z())])
+    }
+
+    /// Component by index, 0 to 2 for x to z.
+    pub fn get(&self, index: usize) -> f32 {
+        self.0[index]
+    }
+
+    /// This vector with `w` appended.
+    pub fn extend(&self, w: f32) -> Vec4 {
+        Vec4([self.x(), self.y(), self.z(), w])
+    }
+}
+
+/// A 3x3 matrix stored as columns. Unlike `Mat4` it does not match the padded layout of WGSL's
+/// `mat3x3f`, so upload linear maps as a `Mat4`.
+#[derive(Debug, Copy, Clone, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct Mat3([Vec3; 3]);
+
+impl Default for Mat3 {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl_binary_op!(Add : add => (lhs: Mat3, rhs: Mat3) -> Mat3 {
+    Mat3(std::array::from_fn(|i| lhs.0[i] + rhs.0[i]))
+});
+
+impl_binary_op!(Sub : sub => (lhs: Mat3, rhs: Mat3) -> Mat3 {
+    Mat3(std::array::from_fn(|i| lhs.0[i] - rhs.0[i]))
+});
+
+impl_binary_op!(Mul : mul => (lhs: Mat3, rhs: f32) -> Mat3 {
+    Mat3(lhs.0.map(|column| column * rhs))
+});
+
+impl_binary_op!(Mul : mul => (lhs: Mat3, rhs: Vec3) -> Vec3 {
+    lhs.0[0] * rhs.x() + lhs.0[1] * rhs.y() + lhs.0[2] * rhs.z()
+});
+
+impl_binary_op!(Mul : mul => (lhs: Mat3, rhs: Mat3) -> Mat3 {
+    Mat3(rhs.0.map(|column| lhs * column))
+});
+
+impl Mat3 {
+    pub fn from_cols(x: Vec3, y: Vec3, z: Vec3) -> Mat3 {
+        Mat3([x, y, z])
+    }
+
+    pub fn identity() -> Mat3 {
+        Mat3([
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ])
+    }
+
+    pub fn col(&self, index: usize) -> Vec3 {
+        self.0[index]
+    }
+
+    pub fn transpose(&self) -> Mat3 {
+        let m = &self.0;
+        Mat3(std::array::from_fn(|i| Vec3::new(m[0].get(i), m[1].get(i), m[2].get(i))))
+    }
+
+    pub fn determinant(&self) -> f32 {
+        let [x, y, z] = &self.0;
+        x.dot(&y.cross(z))
+    }
+
+    /// The inverse, whose rows are the cross products of pairs of columns over the
+    /// determinant, or `None` if the matrix is singular.
+    pub fn inverse(&self) -> Option<Mat3> {
+        let [x, y, z] = &self.0;
+        let det = self.determinant();
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+        Some(Mat3([y.cross(z) / det, z.cross(x) / det, x.cross(y) / det]).transpose())
+    }
+
+    /// This matrix in the upper left of an otherwise identity `Mat4`.
+    pub fn to_mat4(&self) -> Mat4 {
+        let [x, y, z] = self.0;
+        Mat4([x.extend(0.0), y.extend(0.0), z.extend(0.0), Vec4::new(0.0, 0.0, 0.0, 1.0)])
+    }
+}
+
+/// A 4x4 matrix stored as columns, like `mat4x4f` in WGSL, so it can be uploaded as is.
+#[derive(Debug, Copy, Clone, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct Mat4([Vec4; 4]);
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl_binary_op!(Add : add => (lhs: Mat4, rhs: Mat4) -> Mat4 {
+    Mat4(std::array::from_fn(|i| lhs.0[i] + rhs.0[i]))
+});
+
+impl_binary_op!(Sub : sub => (lhs: Mat4, rhs: Mat4) -> Mat4 {
+    Mat4(std::array::from_fn(|i| lhs.0[i] - rhs.0[i]))
+});
+
+impl_binary_op!(Mul : mul => (lhs: Mat4, rhs: f32) -> Mat4 {
+    Mat4(lhs.0.map(|column| column * rhs))
+});
+
+impl_binary_op!(Mul : mul => (lhs: Mat4, rhs: Vec4) -> Vec4 {
+    lhs.0[0] * rhs.x() + lhs.0[1] * rhs.y() + lhs.0[2] * rhs.z() + lhs.0[3] * rhs.w()
+});
+
+impl_binary_op!(Mul : mul => (lhs: Mat4, rhs: Mat4) -> Mat4 {
+    Mat4(rhs.0.map(|column| lhs * column))
+});
+
+impl Mat4 {
+    pub fn from_cols(x: Vec4, y: Vec4, z: Vec4, w: Vec4) -> Mat4 {
+        Mat4([x, y, z, w])
+    }
+
+    pub fn identity() -> Mat4 {
+        Mat4([
+            Vec4::new(1.0, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, 1.0, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, 1.0, 0.0),
+            Vec4::new(0.0, 0.0, 0.0, 1.0),
+        ])
+    }
+
+    pub fn col(&self, index: usize) -> Vec4 {
+        self.0[index]
+    }
+
+    pub fn from_translation(translation: Vec3) -> Mat4 {
+        Mat4([
+            Vec4::new(1.0, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, 1.0, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, 1.0, 0.0),
+            translation.extend(1.0),
+        ])
+    }
+
+    /// `point` with w taken as 1, so that translations apply.
+    pub fn transform_point(&self, point: Vec3) -> Vec3 {
+        (self * point.extend(1.0)).xyz()
+    }
+
+    /// `vector` with w taken as 0, so that translations do not apply.
+    pub fn transform_vector(&self, vector: Vec3) -> Vec3 {
+        (self * vector.extend(0.0)).xyz()
+    }
+
+    /// The upper left 3x3 matrix, which maps vectors.
+    pub fn to_mat3(&self) -> Mat3 {
+        Mat3([self.0[0].xyz(), self.0[1].xyz(), self.0[2].xyz()])
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let m = &self.0;
+        Mat4(std::array::from_fn(|i| {
+            Vec4::new(m[0].get(i), m[1].get(i), m[2].get(i), m[3].get(i))
+        }))
+    }
+
+    pub fn determinant(&self) -> f32 {
+        self.adjugate().1
+    }
+
+    /// The inverse by cofactors, or `None` if the matrix is singular.
+    pub fn inverse(&self) -> Option<Mat4> {
+        let (adjugate, det) = self.adjugate();
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+        Some(bytemuck::cast(adjugate.map(|value| value / det)))
+    }
+
+    /// The transposed matrix of cofactors, as a flat array of columns, and the determinant.
+    fn adjugate(&self) -> ([f32; 16], f32) {
+        let m: [f32; 16] = bytemuck::cast(*self);
+        let mut inv = [0.0; 16];
+        inv[0] = m[5] * m[10] * m[15] - m[5] * m[11] * m[14] - m[9] * m[6] * m[15]
+            + m[9] * m[7] * m[14] + m[13] * m[6] * m[11] - m[13] * m[7] * m[10];
+        inv[4] = -m[4] * m[10] * m[15] + m[4] * m[11] * m[14] + m[8] * m[6] * m[15]
+            - m[8] * m[7] * m[14] - m[12] * m[6] * m[11] + m[12] * m[7] * m[10];
+        inv[8] = m[4] * m[9] * m[15] - m[4] * m[11] * m[13] - m[8] * m[5] * m[15]
+            + m[8] * m[7] * m[13] + m[12] * m[5] * m[11] - m[12] * m[7] * m[9];
+        inv[12] = -m[4] * m[9] * m[14] + m[4] * m[10] * m[13] + m[8] * m[5] * m[14]
+            - m[8] * m[6] * m[13] - m[12] * m[5] * m[10] + m[12] * m[6] * m[9];
+        inv[1] = -m[1] * m[10] * m[15] + m[1] * m[11] * m[14] + m[9] * m[2] * m[15]
+            - m[9] * m[3] * m[14] - m[13] * m[2] * m[11] + m[13] * m[3] * m[10];
+        inv[5] = m[0] * m[10] * m[15] - m[0] * m[11] * m[14] - m[8] * m[2] * m[15]
+            + m[8] * m[3] * m[14] + m[12] * m[2] * m[11] - m[12] * m[3] * m[10];
+        inv[9] = -m[0] * m[9] * m[15] + m[0] * m[11] * m[13] + m[8] * m[1] * m[15]
+            - m[8] * m[3] * m[13] - m[12] * m[1] * m[11] + m[12] * m[3] * m[9];
+        inv[13] = m[0] * m[9] * m[14] - m[0] * m[10] * m[13] - m[8] * m[1] * m[14]
+            + m[8] * m[2] * m[13] + m[12] * m[1] * m[10] - m[12] * m[2] * m[9];
+        inv[2] = m[1] * m[6] * m[15] - m[1] * m[7] * m[14] - m[5] * m[2] * m[15]
+            + m[5] * m[3] * m[14] + m[13] * m[2] * m[7] - m[13] * m[3] * m[6];
+        inv[6] = -m[0] * m[6] * m[15] + m[0] * m[7] * m[14] + m[4] * m[2] * m[15]
+            - m[4] * m[3] * m[14] - m[12] * m[2] * m[7] + m[12] * m[3] * m[6];
+        inv[10] = m[0] * m[5] * m[15] - m[0] * m[7] * m[13] - m[4] * m[1] * m[15]
+            + m[4] * m[3] * m[13] + m[12] * m[1] * m[7] - m[12] * m[3] * m[5];
+        inv[14] = -m[0] * m[5] * m[14] + m[0] * m[6] * m[13] + m[4] * m[1] * m[14]
+            - m[4] * m[2] * m[13] - m[12] * m[1] * m[6] + m[12] * m[2] * m[5];
+        inv[3] = -m[1] * m[6] * m[11] + m[1] * m[7] * m[10] + m[5] * m[2] * m[11]
+            - m[5] * m[3] * m[10] - m[9] * m[2] * m[7] + m[9] * m[3] * m[6];
+        inv[7] = m[0] * m[6] * m[11] - m[0] * m[7] * m[10] - m[4] * m[2] * m[11]
+            + m[4] * m[3] * m[10] + m[8] * m[2] * m[7] - m[8] * m[3] * m[6];
+        inv[11] = -m[0] * m[5] * m[11] + m[0] * m[7] * m[9] + m[4] * m[1] * m[11]
+            - m[4] * m[3] * m[9] - m[8] * m[1] * m[7] + m[8] * m[3] * m[5];
+        inv[15] = m[0] * m[5] * m[10] - m[0] * m[6] * m[9] - m[4] * m[1] * m[10]
+            + m[4] * m[2] * m[9] + m[8] * m[1] * m[6] - m[8] * m[2] * m[5];
+        let det = m[0] * inv[0] + m[1] * inv[4] + m[2] * inv[8] + m[3] * inv[12];
+        (inv, det)
+    }
+}
+
+/// A rotation as a unit quaternion, stored as x, y, z and then w.
+#[derive(Debug, Copy, Clone, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct Quat([f32; 4]);
+
+impl Default for Quat {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl_binary_op!(Mul : mul => (lhs: Quat, rhs: Quat) -> Quat {
+    let [ax, ay, az, aw] = lhs.0;
+    let [bx, by, bz, bw] = rhs.0;
+    Quat([
+        aw * bx + ax * bw + ay * bz - az * by,
+        aw * by - ax * bz + ay * bw + az * bx,
+        aw * bz + ax * by - ay * bx + az * bw,
+        aw * bw - ax * bx - ay * by - az * bz,
+    ])
+});
+
+impl_binary_op!(Mul : mul => (lhs: Quat, rhs: Vec3) -> Vec3 {
+    lhs.rotate(Vec3(rhs.0))
+});
+
+impl Quat {
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Quat {
+        Quat([x, y, z, w])
+    }
+
+    pub fn identity() -> Quat {
+        Quat([0.0, 0.0, 0.0, 1.0])
+    }
+
+    /// Rotation by `angle` radians about `axis`, counterclockwise looking down the axis.
+    pub fn from_axis_angle(axis: Vec3, angle: f32) -> Quat {
+        let axis = axis.normalized();
+        let (sin, cos) = (angle / 2.0).sin_cos();
+        Quat([axis.x() * sin, axis.y() * sin, axis.z() * sin, cos])
+    }
+
+    /// Rotation about the x axis, then the y axis and then the z axis, by angles in radians.
+    pub fn from_euler(x: f32, y: f32, z: f32) -> Quat {
+        Quat::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), z)
+            * Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), y)
+            * Quat::from_axis_angle(Vec3::new(1.0, 0.0, 0.0), x)
+    }
+
+    /// The angles `from_euler` takes to make this rotation, with y within ±90°.
+    pub fn to_euler(&self) -> [f32; 3] {
+        let [x, y, z, w] = self.0;
+        [
+            (2.0 * (w * x + y * z)).atan2(1.0 - 2.0 * (x * x + y * y)),
+            (2.0 * (w * y - z * x)).clamp(-1.0, 1.0).asin(),
+            (2.0 * (w * z + x * y)).atan2(1.0 - 2.0 * (y * y + z * z)),
+        ]
+    }
+
+    pub fn dot(&self, rhs: &Quat) -> f32 {
+        self.0.iter().zip(rhs.0).map(|(a, b)| a * b).sum()
+    }
+
+    pub fn length(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalized(self) -> Quat {
+        let length = self.length();
+        Quat(self.0.map(|v| v / length))
+    }
+
+    /// The opposite rotation, for a unit quaternion.
+    pub fn conjugate(&self) -> Quat {
+        let [x, y, z, w] = self.0;
+        Quat([-x, -y, -z, w])
+    }
+
+    pub fn rotate(&self, v: Vec3) -> Vec3 {
+        let [x, y, z, w] = self.0;
+        let axis = Vec3::new(x, y, z);
+        let t = 2.0 * axis.cross(&v);
+        v + w * t + axis.cross(&t)
+    }
+
+    /// The rotation a fraction `t` of the way from this one to `other` along the shorter arc, at
+    /// constant angular speed.
+    pub fn slerp(&self, other: &Quat, t: f32) -> Quat {
+        // q and -q are the same rotation; flip to the nearer one to take the shorter arc.
+        let (other, cos) = match self.dot(other) {
+            cos if cos < 0.0 => (Quat(other.0.map(|v| -v)), -cos),
+            cos => (*other, cos),
+        };
+        // Nearly equal rotations divide by a vanishing sine; interpolate linearly instead.
+        let (a, b) = if cos > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let angle = cos.acos();
+            let sin = angle.sin();
+            (((1.0 - t) * angle).sin() / sin, (t * angle).sin() / sin)
+        };
+        Quat(std::array::from_fn(|i| a * self.0[i] + b * other.0[i])).normalized()
+    }
+
+    /// The rotation as a matrix.
+    pub fn to_mat3(&self) -> Mat3 {
+        let [x, y, z, w] = self.0;
+        Mat3([
+            Vec3::new(1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y + w * z), 2.0 * (x * z - w * y)),
+            Vec3::new(2.0 * (x * y - w * z), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z + w * x)),
+            Vec3::new(2.0 * (x * z + w * y), 2.0 * (y * z - w * x), 1.0 - 2.0 * (x * x + y * y)),
+        ])
+    }
+
+    pub fn to_mat4(&self) -> Mat4 {
+        self.to_mat3().to_mat4()
+    }
+}
+
+/// Scale, then rotation, then translation, as carried by scene graph nodes.
+#[derive(Debug, Copy, Clone, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    /// Scale along each local axis; components must not be zero.
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform {
+            translation: Vec3::zero(),
+            rotation: Quat::identity(),
+            scale: Vec3::all(1.0),
+        }
+    }
+
+    pub fn to_mat4(&self) -> Mat4 {
+        let [x, y, z] = self.rotation.to_mat3().0;
+        Mat4([
+            (x * self.scale.x()).extend(0.0),
+            (y * self.scale.y()).extend(0.0),
+            (z * self.scale.z()).extend(0.0),
+            self.translation.extend(1.0),
+        ])
+    }
+}
+
+/// A half-line from `origin` along `dir`, which need not be normalized.
+#[derive(Debug, Copy, Clone, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub dir: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, dir: Vec3) -> Ray {
+        Ray { origin, dir }
+    }
+
+    /// The point `t` lengths of `dir` along the ray.
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + t * self.dir
+    }
+
+    /// The ray in the space `transform` maps to. Distances in units of `dir` carry over.
+    pub fn transformed(&self, transform: &Mat4) -> Ray {
+        Ray {
+            origin: transform.transform_point(self.origin),
+            dir: transform.transform_vector(self.dir),
+        }
+    }
+}
+
+/// An axis-aligned bounding box.
+#[derive(Debug, Copy, Clone, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// A box containing nothing, which any union replaces.
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3::all(f32::INFINITY),
+            max: Vec3::all(f32::NEG_INFINITY),
+        }
+    }
+
+    /// The smallest box containing `point`.
+    pub fn from_point(point: Vec3) -> Aabb {
+        Aabb { min: point, max: point }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x() > self.max.x() || self.min.y() > self.max.y() || self.min.z() > self.max.z()
+    }
+
+    pub fn contains(&self, point: Vec3) -> bool {
+        (0..3).all(|axis| self.min.get(axis) <= point.get(axis) && point.get(axis) <= self.max.get(axis))
+    }
+
+    /// The smallest box containing both.
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(&other.min),
+            max: self.max.max(&other.max),
+        }
+    }
+
+    /// The box both contain, which is empty if they do not overlap.
+    pub fn intersection(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.max(&other.min),
+            max: self.max.min(&other.max),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+
+    /// The box around this one after `transform`, which contains its eight transformed corners.
+    pub fn transformed(&self, transform: &Mat4) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        (0..8).fold(Aabb::empty(), |aabb, corner| {
+            let pick = |bit: usize, axis: usize| {
+                if corner & bit == 0 { self.min.get(axis) } else { self.max.get(axis) }
+            };
+            let point = transform.transform_point(Vec3::new(pick(1, 0), pick(2, 1), pick(4, 2)));
+            aabb.union(&Aabb::from_point(point))
+        })
+    }
+
+    /// The range of `t` where `ray.at(t)` lies in the box within `0..=limit`, by the slab
+    /// method, or `None` if the ray misses it there.
+    pub fn ray_interval(&self, ray: &Ray, limit: f32) -> Option<(f32, f32)> {
+        let (mut near, mut far) = (0.0f32, limit);
+        for axis in 0..3 {
+            let inv = ray.dir.get(axis).recip();
+            let a = (self.min.get(axis) - ray.origin.get(axis)) * inv;
+            let b = (self.max.get(axis) - ray.origin.get(axis)) * inv;
+            // A ray parallel to the slab and on its edge gives NaN, which min and max skip.
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+        }
+        (near <= far).then_some((near, far))
+    }
+}
+
+
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Pod, Zeroable)]
 #[repr(C)]
 pub struct CameraUniforms {
     origin: Vec4,
@@ -221,55 +861,61 @@ impl Camera {
         &self.uniforms
     }
 
+    pub fn origin(&self) -> Vec4 {
+        self.uniforms.origin
+    }
+
+    pub fn set_origin(&mut self, origin: Vec4) {
+        self.uniforms.origin = origin;
+    }
+
+    /// The direction the camera looks in.
+    pub fn forward(&self) -> Vec4 {
+        self.uniforms.w
+    }
+
+    /// Rotation about the horizontal axis in radians, positive looking down.
+    pub fn pitch(&self) -> f32 {
+        self.pitch
+    }
+
+    /// Rotation about the vertical axis in radians.
+    pub fn yaw(&self) -> f32 {
+        self.yaw
+    }
+
     pub fn look_at(origin: Vec4, center: Vec4, up: Vec4) -> Camera {
         let w = (center - origin).normalized();
         let u = w.cross(&up).normalized();
         let v = u.cross(&w);
         Camera {
             uniforms: CameraUniforms {
-                origin: origin,
-                u: u,
-                v: v,
-                w: w,
+                origin,
+                u,
+                v,
+                w,
             },
             pitch:0.0,
             yaw:0.0
         }
     }
-    pub fn zoom(&mut self, displacement: f32) {
-        self.uniforms.origin += displacement * self.uniforms.w;
-    }
     pub fn moves(&mut self, x:f32, y:f32, z:f32) {
         self.uniforms.w += Vec4::new(x, y, z, 0.0);
     }
     pub fn rotate(&mut self, pitch:f32, yaw:f32){
         self.pitch+=pitch;
         self.yaw+=yaw;
-        let ninety = 90.0/180.0*3.1415926;
-        if(self.pitch > ninety){
+        let ninety = 90.0/180.0*std::f32::consts::PI;
+        if self.pitch > ninety {
             self.pitch = ninety;
         }
-        if(self.pitch < -ninety){
+        if self.pitch < -ninety {
             self.pitch = -ninety;
         }
     }
-    pub fn setrotation(&mut self, pitch:f32, yaw:f32){
-        self.pitch=pitch;
-        self.yaw=yaw;
-        let ninety = 89.0/180.0*3.1415926;
-        if(self.yaw > ninety){
-            self.yaw = ninety;
-        }
-        if(self.yaw < -ninety){
-            self.yaw = -ninety;
-        }
-    }
     pub fn set_w(&mut self){
         self.uniforms.w = Vec4::new((self.pitch).cos()*(self.yaw).sin(),-(self.pitch).sin(),-(self.pitch).cos()*(self.yaw.cos()),0.0);
     }
-    pub fn moveset(&mut self, x:f32, y:f32, z:f32) {
-        self.uniforms.w = Vec4::new(x, y, z, 0.0);
-    }
     pub fn shift(&mut self, x:f32, y:f32, z:f32){
         let fx = x*self.yaw.cos()-z*self.yaw.sin();
         let fz = x*self.yaw.sin()+z*self.yaw.cos();
@@ -278,5 +924,365 @@ impl Camera {
 
 }
 
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        proptest::prelude::*,
+        std::mem::{offset_of, size_of},
+    };
+
+    fn vec3() -> impl Strategy<Value = Vec3> {
+        prop::array::uniform3(-10.0f32..10.0).prop_map(Vec3::from)
+    }
+
+    fn vec4() -> impl Strategy<Value = Vec4> {
+        prop::array::uniform4(-10.0f32..10.0).prop_map(|[x, y, z, w]| Vec4::new(x, y, z, w))
+    }
+
+    fn mat3() -> impl Strategy<Value = Mat3> {
+        prop::array::uniform3(vec3()).prop_map(|[x, y, z]| Mat3::from_cols(x, y, z))
+    }
+
+    fn mat4() -> impl Strategy<Value = Mat4> {
+        prop::array::uniform4(vec4()).prop_map(|[x, y, z, w]| Mat4::from_cols(x, y, z, w))
+    }
+
+    fn unit_quat() -> impl Strategy<Value = Quat> {
+        prop::array::uniform4(-1.0f32..1.0)
+            .prop_filter("too short to normalize", |q| q.iter().map(|v| v * v).sum::<f32>() > 0.01)
+            .prop_map(|[x, y, z, w]| Quat::new(x, y, z, w).normalized())
+    }
+
+    /// Transforms with scales kept away from zero, so that their matrices invert accurately.
+    fn transform() -> impl Strategy<Value = Transform> {
+        let scale = prop_oneof![0.2f32..5.0, -5.0f32..-0.2];
+        (vec3(), unit_quat(), prop::array::uniform3(scale)).prop_map(|(translation, rotation, scale)| {
+            Transform { translation, rotation, scale: scale.into() }
+        })
+    }
+
+    fn aabb() -> impl Strategy<Value = Aabb> {
+        (vec3(), vec3()).prop_map(|(a, b)| Aabb { min: a.min(&b), max: a.max(&b) })
+    }
+
+    /// Whether `a` and `b` differ by at most `tolerance` relative to their size.
+    fn near(a: Vec3, b: Vec3, tolerance: f32) -> bool {
+        (a - b).length() <= tolerance * (1.0 + a.length().max(b.length()))
+    }
+
+    fn near_mat4(a: &Mat4, b: &Mat4, tolerance: f32) -> bool {
+        (0..4).all(|i| (a.col(i) - b.col(i)).length() <= tolerance * (1.0 + a.col(i).length().max(b.col(i).length())))
+    }
+
+    /// Whether `a` and `b` are the same rotation, which `q` and `-q` both are.
+    fn same_rotation(a: &Quat, b: &Quat, tolerance: f32) -> bool {
+        a.dot(b).abs() >= 1.0 - tolerance
+    }
+
+    #[test]
+    fn math_types_are_packed() {
+        assert_eq!(size_of::<Vec3>(), 12);
+        assert_eq!(size_of::<Mat3>(), 36);
+        assert_eq!(size_of::<Mat4>(), 64);
+        assert_eq!(size_of::<Quat>(), 16);
+        assert_eq!(size_of::<Ray>(), 24);
+        assert_eq!(size_of::<Aabb>(), 24);
+        assert_eq!(size_of::<Transform>(), 40);
+        let m: [f32; 16] = bytemuck::cast(Mat4::from_translation(Vec3::new(1.0, 2.0, 3.0)));
+        assert_eq!(m[12..], [1.0, 2.0, 3.0, 1.0]);
+    }
+
+    proptest! {
+        #[test]
+        fn vec3_addition_is_a_group(a in vec3(), b in vec3(), c in vec3()) {
+            prop_assert_eq!(a + b, b + a);
+            prop_assert!(near((a + b) + c, a + (b + c), 1e-6));
+            prop_assert_eq!(a + Vec3::zero(), a);
+            prop_assert_eq!(a + -a, Vec3::zero());
+            prop_assert_eq!(a - b, a + -b);
+            let mut sum = a;
+            sum += b;
+            sum -= c;
+            prop_assert_eq!(sum, a + b - c);
+        }
+
+        #[test]
+        fn vec3_scaling_distributes(a in vec3(), b in vec3(), s in -10.0f32..10.0, t in 0.1f32..10.0) {
+            prop_assert!(near(s * (a + b), s * a + s * b, 1e-5));
+            prop_assert_eq!(s * a, a * s);
+            prop_assert!(near(a / t, a * t.recip(), 1e-6));
+            prop_assert!(((s * a).length() - s.abs() * a.length()).abs() <= 1e-4 * (1.0 + a.length()));
+            let mut scaled = a;
+            scaled *= s;
+            scaled /= t;
+            prop_assert_eq!(scaled, a * s / t);
+            prop_assert_eq!((a * b).x(), a.x() * b.x());
+        }
+
+        #[test]
+        fn vec3_dot_and_cross(a in vec3(), b in vec3()) {
+            let scale = a.length() * b.length();
+            let cross = a.cross(&b);
+            prop_assert_eq!(a.dot(&b), b.dot(&a));
+            prop_assert_eq!(cross, -b.cross(&a));
+            prop_assert!(cross.dot(&a).abs() <= 1e-4 * scale * (1.0 + a.length()));
+            prop_assert!(cross.dot(&b).abs() <= 1e-4 * scale * (1.0 + b.length()));
+            // Lagrange's identity.
+            let lagrange = cross.length_squared() + a.dot(&b).powi(2);
+            prop_assert!((lagrange - scale * scale).abs() <= 1e-4 * (1.0 + scale * scale));
+        }
+
+        #[test]
+        fn vec3_normalized_has_unit_length(a in vec3()) {
+            prop_assume!(a.length() > 1e-3);
+            prop_assert!((a.normalized().length() - 1.0).abs() < 1e-5);
+            prop_assert!(near(a.normalized() * a.length(), a, 1e-5));
+        }
+
+        #[test]
+        fn vec3_min_max_bound_both(a in vec3(), b in vec3()) {
+            let (min, max) = (a.min(&b), a.max(&b));
+            for axis in 0..3 {
+                prop_assert!(min.get(axis) <= a.get(axis).min(b.get(axis)));
+                prop_assert!(max.get(axis) >= a.get(axis).max(b.get(axis)));
+            }
+            prop_assert_eq!(min + max, a + b);
+        }
+
+        #[test]
+        fn vec4_cross_is_a_direction(a in vec4(), b in vec4()) {
+            let cross = a.cross(&b);
+            prop_assert_eq!(cross.w(), 0.0);
+            prop_assert_eq!(cross.xyz(), a.xyz().cross(&b.xyz()));
+            prop_assert_eq!(a.xyz().extend(a.w()), a);
+        }
+
+        #[test]
+        fn mat3_transpose(a in mat3(), b in mat3(), v in vec3(), u in vec3()) {
+            prop_assert_eq!(a.transpose().transpose(), a);
+            let (ab, ba) = ((a * b).transpose(), b.transpose() * a.transpose());
+            prop_assert!((0..3).all(|i| near(ab.col(i), ba.col(i), 1e-5)));
+            // <u, Av> = <A^T u, v>, up to rounding in terms as large as |u| |A| |v|.
+            let (left, right) = (u.dot(&(a * v)), (a.transpose() * u).dot(&v));
+            let size = u.length() * v.length() * (0..3).map(|i| a.col(i).length()).sum::<f32>();
+            prop_assert!((left - right).abs() <= 1e-5 * (1.0 + size));
+        }
+
+        #[test]
+        fn mat3_inverse(t in transform(), v in vec3()) {
+            let m = t.to_mat4().to_mat3();
+            let inverse = m.inverse().unwrap();
+            prop_assert!(near(inverse * (m * v), v, 1e-4));
+            prop_assert!(near(m * (inverse * v), v, 1e-4));
+            prop_assert!((m.determinant() * inverse.determinant() - 1.0).abs() < 1e-3);
+        }
+
+        #[test]
+        fn mat3_linear(a in mat3(), b in mat3(), u in vec3(), v in vec3(), s in -10.0f32..10.0) {
+            prop_assert!(near(a * (u + v), a * u + a * v, 1e-4));
+            prop_assert!(near(a * (u * s), (a * s) * u, 1e-4));
+            prop_assert!(near((a + b) * u, a * u + b * u, 1e-4));
+            prop_assert!(near((a - b) * u, a * u - b * u, 1e-4));
+            prop_assert!(near((a * b) * u, a * (b * u), 1e-4));
+            prop_assert_eq!(Mat3::identity() * u, u);
+        }
+
+        #[test]
+        fn mat3_determinant_is_multiplicative(a in mat3(), b in mat3()) {
+            // Rounding scales with the product of column lengths, which bounds the determinant.
+            let size = |m: &Mat3| (0..3).map(|i| m.col(i).length()).product::<f32>();
+            let (product, separate) = ((a * b).determinant(), a.determinant() * b.determinant());
+            prop_assert!((product - separate).abs() <= 1e-5 * (1.0 + size(&a) * size(&b)));
+            prop_assert!((a.transpose().determinant() - a.determinant()).abs() <= 1e-5 * (1.0 + size(&a)));
+        }
+
+        #[test]
+        fn mat4_transpose(a in mat4(), b in mat4()) {
+            prop_assert_eq!(a.transpose().transpose(), a);
+            prop_assert!(near_mat4(&(a * b).transpose(), &(b.transpose() * a.transpose()), 1e-5));
+            prop_assert_eq!(a.transpose().to_mat3(), a.to_mat3().transpose());
+        }
+
+        #[test]
+        fn mat4_inverse(a in transform(), b in transform(), p in vec3()) {
+            let (m, n) = (a.to_mat4(), b.to_mat4());
+            let inverse = m.inverse().unwrap();
+            prop_assert!(near_mat4(&(m * inverse), &Mat4::identity(), 1e-4));
+            prop_assert!(near_mat4(&(inverse * m), &Mat4::identity(), 1e-4));
+            prop_assert!(near(inverse.transform_point(m.transform_point(p)), p, 1e-4));
+            prop_assert!(near_mat4(&(m * n).inverse().unwrap(), &(n.inverse().unwrap() * inverse), 1e-3));
+            prop_assert!(near_mat4(&inverse.transpose(), &m.transpose().inverse().unwrap(), 1e-4));
+            prop_assert!((m.determinant() - m.to_mat3().determinant()).abs() <= 1e-3 * (1.0 + m.determinant().abs()));
+        }
+
+        #[test]
+        fn mat4_linear(a in mat4(), b in mat4(), v in vec4(), s in -10.0f32..10.0) {
+            let close = |x: Vec4, y: Vec4| (x - y).length() <= 1e-4 * (1.0 + x.length().max(y.length()));
+            prop_assert!(close((a + b) * v, a * v + b * v));
+            prop_assert!(close((a - b) * v, a * v - b * v));
+            prop_assert!(close((a * s) * v, a * v * s));
+            prop_assert!(close((a * b) * v, a * (b * v)));
+            prop_assert_eq!(Mat4::identity() * v, v);
+        }
+
+        #[test]
+        fn mat4_points_translate_and_vectors_do_not(t in vec3(), a in mat3(), p in vec3()) {
+            let m = Mat4::from_translation(t) * a.to_mat4();
+            prop_assert!(near(m.transform_point(p), a * p + t, 1e-5));
+            prop_assert!(near(m.transform_vector(p), a * p, 1e-5));
+        }
+
+        #[test]
+        fn singular_matrices_do_not_invert(a in vec3(), b in vec3(), s in -2.0f32..2.0) {
+            // The third column depends on the others.
+            let m = Mat3::from_cols(a, b, a * s);
+            prop_assert!(m.determinant().abs() <= 1e-3 * (1.0 + a.length() * a.length() * b.length()));
+            prop_assert!(Mat4::from_cols(Vec4::zero(), a.extend(0.0), b.extend(0.0), Vec4::zero()).inverse().is_none());
+        }
+
+        #[test]
+        fn quat_rotation_preserves_lengths_and_angles(q in unit_quat(), u in vec3(), v in vec3()) {
+            let (ru, rv) = (q * u, q.rotate(v));
+            prop_assert!((ru.length() - u.length()).abs() <= 1e-4 * (1.0 + u.length()));
+            prop_assert!((ru.dot(&rv) - u.dot(&v)).abs() <= 1e-3 * (1.0 + u.length() * v.length()));
+            prop_assert!(near(ru.cross(&rv), q * u.cross(&v), 1e-3));
+            prop_assert!(near(q.to_mat3() * u, ru, 1e-5));
+            prop_assert!(near(q.conjugate() * ru, u, 1e-4));
+            prop_assert!((q.to_mat3().determinant() - 1.0).abs() < 1e-4);
+        }
+
+        #[test]
+        fn quat_product_composes(a in unit_quat(), b in unit_quat(), v in vec3()) {
+            prop_assert!(near((a * b) * v, a * (b * v), 1e-4));
+            prop_assert!(near_mat4(&(a * b).to_mat4(), &(a.to_mat4() * b.to_mat4()), 1e-5));
+            prop_assert!(same_rotation(&(a * a.conjugate()), &Quat::identity(), 1e-5));
+            prop_assert!(((a * b).length() - 1.0).abs() < 1e-5);
+        }
+
+        #[test]
+        fn quat_axis_angle(axis in vec3(), angle in -3.0f32..3.0, v in vec3()) {
+            prop_assume!(axis.length() > 0.1);
+            let q = Quat::from_axis_angle(axis, angle);
+            let unit = axis.normalized();
+            prop_assert!(near(q * axis, axis, 1e-4));
+            // Rodrigues' rotation formula.
+            let (sin, cos) = angle.sin_cos();
+            let expected = v * cos + unit.cross(&v) * sin + unit * unit.dot(&v) * (1.0 - cos);
+            prop_assert!(near(q * v, expected, 1e-4));
+        }
 
+        #[test]
+        fn quat_euler_round_trip(x in -3.1f32..3.1, y in -1.5f32..1.5, z in -3.1f32..3.1) {
+            let q = Quat::from_euler(x, y, z);
+            let [ex, ey, ez] = q.to_euler();
+            prop_assert!(same_rotation(&Quat::from_euler(ex, ey, ez), &q, 1e-5));
+            prop_assert!((ey - y).abs() < 1e-2);
+        }
+
+        #[test]
+        fn quat_slerp(a in unit_quat(), b in unit_quat(), t in 0.0f32..1.0) {
+            prop_assert!(same_rotation(&a.slerp(&b, 0.0), &a, 1e-5));
+            prop_assert!(same_rotation(&a.slerp(&b, 1.0), &b, 1e-5));
+            prop_assert!(same_rotation(&a.slerp(&a, t), &a, 1e-5));
+            let between = a.slerp(&b, t);
+            prop_assert!((between.length() - 1.0).abs() < 1e-5);
+            // Constant angular speed along the shorter arc: the angles to the ends split the
+            // whole in proportion to t.
+            let angle = |p: &Quat, q: &Quat| 2.0 * p.dot(q).abs().min(1.0).acos();
+            let whole = angle(&a, &b);
+            prop_assert!((angle(&a, &between) - t * whole).abs() < 2e-2);
+            prop_assert!((angle(&between, &b) - (1.0 - t) * whole).abs() < 2e-2);
+            prop_assert!(whole <= std::f32::consts::PI + 1e-3);
+        }
+
+        #[test]
+        fn transform_matrix_applies_scale_rotation_translation(t in transform(), p in vec3()) {
+            let expected = t.rotation * (t.scale * p) + t.translation;
+            prop_assert!(near(t.to_mat4().transform_point(p), expected, 1e-5));
+        }
+
+        #[test]
+        fn ray_at_is_linear(origin in vec3(), dir in vec3(), s in -10.0f32..10.0, t in -10.0f32..10.0) {
+            let ray = Ray::new(origin, dir);
+            prop_assert_eq!(ray.at(0.0), origin);
+            prop_assert!(near(ray.at(s) - ray.at(t), dir * (s - t), 1e-5));
+        }
+
+        #[test]
+        fn ray_transform_keeps_parameters(origin in vec3(), dir in vec3(), m in transform(), t in -10.0f32..10.0) {
+            let (ray, m) = (Ray::new(origin, dir), m.to_mat4());
+            prop_assert!(near(ray.transformed(&m).at(t), m.transform_point(ray.at(t)), 1e-4));
+        }
+
+        #[test]
+        fn aabb_union_contains_both(a in aabb(), b in aabb(), p in vec3()) {
+            let union = a.union(&b);
+            for corner in [a.min, a.max, b.min, b.max] {
+                prop_assert!(union.contains(corner));
+            }
+            prop_assert_eq!(a.union(&Aabb::empty()), a);
+            prop_assert!(Aabb::empty().is_empty() && !Aabb::from_point(p).is_empty());
+            prop_assert!(a.union(&Aabb::from_point(p)).contains(p));
+        }
 
+        #[test]
+        fn aabb_intersection_is_contained_in_both(a in aabb(), b in aabb(), p in vec3()) {
+            let intersection = a.intersection(&b);
+            prop_assert_eq!(intersection.contains(p), a.contains(p) && b.contains(p));
+            if !intersection.is_empty() {
+                prop_assert!(a.contains(intersection.min) && a.contains(intersection.max));
+                prop_assert!(b.contains(intersection.min) && b.contains(intersection.max));
+            }
+            prop_assert!(a.intersection(&Aabb::empty()).is_empty());
+            prop_assert_eq!(a.intersection(&a.union(&b)), a);
+        }
+
+        #[test]
+        fn aabb_transformed_contains_points(a in aabb(), m in transform(), s in prop::array::uniform3(0.0f32..1.0)) {
+            // A point inside the box, placed by fractions along each axis.
+            let p = Vec3::new(
+                a.min.x() + (a.max.x() - a.min.x()) * s[0],
+                a.min.y() + (a.max.y() - a.min.y()) * s[1],
+                a.min.z() + (a.max.z() - a.min.z()) * s[2],
+            );
+            let m = m.to_mat4();
+            let moved = a.transformed(&m);
+            let q = m.transform_point(p);
+            let slack = Aabb { min: moved.min - Vec3::all(1e-3), max: moved.max + Vec3::all(1e-3) };
+            prop_assert!(slack.contains(q), "{q:?} outside {moved:?}");
+            prop_assert!(near(moved.center(), m.transform_point(a.center()), 1e-4));
+        }
+
+        #[test]
+        fn aabb_ray_interval_lies_in_the_box(a in aabb(), origin in vec3(), dir in vec3()) {
+            prop_assume!(dir.length() > 1e-3);
+            let ray = Ray::new(origin, dir);
+            let slack = Aabb { min: a.min - Vec3::all(1e-3), max: a.max + Vec3::all(1e-3) };
+            match a.ray_interval(&ray, f32::INFINITY) {
+                Some((near, far)) => {
+                    prop_assert!(0.0 <= near && near <= far);
+                    prop_assert!(slack.contains(ray.at(near)) && slack.contains(ray.at((near + far) / 2.0)));
+                }
+                None => prop_assert!(!a.contains(origin)),
+            }
+            // A ray aimed at the center always hits.
+            let aimed = Ray::new(origin, a.center() - origin);
+            prop_assert!(a.ray_interval(&aimed, f32::INFINITY).is_some());
+        }
+    }
+
+    #[test]
+    fn camera_uniforms_layout() {
+        crate::renderer::tests::assert_matches_shader(
+            "CameraUniforms",
+            size_of::<CameraUniforms>(),
+            &[
+                ("origin", offset_of!(CameraUniforms, origin)),
+                ("u", offset_of!(CameraUniforms, u)),
+                ("v", offset_of!(CameraUniforms, v)),
+                ("w", offset_of!(CameraUniforms, w)),
+            ],
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use {
+    anyhow::Result,
+    bytemuck::{Pod, Zeroable},
+    std::fmt,
+    wgpu::{self, PipelineCompilationOptions},
+};
+
+use super::{output_constants, scene::Scene, storage_buffer_entry};
+
+/// Longest path that gets logged; further bounces are dropped.
+const MAX_VERTICES: u32 = 128;
+
+/// Mirrors `PathRecord` in shaders.wgsl.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct PathRecord {
+    position: [f32; 3],
+    kind: u32,
+    normal: [f32; 3],
+    primitive: i32,
+    direction: [f32; 3],
+    pdf: f32,
+    throughput: [f32; 3],
+    material: i32,
+}
+
+/// Byte offset of the records in `PathLog`, after the count.
+const RECORDS_OFFSET: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PathEvent {
+    /// The path scattered off a surface.
+    Surface,
+    /// The path left the scene; the position is one unit along the escaping ray.
+    Escaped,
+    /// The path ended on an emitter.
+    Emitter,
+}
+
+#[derive(Debug, Clone)]
+pub struct PathVertex {
+    pub event: PathEvent,
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub object: Option<String>,
+    pub material: Option<String>,
+    /// Outgoing direction, or the ray direction for an escaped path.
+    pub direction: [f32; 3],
+    /// Density of the sampled direction. `None` for specular bounces and where the path ended.
+    pub pdf: Option<f32>,
+    /// Path throughput after this vertex.
+    pub throughput: [f32; 3],
+}
+
+/// The first sample of one pixel, bounce by bounce.
+#[derive(Debug, Clone)]
+pub struct DebugPath {
+    pub pixel: (u32, u32),
+    pub vertices: Vec<PathVertex>,
+}
+
+impl fmt::Display for DebugPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let vec = |v: [f32; 3]| format!("({:.4}, {:.4}, {:.4})", v[0], v[1], v[2]);
+        writeln!(f, "path of pixel {:?}, {} vertices", self.pixel, self.vertices.len())?;
+        for (i, vertex) in self.vertices.iter().enumerate() {
+            let pdf = match (vertex.event, vertex.pdf) {
+                (_, Some(pdf)) => format!("{pdf:.4}"),
+                (PathEvent::Surface, None) => "delta".to_string(),
+                (_, None) => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{i:>3} {:?} {} [{}]",
+                vertex.event,
+                vertex.object.as_deref().unwrap_or("-"),
+                vertex.material.as_deref().unwrap_or("-"),
+            )?;
+            writeln!(
+                f,
+                "      position {} normal {} direction {} pdf {pdf} throughput {}",
+                vec(vertex.position),
+                vec(vertex.normal),
+                vec(vertex.direction),
+                vec(vertex.throughput),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Logs the path the tracer follows for one requested pixel and draws it over the image.
+pub(super) struct PathDebugger {
+    buffer: wgpu::Buffer,
+    overlay_pipeline_layout: wgpu::PipelineLayout,
+    target_format: wgpu::TextureFormat,
+    overlay_pipeline: wgpu::RenderPipeline,
+    overlay_bind_group: wgpu::BindGroup,
+    /// Pixel to log in the next frame.
+    requested: Option<(u32, u32)>,
+    /// Pixel logged by the last frame and not read back yet.
+    logged: Option<(u32, u32)>,
+    pub show_overlay: bool,
+}
+
+impl PathDebugger {
+    pub fn new(
+        device: &wgpu::Device,
+        shader_module: &wgpu::ShaderModule,
+        uniform_layout: &wgpu::BindGroupLayout,
+        target_format: wgpu::TextureFormat,
+    ) -> PathDebugger {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("path log"),
+            size: (RECORDS_OFFSET + MAX_VERTICES as usize * std::mem::size_of::<PathRecord>())
+                as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("path overlay"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                visibility: wgpu::ShaderStages::VERTEX,
+                ..storage_buffer_entry(0, true)
+            }],
+        });
+        let overlay_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("path overlay"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        let overlay_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("path overlay"),
+            bind_group_layouts: &[uniform_layout, &layout],
+            push_constant_ranges: &[],
+        });
+        let overlay_pipeline =
+            create_overlay_pipeline(device, shader_module, &overlay_pipeline_layout, target_format);
+
+        PathDebugger {
+            buffer,
+            overlay_pipeline_layout,
+            target_format,
+            overlay_pipeline,
+            overlay_bind_group,
+            requested: None,
+            logged: None,
+            show_overlay: true,
+        }
+    }
+
+    /// Rebuilds the overlay pipeline from a reloaded shader module.
+    pub fn set_shader(&mut self, device: &wgpu::Device, shader_module: &wgpu::ShaderModule) {
+        self.overlay_pipeline = create_overlay_pipeline(
+            device,
+            shader_module,
+            &self.overlay_pipeline_layout,
+            self.target_format,
+        );
+    }
+
+    /// The buffer the tracer logs into.
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn request(&mut self, pixel: (u32, u32)) {
+        self.requested = Some(pixel);
+    }
+
+    /// The pixel the frame about to be rendered should log, as passed to the shader.
+    pub fn begin_frame(&mut self) -> [i32; 2] {
+        match self.requested.take() {
+            Some((x, y)) => {
+                self.logged = Some((x, y));
+                [x as i32, y as i32]
+            }
+            None => [-1, -1],
+        }
+    }
+
+    pub fn draw_overlay(&self, render_pass: &mut wgpu::RenderPass, uniform_bind_group: &wgpu::BindGroup) {
+        if !self.show_overlay {
+            return;
+        }
+        render_pass.set_pipeline(&self.overlay_pipeline);
+        render_pass.set_bind_group(0, uniform_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.overlay_bind_group, &[]);
+        render_pass.draw(0..MAX_VERTICES, 0..1);
+    }
+
+    /// Decodes the path logged by the last frame, once. `read_buffer` reads the log back.
+    pub fn take_path(
+        &mut self,
+        scene: &Scene,
+        read_buffer: impl Fn(&wgpu::Buffer) -> Result<Vec<u8>>,
+    ) -> Result<Option<DebugPath>> {
+        let Some(pixel) = self.logged.take() else {
+            return Ok(None);
+        };
+        let data = read_buffer(&self.buffer)?;
+        let count = u32::from_le_bytes([data[0], data[1], data[2], data[3]]).min(MAX_VERTICES);
+        let records: Vec<PathRecord> = bytemuck::pod_collect_to_vec(&data[RECORDS_OFFSET..]);
+        let objects = scene.objects();
+        let vertices = records[..count as usize]
+            .iter()
+            .map(|record| {
+                let event = match record.kind {
+                    0 => PathEvent::Surface,
+                    1 => PathEvent::Escaped,
+                    _ => PathEvent::Emitter,
+                };
+                let object = usize::try_from(record.primitive)
+                    .ok()
+                    .and_then(|i| objects.get(i));
+                let material = usize::try_from(record.material)
+                    .ok()
+                    .and_then(|i| scene.materials.get(i));
+                PathVertex {
+                    event,
+                    position: record.position,
+                    normal: record.normal,
+                    object: object.map(|object| object.name.to_string()),
+                    material: material.map(|material| material.name.clone()),
+                    direction: record.direction,
+                    pdf: (event == PathEvent::Surface && record.pdf >= 0.0).then_some(record.pdf),
+                    throughput: record.throughput,
+                }
+            })
+            .collect();
+        Ok(Some(DebugPath { pixel, vertices }))
+    }
+}
+
+fn create_overlay_pipeline(
+    device: &wgpu::Device,
+    shader_module: &wgpu::ShaderModule,
+    pipeline_layout: &wgpu::PipelineLayout,
+    target_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("path overlay"),
+        layout: Some(pipeline_layout),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::LineStrip,
+            ..Default::default()
+        },
+        vertex: wgpu::VertexState {
+            module: shader_module,
+            entry_point: Some("path_overlay_vs"),
+            buffers: &[],
+            compilation_options: PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader_module,
+            entry_point: Some("path_overlay_fs"),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: target_format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: PipelineCompilationOptions {
+                constants: &output_constants(target_format),
+                ..Default::default()
+            },
+        }),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
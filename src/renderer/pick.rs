@@ -0,0 +1,81 @@
+use super::{
+    math::{Camera, Ray, Vec3, Vec4},
+    reference::hit_primitive,
+    scene::Scene,
+};
+
+/// An object found under the cursor.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Pick {
+    /// Index into `Scene::objects`.
+    pub object: usize,
+    /// Distance from the camera to the hit.
+    pub distance: f32,
+    pub position: Vec4,
+}
+
+/// Casts the primary ray through `pos`, in pixels of a `width` by `height` image, and returns the
+/// nearest object it hits. Mirrors `screen_pos`, `camera_dir`, `RayBounce` and `hit_instance` in
+/// shaders.wgsl, and intersects primitives like the reference tracer, so that it picks what the
+/// tracer shows there. Tests every object rather than traversing the BVH, which finds the same
+/// hit.
+pub(super) fn pick(
+    scene: &Scene,
+    camera: &Camera,
+    fov: f32,
+    [width, height]: [u32; 2],
+    [x, y]: [f32; 2],
+) -> Option<Pick> {
+    let screen = screen_pos([width, height], [x, y]);
+    let ray = Ray::new(camera.origin().xyz(), camera_dir(camera.forward().xyz(), fov, screen));
+
+    let mut nearest: Option<Pick> = None;
+    for (object, placed) in scene.objects().iter().enumerate() {
+        let Some(to_local) = placed.transform.inverse() else {
+            continue;
+        };
+        let local = ray.transformed(&to_local);
+        for primitive in placed.primitives {
+            if let Some(hit) = hit_primitive(&primitive.to_gpu(&scene.materials), &local) {
+                let position = placed.transform.transform_point(hit.pos);
+                let distance = (position - ray.origin).length();
+                if nearest.is_none_or(|nearest| distance < nearest.distance) {
+                    nearest = Some(Pick { object, distance, position: position.extend(0.0) });
+                }
+            }
+        }
+    }
+    nearest
+}
+
+/// The pixel position where `point` appears in a `width` by `height` image, or `None` if it is
+/// behind the camera. Mirrors `camera_project` and `pixel_pos` in shaders.wgsl.
+pub(super) fn project(
+    camera: &Camera,
+    fov: f32,
+    [width, height]: [u32; 2],
+    point: Vec4,
+) -> Option<[f32; 2]> {
+    let forward = camera.forward().xyz();
+    let dir = (point.xyz() - camera.origin().xyz()).normalized();
+    let k = 2.0 * dir.dot(&forward);
+    let n = k * dir - forward;
+    if k <= 0.0 || n.z() >= -0.0001 {
+        return None;
+    }
+    let screen = [fov * n.x() / -n.z(), fov * n.y() / -n.z()];
+    let (w, h) = ((width - 1) as f32, (height - 1) as f32);
+    Some([(screen[0] * h / w / 2.0 + 0.5) * w, (-screen[1] / 2.0 + 0.5) * h])
+}
+
+/// Screen space coordinates of a pixel position. Mirrors `screen_pos` in shaders.wgsl.
+pub(super) fn screen_pos([width, height]: [u32; 2], [x, y]: [f32; 2]) -> [f32; 2] {
+    let (w, h) = ((width - 1) as f32, (height - 1) as f32);
+    [(x / w - 0.5) * 2.0 * w / h, -(y / h - 0.5) * 2.0]
+}
+
+/// Primary ray direction through a screen point. Mirrors `camera_dir` in shaders.wgsl.
+pub(super) fn camera_dir(forward: Vec3, fov: f32, [x, y]: [f32; 2]) -> Vec3 {
+    let fovv = Vec3::new(x / fov, y / fov, -1.0);
+    (forward + fovv.normalized()).normalized()
+}
@@ -0,0 +1,310 @@
+use {
+    std::{
+        fmt,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    },
+    wgpu,
+};
+
+/// Passes timed on the GPU. Each one writes a begin and end timestamp, in this order.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum Pass {
+    Trace,
+    Denoise,
+    Display,
+}
+
+const QUERY_COUNT: u32 = 6;
+
+/// Where the ray counts start in the readback buffer, after the timestamps.
+const RAY_COUNTS_OFFSET: u64 = 64;
+
+/// Weight of the newest frame in the smoothed frame time.
+const SMOOTHING: f64 = 0.1;
+
+/// How long recent frames took and how much work they did. Pass times are measured with GPU
+/// timestamps and are `None` on adapters without them; everything else is timed on the CPU.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct FrameStats {
+    /// Wall-clock time from one frame to the next, smoothed over the last few frames.
+    pub frame_time: Duration,
+    pub trace_time: Option<Duration>,
+    pub denoise_time: Option<Duration>,
+    /// `None` as well while rendering offline, where nothing is displayed.
+    pub display_time: Option<Duration>,
+    pub samples_per_frame: u64,
+    pub rays_per_frame: u64,
+    /// Samples per pixel accumulated since the camera, scene or settings last changed.
+    pub accumulated_spp: u64,
+}
+
+impl FrameStats {
+    pub fn samples_per_second(&self) -> f64 {
+        per_second(self.samples_per_frame, self.frame_time)
+    }
+
+    pub fn rays_per_second(&self) -> f64 {
+        per_second(self.rays_per_frame, self.frame_time)
+    }
+}
+
+fn per_second(count: u64, frame_time: Duration) -> f64 {
+    if frame_time.is_zero() {
+        0.0
+    } else {
+        count as f64 / frame_time.as_secs_f64()
+    }
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1} ms/frame", self.frame_time.as_secs_f64() * 1000.0)?;
+        let passes = [
+            ("trace", self.trace_time),
+            ("denoise", self.denoise_time),
+            ("display", self.display_time),
+        ];
+        let mut timed = passes
+            .iter()
+            .filter_map(|(name, time)| Some((name, (*time)?)))
+            .peekable();
+        if timed.peek().is_some() {
+            write!(f, " (")?;
+            for (i, (name, time)) in timed.enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{name} {:.2} ms", time.as_secs_f64() * 1000.0)?;
+            }
+            write!(f, ")")?;
+        }
+        write!(
+            f,
+            ", {:.2} Msamples/s, {:.1} Mrays/s, {} spp",
+            self.samples_per_second() / 1e6,
+            self.rays_per_second() / 1e6,
+            self.accumulated_spp,
+        )
+    }
+}
+
+/// Timestamp queries around the tracer's passes, on adapters that support them.
+struct Timestamps {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    /// Nanoseconds per timestamp tick.
+    period: f64,
+}
+
+/// Progress of reading one frame's timestamps and ray counts back.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Readback {
+    Idle,
+    /// Copied into the readback buffer by the frame being encoded.
+    Copied { displayed: bool },
+    /// Waiting for the readback buffer to be mapped.
+    Mapping { displayed: bool },
+}
+
+/// Collects `FrameStats` without stalling: results are copied into a buffer that is mapped in
+/// the background and read a frame or more later. Frames encoded meanwhile are not measured.
+pub(super) struct Profiler {
+    timestamps: Option<Timestamps>,
+    ray_counts: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    readback: Readback,
+    /// Set by the map callback once `readback_buffer` can be read.
+    mapped: Arc<AtomicBool>,
+    last_frame: Option<Instant>,
+    stats: FrameStats,
+}
+
+impl Profiler {
+    /// A profiler for a tracer dispatching `workgroups` workgroups per frame. Pass times are
+    /// measured if the device was created with `Features::TIMESTAMP_QUERY`.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, workgroups: u32) -> Profiler {
+        let timestamps = device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| Timestamps {
+                query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                    label: Some("pass timestamps"),
+                    ty: wgpu::QueryType::Timestamp,
+                    count: QUERY_COUNT,
+                }),
+                resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("resolved timestamps"),
+                    size: QUERY_COUNT as u64 * 8,
+                    usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                }),
+                period: queue.get_timestamp_period() as f64,
+            });
+        let ray_counts = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ray counts"),
+            size: workgroups as u64 * 4,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("profiler readback"),
+            size: RAY_COUNTS_OFFSET + ray_counts.size(),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Profiler {
+            timestamps,
+            ray_counts,
+            readback_buffer,
+            readback: Readback::Idle,
+            mapped: Arc::new(AtomicBool::new(false)),
+            last_frame: None,
+            stats: FrameStats::default(),
+        }
+    }
+
+    /// The per-workgroup ray counters written by the tracing pass.
+    pub fn ray_counts(&self) -> &wgpu::Buffer {
+        &self.ray_counts
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    /// Starts timing a frame tracing `samples` samples in total. `restart` is set when the
+    /// accumulated image was thrown away. Picks up the results of an earlier frame if they
+    /// arrived.
+    pub fn begin_frame(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        samples: u64,
+        spp: u32,
+        restart: bool,
+    ) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame {
+            let frame_time = now - last_frame;
+            self.stats.frame_time = if self.stats.frame_time.is_zero() {
+                frame_time
+            } else {
+                self.stats.frame_time.mul_f64(1.0 - SMOOTHING) + frame_time.mul_f64(SMOOTHING)
+            };
+        }
+        self.last_frame = Some(now);
+        self.stats.samples_per_frame = samples;
+        if restart {
+            self.stats.accumulated_spp = 0;
+        }
+        self.stats.accumulated_spp += spp as u64;
+
+        if let Readback::Mapping { displayed } = self.readback {
+            // Never blocks; a lost device shows up elsewhere.
+            let _ = device.poll(wgpu::PollType::Poll);
+            if self.mapped.swap(false, Ordering::Acquire) {
+                self.read(displayed);
+                self.readback = Readback::Idle;
+            }
+        }
+        encoder.clear_buffer(&self.ray_counts, 0, None);
+    }
+
+    /// Timestamp writes for a compute pass, if they are supported.
+    pub fn compute_pass(&self, pass: Pass) -> Option<wgpu::ComputePassTimestampWrites<'_>> {
+        let (query_set, begin, end) = self.queries(pass)?;
+        Some(wgpu::ComputePassTimestampWrites {
+            query_set,
+            beginning_of_pass_write_index: Some(begin),
+            end_of_pass_write_index: Some(end),
+        })
+    }
+
+    /// Timestamp writes for a render pass, if they are supported.
+    pub fn render_pass(&self, pass: Pass) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        let (query_set, begin, end) = self.queries(pass)?;
+        Some(wgpu::RenderPassTimestampWrites {
+            query_set,
+            beginning_of_pass_write_index: Some(begin),
+            end_of_pass_write_index: Some(end),
+        })
+    }
+
+    fn queries(&self, pass: Pass) -> Option<(&wgpu::QuerySet, u32, u32)> {
+        let timestamps = self.timestamps.as_ref()?;
+        let begin = pass as u32 * 2;
+        Some((&timestamps.query_set, begin, begin + 1))
+    }
+
+    /// Copies this frame's results for reading back, unless an earlier frame's are still on
+    /// their way. `displayed` says whether the display pass was encoded.
+    pub fn end_frame(&mut self, encoder: &mut wgpu::CommandEncoder, displayed: bool) {
+        if self.readback != Readback::Idle {
+            return;
+        }
+        if let Some(timestamps) = &self.timestamps {
+            let count = if displayed { QUERY_COUNT } else { QUERY_COUNT - 2 };
+            encoder.resolve_query_set(&timestamps.query_set, 0..count, &timestamps.resolve_buffer, 0);
+            encoder.copy_buffer_to_buffer(
+                &timestamps.resolve_buffer,
+                0,
+                &self.readback_buffer,
+                0,
+                count as u64 * 8,
+            );
+        }
+        encoder.copy_buffer_to_buffer(
+            &self.ray_counts,
+            0,
+            &self.readback_buffer,
+            RAY_COUNTS_OFFSET,
+            self.ray_counts.size(),
+        );
+        self.readback = Readback::Copied { displayed };
+    }
+
+    /// Starts mapping the results copied by `end_frame`. Call after submitting its encoder.
+    pub fn frame_submitted(&mut self) {
+        let Readback::Copied { displayed } = self.readback else {
+            return;
+        };
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                // Mapping only fails when the device is lost, and then the tracer is replaced.
+                mapped.store(result.is_ok(), Ordering::Release);
+            });
+        self.readback = Readback::Mapping { displayed };
+    }
+
+    fn read(&mut self, displayed: bool) {
+        let data = self.readback_buffer.slice(..).get_mapped_range();
+        self.stats.rays_per_frame = data[RAY_COUNTS_OFFSET as usize..]
+            .chunks_exact(4)
+            .map(|count| u32::from_le_bytes(count.try_into().unwrap()) as u64)
+            .sum();
+        if let Some(timestamps) = &self.timestamps {
+            let ticks: Vec<u64> = data[..QUERY_COUNT as usize * 8]
+                .chunks_exact(8)
+                .map(|tick| u64::from_le_bytes(tick.try_into().unwrap()))
+                .collect();
+            let time = |pass: Pass| {
+                let begin = pass as usize * 2;
+                let ticks = ticks[begin + 1].saturating_sub(ticks[begin]);
+                Duration::from_nanos((ticks as f64 * timestamps.period) as u64)
+            };
+            self.stats.trace_time = Some(time(Pass::Trace));
+            self.stats.denoise_time = Some(time(Pass::Denoise));
+            self.stats.display_time = displayed.then(|| time(Pass::Display));
+        }
+        drop(data);
+        self.readback_buffer.unmap();
+    }
+}
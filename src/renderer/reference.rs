@@ -0,0 +1,726 @@
+use std::{
+    f32::consts::PI,
+    ops::Range,
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+};
+
+use super::{
+    aov::RenderOutput,
+    bvh::{FlatScene, GpuBvhNode, GpuInstance},
+    math::{Ray, Vec3},
+    pick::{camera_dir, screen_pos},
+    scene::{
+        GpuPrimitive, Scene, Viewpoint, PRIMITIVE_BOX, PRIMITIVE_CAPSULE, PRIMITIVE_CONE, PRIMITIVE_CYLINDER,
+        PRIMITIVE_DISK, PRIMITIVE_PLANE, PRIMITIVE_QUAD, PRIMITIVE_TORUS,
+    },
+};
+
+/// Side of the square tiles that the threads take turns rendering.
+const TILE_SIZE: u32 = 16;
+
+/// Matches `BVH_STACK_SIZE` in shaders.wgsl.
+const BVH_STACK_SIZE: usize = 24;
+
+/// Matches `TORUS_SEARCH_RADIUS` in shaders.wgsl.
+const TORUS_SEARCH_RADIUS: f32 = 1.25;
+
+/// Renders `scene` on the CPU with the integrator of `path_tracer_cs` in shaders.wgsl, as a
+/// reference to check the GPU against. Like offline rendering on the GPU it traces
+/// `scene.settings.samples` samples per pixel in each frame until it has at least `spp`, hashing
+/// the same frame indices into the same random numbers, so for the same seed the two images
+/// differ only by rounding. Only the beauty pass is produced, and it is not denoised.
+///
+/// The image is split into tiles that are rendered on every available core.
+pub fn render_reference(scene: &Scene, viewpoint: &Viewpoint, [width, height]: [u32; 2], spp: u32) -> RenderOutput {
+    let camera = viewpoint.camera();
+    let tracer = Tracer {
+        scene,
+        flat: FlatScene::new(scene),
+        origin: camera.origin().xyz(),
+        forward: camera.forward().xyz(),
+        fov: viewpoint.zoom,
+        size: [width, height],
+        frames: spp.div_ceil(scene.settings.samples.max(1)),
+    };
+
+    let columns = width.div_ceil(TILE_SIZE);
+    let tiles = columns * height.div_ceil(TILE_SIZE);
+    let next = AtomicU32::new(0);
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    let rendered: Vec<(u32, Vec<[f32; 4]>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut rendered = Vec::new();
+                    loop {
+                        let tile = next.fetch_add(1, Ordering::Relaxed);
+                        if tile >= tiles {
+                            return rendered;
+                        }
+                        let origin = [tile % columns * TILE_SIZE, tile / columns * TILE_SIZE];
+                        rendered.push((tile, tracer.render_tile(origin)));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+            .collect()
+    });
+
+    let mut beauty = vec![[0.0; 4]; (width * height) as usize];
+    for (tile, pixels) in rendered {
+        let [x0, y0] = [tile % columns * TILE_SIZE, tile / columns * TILE_SIZE];
+        let tile_width = TILE_SIZE.min(width - x0) as usize;
+        for (row, pixels) in pixels.chunks(tile_width).enumerate() {
+            let start = ((y0 as usize + row) * width as usize) + x0 as usize;
+            beauty[start..start + tile_width].copy_from_slice(pixels);
+        }
+    }
+    RenderOutput {
+        width,
+        height,
+        beauty,
+        aovs: Vec::new(),
+        cryptomattes: Vec::new(),
+    }
+}
+
+/// Where a ray meets a primitive, in world space. Mirrors `HitInfo` in shaders.wgsl.
+pub(super) struct Hit {
+    normal: Vec3,
+    pub pos: Vec3,
+    time: f32,
+    /// Index into `Scene::materials`.
+    material: usize,
+}
+
+struct Tracer<'a> {
+    scene: &'a Scene,
+    flat: FlatScene,
+    origin: Vec3,
+    forward: Vec3,
+    fov: f32,
+    size: [u32; 2],
+    frames: u32,
+}
+
+impl Tracer<'_> {
+    /// The pixels of the tile with its top left corner at `origin`, row by row, cut off at the
+    /// edges of the image.
+    fn render_tile(&self, [x0, y0]: [u32; 2]) -> Vec<[f32; 4]> {
+        let [width, height] = self.size;
+        let mut pixels = Vec::with_capacity((TILE_SIZE * TILE_SIZE) as usize);
+        for y in y0..(y0 + TILE_SIZE).min(height) {
+            for x in x0..(x0 + TILE_SIZE).min(width) {
+                let seed = self.scene.settings.seed;
+                // The GPU keeps a running mean over frames with the camera still.
+                let sum = (0..self.frames).fold(Vec3::zero(), |sum, frame| {
+                    sum + self.trace_pixel([x, y], frame.wrapping_add(seed) % 1000000)
+                });
+                let color = sum / self.frames as f32;
+                pixels.push([color.x(), color.y(), color.z(), 1.0]);
+            }
+        }
+        pixels
+    }
+
+    /// The estimate `path_tracer_cs` stores for a pixel in the frame with index `frame`,
+    /// averaging `settings.samples` paths. Follows the shader step by step, quirks included.
+    fn trace_pixel(&self, [x, y]: [u32; 2], frame: u32) -> Vec3 {
+        let settings = &self.scene.settings;
+        let [x, y] = screen_pos(self.size, [x as f32 + 0.5, y as f32 + 0.5]);
+        let frame = frame as f32;
+        let mut colors = Vec3::zero();
+        for j in 0..settings.samples {
+            let j = j as f32;
+            let mut ray = Ray::new(self.origin, camera_dir(self.forward, self.fov, [x, y]));
+            let mut color = Vec3::all(1.0);
+            let mut transparent_bounces = 0;
+            for _ in 0..settings.max_bounces {
+                let Some(hit) = self.ray_bounce(&ray) else {
+                    color = color * self.sky(ray.dir);
+                    break;
+                };
+                let material = &self.scene.materials[hit.material];
+                let emission = material.emission.xyz();
+                if emission != Vec3::zero() {
+                    color = color * emission;
+                    break;
+                }
+
+                color = color * material.color.xyz();
+                if material.transparency > 0.5 {
+                    if transparent_bounces == settings.max_transparent_bounces {
+                        break;
+                    }
+                    transparent_bounces += 1;
+                    ray = Ray::new(hit.pos, refracted(&ray, &hit, material.refractive_index));
+                    continue;
+                }
+
+                let mut random = random_direction(Vec3::new(frame % 1000.0 + j, x + j * 2.0, y + j * 3.0));
+                if random.dot(&hit.normal) < 0.0 {
+                    random = -random;
+                }
+                random = (random + hit.normal) / (random + hit.normal).length();
+                let mirrored = 2.0 * hit.normal.dot(&ray.dir) * hit.normal - ray.dir;
+                ray = Ray::new(
+                    hit.pos,
+                    random * (1.0 - material.reflections) - mirrored * material.reflections,
+                );
+            }
+            colors += color;
+        }
+        colors / settings.samples as f32
+    }
+
+    /// Radiance of the sky seen along `dir`.
+    fn sky(&self, dir: Vec3) -> Vec3 {
+        let environment = &self.scene.environment;
+        let t = 0.5 * (dir.normalized().y() + 1.0);
+        let (nadir, zenith) = (environment.nadir.xyz(), environment.zenith.xyz());
+        environment.intensity * (nadir * (1.0 - t) + zenith * t)
+    }
+
+    /// The nearest hit along `ray`. Mirrors `RayBounce`.
+    fn ray_bounce(&self, ray: &Ray) -> Option<Hit> {
+        let mut nearest_hit = None;
+        self.traverse(0, ray, 3.4e38, |items, mut nearest| {
+            for instance in &self.flat.instances[items] {
+                if let Some(hit) = self.hit_instance(instance, ray, nearest) {
+                    nearest = hit.time / ray.dir.length();
+                    nearest_hit = Some(hit);
+                }
+            }
+            nearest
+        });
+        nearest_hit
+    }
+
+    /// The nearest primitive of `instance` that `ray` hits before `ray.at(limit)`, in world space.
+    /// Mirrors `hit_instance`.
+    fn hit_instance(&self, instance: &GpuInstance, ray: &Ray, limit: f32) -> Option<Hit> {
+        let local = ray.transformed(&instance.to_local);
+        let mut nearest_hit = None;
+        self.traverse(instance.root, &local, limit, |items, mut nearest| {
+            for primitive in &self.flat.primitives[items] {
+                if let Some(hit) = hit_primitive(primitive, &local) {
+                    let t = hit.time / local.dir.length();
+                    if t < nearest {
+                        nearest = t;
+                        nearest_hit = Some(hit);
+                    }
+                }
+            }
+            nearest
+        });
+        nearest_hit.map(|hit| {
+            let pos = instance.to_world.transform_point(hit.pos);
+            Hit {
+                // Normals take the inverse transpose so that they stay perpendicular under scaling.
+                normal: (instance.to_local.to_mat3().transpose() * hit.normal).normalized(),
+                pos,
+                time: (pos - ray.origin).length(),
+                material: hit.material,
+            }
+        })
+    }
+
+    /// Walks the tree rooted at node `root` like the shader, handing the item range of every leaf
+    /// whose box `ray` enters before `ray.at(nearest)` to `visit`, which returns the new nearest
+    /// hit.
+    fn traverse(&self, root: u32, ray: &Ray, mut nearest: f32, mut visit: impl FnMut(Range<usize>, f32) -> f32) {
+        let inv = Vec3::new(ray.dir.x().recip(), ray.dir.y().recip(), ray.dir.z().recip());
+        let mut stack = [0; BVH_STACK_SIZE];
+        let mut top = 1;
+        stack[0] = root;
+        while top > 0 {
+            top -= 1;
+            let node = &self.flat.nodes[stack[top] as usize];
+            if !hits_box(node, ray, inv, nearest) {
+                continue;
+            }
+            if node.count == 0 {
+                stack[top] = node.first;
+                // Writes past the end of the shader's stack are dropped.
+                if let Some(slot) = stack.get_mut(top + 1) {
+                    *slot = node.first + 1;
+                }
+                top = (top + 2).min(BVH_STACK_SIZE);
+                continue;
+            }
+            let first = node.first as usize;
+            nearest = visit(first..first + node.count as usize, nearest);
+        }
+    }
+}
+
+/// Mirrors `hits_box`. `inv` is the reciprocal of `ray.dir`.
+fn hits_box(node: &GpuBvhNode, ray: &Ray, inv: Vec3, limit: f32) -> bool {
+    let t0 = (Vec3::from(node.min) - ray.origin) * inv;
+    let t1 = (Vec3::from(node.max) - ray.origin) * inv;
+    let near = t0.min(&t1);
+    let far = t0.max(&t1);
+    (0..3).all(|axis| node.min[axis] <= node.max[axis])
+        && near.x().max(near.y()).max(near.z().max(0.0)) <= far.x().min(far.y()).min(far.z().min(limit))
+}
+
+/// Where `ray` meets `primitive`, in the space of `ray`, if that lies ahead of its origin. Mirrors
+/// `hit_primitive`.
+pub(super) fn hit_primitive(primitive: &GpuPrimitive, ray: &Ray) -> Option<Hit> {
+    let normal = Vec3::from(primitive.u);
+    match primitive.kind {
+        PRIMITIVE_PLANE => hit_flat(primitive, ray, normal),
+        PRIMITIVE_QUAD => hit_quad(primitive, ray),
+        PRIMITIVE_DISK => hit_flat(primitive, ray, normal)
+            .filter(|hit| (hit.pos - Vec3::from(primitive.position)).length() <= primitive.radius),
+        PRIMITIVE_BOX => hit_box(primitive, ray),
+        PRIMITIVE_CYLINDER => hit_cone(primitive, ray, primitive.radius),
+        PRIMITIVE_CONE => hit_cone(primitive, ray, primitive.v[0]),
+        PRIMITIVE_CAPSULE => hit_capsule(primitive, ray),
+        PRIMITIVE_TORUS => hit_torus(primitive, ray),
+        _ => hit_sphere(primitive, ray),
+    }
+}
+
+/// Mirrors `hit_flat`, which turns the normal of the plane to face the ray.
+fn hit_flat(primitive: &GpuPrimitive, ray: &Ray, normal: Vec3) -> Option<Hit> {
+    let facing = normal.dot(&ray.dir);
+    let pos = ray.origin + (Vec3::from(primitive.position) - ray.origin).dot(&normal) / facing * ray.dir;
+    (facing != 0.0 && ray.dir.dot(&(pos - ray.origin)) > 0.01).then(|| Hit {
+        normal: if facing > 0.0 { -normal } else { normal },
+        pos,
+        time: (pos - ray.origin).length(),
+        material: primitive.material_id as usize,
+    })
+}
+
+/// Mirrors `hit_quad`.
+fn hit_quad(primitive: &GpuPrimitive, ray: &Ray) -> Option<Hit> {
+    let (u, v) = (Vec3::from(primitive.u), Vec3::from(primitive.v));
+    let normal = u.cross(&v);
+    let hit = hit_flat(primitive, ray, normal.normalized())?;
+    let p = hit.pos - Vec3::from(primitive.position);
+    let a = normal.dot(&p.cross(&v)) / normal.dot(&normal);
+    let b = normal.dot(&u.cross(&p)) / normal.dot(&normal);
+    (a.min(b) >= 0.0 && a.max(b) <= 1.0).then_some(hit)
+}
+
+/// Mirrors `hit_box`, which misses rays that start inside the box.
+fn hit_box(primitive: &GpuPrimitive, ray: &Ray) -> Option<Hit> {
+    let slab = |corner: [f32; 3]| {
+        let t = Vec3::from(corner) - ray.origin;
+        Vec3::new(t.x() / ray.dir.x(), t.y() / ray.dir.y(), t.z() / ray.dir.z())
+    };
+    let (t0, t1) = (slab(primitive.position), slab(primitive.u));
+    let (near, far) = (t0.min(&t1), t0.max(&t1));
+    let entry = near.x().max(near.y()).max(near.z());
+    let pos = ray.origin + entry * ray.dir;
+    let crosses = entry <= far.x().min(far.y()).min(far.z());
+    (crosses && ray.dir.dot(&(pos - ray.origin)) > 0.01).then(|| {
+        let face = |axis: usize| if near.get(axis) == entry { -ray.dir.get(axis).signum() } else { 0.0 };
+        Hit {
+            normal: Vec3::new(face(0), face(1), face(2)).normalized(),
+            pos,
+            time: (pos - ray.origin).length(),
+            material: primitive.material_id as usize,
+        }
+    })
+}
+
+/// The hit at `pos` on `primitive` with `normal`, if it lies ahead of the origin of `ray` like
+/// every hit in the shader.
+fn hit_ahead(primitive: &GpuPrimitive, ray: &Ray, pos: Vec3, normal: Vec3) -> Option<Hit> {
+    (ray.dir.dot(&(pos - ray.origin)) > 0.01).then(|| Hit {
+        normal,
+        pos,
+        time: (pos - ray.origin).length(),
+        material: primitive.material_id as usize,
+    })
+}
+
+/// Mirrors `hit_cone`, which enters the capped cone through the cap where the ray enters the slab
+/// between the caps, if the cone holds that point, or else where it first reaches the side.
+fn hit_cone(primitive: &GpuPrimitive, ray: &Ray, top: f32) -> Option<Hit> {
+    let axis = Vec3::from(primitive.u);
+    let height = axis.length();
+    let w = axis / height;
+    let dir = ray.dir / ray.dir.length();
+    let o = ray.origin - Vec3::from(primitive.position);
+    let (ow, dw) = (o.dot(&w), dir.dot(&w));
+    let (near, far) = if dw != 0.0 {
+        let (a, b) = (-ow / dw, (height - ow) / dw);
+        (a.min(b), a.max(b))
+    } else if ow < 0.0 || ow > height {
+        return None;
+    } else {
+        (-3.4e38, 3.4e38)
+    };
+    let slope = (top - primitive.radius) / height;
+    let (op, dp) = (o - ow * w, dir - dw * w);
+    let (r0, r1) = (primitive.radius + slope * ow, slope * dw);
+    let a = dp.dot(&dp) - r1 * r1;
+    let b = op.dot(&dp) - r0 * r1;
+    let c = op.dot(&op) - r0 * r0;
+    let on_side = (a * near + 2.0 * b) * near + c > 0.0;
+    let mut t = near;
+    if on_side {
+        let disc = b * b - a * c;
+        if disc < 0.0 {
+            return None;
+        }
+        let (first, second) = if a != 0.0 {
+            ((-b - disc.sqrt()) / a, (-b + disc.sqrt()) / a)
+        } else {
+            (-c / (2.0 * b), -c / (2.0 * b))
+        };
+        t = if first.min(second) > near { first.min(second) } else { first.max(second) };
+        if t <= near || t > far {
+            return None;
+        }
+    }
+    let p = o + t * dir;
+    let out = p - p.dot(&w) * w;
+    let normal = if on_side {
+        (out / out.length().max(1e-12) - slope * w).normalized()
+    } else {
+        -dw.signum() * w
+    };
+    hit_ahead(primitive, ray, Vec3::from(primitive.position) + p, normal)
+}
+
+/// Mirrors `hit_capsule`, which enters the capsule through its side or, beyond the ends, through
+/// the sphere on the end the ray passes.
+fn hit_capsule(primitive: &GpuPrimitive, ray: &Ray) -> Option<Hit> {
+    let axis = Vec3::from(primitive.u);
+    let height = axis.length();
+    let w = axis / height;
+    let r = primitive.radius;
+    let dir = ray.dir / ray.dir.length();
+    let o = ray.origin - Vec3::from(primitive.position);
+    let dw = dir.dot(&w);
+    let (op, dp) = (o - o.dot(&w) * w, dir - dw * w);
+    let a = dp.dot(&dp);
+    let b = op.dot(&dp);
+    let c = op.dot(&op) - r * r;
+    let disc = b * b - a * c;
+    if disc < 0.0 || (a < 1e-12 && c > 0.0) {
+        return None;
+    }
+    let mut t = (-b - disc.sqrt()) / a.max(1e-12);
+    let h = if a < 1e-12 {
+        if dw < 0.0 { height + 1.0 } else { -1.0 }
+    } else {
+        o.dot(&w) + t * dw
+    };
+    let mut normal = op + t * dp;
+    if !(0.0..=height).contains(&h) {
+        let oc = o - h.clamp(0.0, height) * w;
+        let k = oc.dot(&dir);
+        let end_disc = k * k - oc.dot(&oc) + r * r;
+        if end_disc < 0.0 {
+            return None;
+        }
+        t = -k - end_disc.sqrt();
+        normal = oc + t * dir;
+    }
+    hit_ahead(primitive, ray, Vec3::from(primitive.position) + o + t * dir, normal.normalized())
+}
+
+/// Mirrors `hit_torus`, which finds the first root of the torus's quartic that the ray crosses
+/// from outside, in units of the bounding radius and starting from a sphere a little larger.
+fn hit_torus(primitive: &GpuPrimitive, ray: &Ray) -> Option<Hit> {
+    let bound = primitive.radius + primitive.v[0];
+    let (major, minor) = (primitive.radius / bound, primitive.v[0] / bound);
+    let axis = Vec3::from(primitive.u);
+    let dir = ray.dir / ray.dir.length();
+    let o = (ray.origin - Vec3::from(primitive.position)) / bound;
+    let k0 = o.dot(&dir);
+    let disc = k0 * k0 - o.dot(&o) + TORUS_SEARCH_RADIUS * TORUS_SEARCH_RADIUS;
+    if disc < 0.0 {
+        return None;
+    }
+    let start = (-k0 - disc.sqrt()).max(0.01 / ray.dir.length() / bound);
+    let end = -k0 + disc.sqrt();
+    if start >= end {
+        return None;
+    }
+    let o1 = o + start * dir;
+    let (m, k, q, e) = (o1.dot(&o1), o1.dot(&dir), o1.dot(&axis), dir.dot(&axis));
+    let s = m + major * major - minor * minor;
+    let rr = 4.0 * major * major;
+    let coefficients = [
+        s * s - rr * (m - q * q),
+        4.0 * k * s - 2.0 * rr * (k - q * e),
+        4.0 * k * k + 2.0 * s - rr * (1.0 - e * e),
+        4.0 * k,
+    ];
+    let t = first_entry(coefficients, 0.0, end - start)?;
+    let p = o1 + t * dir;
+    let along = p.dot(&axis);
+    let normal = (p.dot(&p) + major * major - minor * minor) * p - 2.0 * major * major * (p - along * axis);
+    hit_ahead(primitive, ray, Vec3::from(primitive.position) + bound * p, normal.normalized())
+}
+
+/// Mirrors `quartic`: t⁴ + c[3] t³ + c[2] t² + c[1] t + c[0].
+fn quartic(c: [f32; 4], t: f32) -> f32 {
+    (((t + c[3]) * t + c[2]) * t + c[1]) * t + c[0]
+}
+
+fn quartic_slope(c: [f32; 4], t: f32) -> f32 {
+    ((4.0 * t + 3.0 * c[3]) * t + 2.0 * c[2]) * t + c[1]
+}
+
+/// Mirrors `bisect`.
+fn bisect(f: impl Fn(f32) -> f32, a: f32, b: f32) -> f32 {
+    let negative = f(a) < 0.0;
+    let (mut lo, mut hi) = (a, b);
+    for _ in 0..32 {
+        let mid = 0.5 * (lo + hi);
+        if (f(mid) < 0.0) == negative {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    0.5 * (lo + hi)
+}
+
+/// Mirrors `first_entry`, which splits `start..end` where the quartic is monotonic and bisects the
+/// first piece that falls from positive to not positive.
+fn first_entry(c: [f32; 4], start: f32, end: f32) -> Option<f32> {
+    let mut bends = [start, end, end, end];
+    let disc = 36.0 * c[3] * c[3] - 96.0 * c[2];
+    if disc > 0.0 {
+        bends[1] = ((-6.0 * c[3] - disc.sqrt()) / 24.0).clamp(start, end);
+        bends[2] = ((-6.0 * c[3] + disc.sqrt()) / 24.0).clamp(start, end);
+    }
+    let slope = |t| quartic_slope(c, t);
+    let mut stops = vec![start];
+    for pair in bends.windows(2) {
+        if (slope(pair[0]) < 0.0) != (slope(pair[1]) < 0.0) {
+            stops.push(bisect(slope, pair[0], pair[1]));
+        }
+    }
+    stops.push(end);
+    let value = |t| quartic(c, t);
+    stops
+        .windows(2)
+        .find(|pair| value(pair[0]) > 0.0 && value(pair[1]) <= 0.0)
+        .map(|pair| bisect(value, pair[0], pair[1]))
+}
+
+/// Mirrors `Hit_sphere`, which finds the near intersection and requires it to lie ahead of the
+/// origin.
+fn hit_sphere(sphere: &GpuPrimitive, ray: &Ray) -> Option<Hit> {
+    let center = Vec3::from(sphere.position);
+    let a = (center - ray.origin).dot(&ray.dir) / ray.dir.length();
+    let b = (center - ray.origin).length();
+    let dir = ray.dir / ray.dir.length();
+    let oc = ray.origin - center;
+    let qa = dir.dot(&dir);
+    let qb = 2.0 * oc.dot(&dir);
+    let qc = oc.dot(&oc) - sphere.radius * sphere.radius;
+    let discriminant = qb * qb - 4.0 * qa * qc;
+    let entry = if discriminant < 0.0 {
+        Vec3::all(9999.0)
+    } else {
+        ray.origin + (-qb - discriminant.sqrt()) / (2.0 * qa) * dir
+    };
+    if b * b - a * a < sphere.radius * sphere.radius && ray.dir.dot(&(entry - ray.origin)) > 0.01 {
+        Some(Hit {
+            normal: (entry - center) / (entry - center).length(),
+            pos: entry,
+            time: (entry - ray.origin).length(),
+            material: sphere.material_id as usize,
+        })
+    } else {
+        None
+    }
+}
+
+/// The direction the shader continues in through a transparent surface.
+fn refracted(ray: &Ray, hit: &Hit, refractive_index: f32) -> Vec3 {
+    let (dir, normal) = (ray.dir, hit.normal);
+    let cos1 = normal.dot(&dir) / (dir.length() * normal.length());
+    let sin1 = (1.0 - cos1 * cos1).max(0.0).sqrt();
+    let sin2 = sin1 / refractive_index;
+    let along = dir - dir.dot(&normal) * normal / normal.length();
+    let along = along / along.length().max(1e-8);
+    let refracted = along * sin2 - normal * (1.0 - sin2 * sin2).max(0.0).sqrt();
+    refracted / refracted.length()
+}
+
+/// Mirrors `rand`: a hash of `p` in [0, 1).
+fn rand(p: Vec3) -> f32 {
+    let h = p.dot(&Vec3::new(127.1, 311.7, 191.91)).sin() * 43758.547;
+    h - h.floor()
+}
+
+/// Mirrors `random_direction`: a point on the unit sphere hashed from `seed`.
+fn random_direction(seed: Vec3) -> Vec3 {
+    let u1 = rand(seed);
+    let u2 = rand(seed + Vec3::new(17.0, 31.0, 47.0));
+    let theta = 2.0 * PI * u1;
+    let z = 2.0 * u2 - 1.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    Vec3::new(r * theta.cos(), r * theta.sin(), z)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::renderer::{
+            math::Vec4,
+            scene::{Environment, Material, Primitive, Shape},
+        },
+        proptest::prelude::*,
+    };
+
+    #[test]
+    fn empty_scene_shows_the_sky() {
+        let scene = Scene {
+            environment: Environment {
+                nadir: Vec4::new(1.0, 0.5, 0.0, 0.0),
+                zenith: Vec4::new(0.0, 0.5, 1.0, 0.0),
+                intensity: 2.0,
+            },
+            ..Scene::default()
+        };
+        let viewpoint = Viewpoint {
+            pitch: 0.3,
+            ..Viewpoint::default()
+        };
+        // Not a whole number of tiles, so that the edge tiles are cut off.
+        let size = [TILE_SIZE * 2 + 3, TILE_SIZE + 5];
+        let output = render_reference(&scene, &viewpoint, size, 1);
+        let camera = viewpoint.camera();
+        for (i, pixel) in output.beauty.iter().enumerate() {
+            let [x, y] = [i as u32 % size[0], i as u32 / size[0]];
+            let screen = screen_pos(size, [x as f32 + 0.5, y as f32 + 0.5]);
+            let dir = camera_dir(camera.forward().xyz(), viewpoint.zoom, screen);
+            let t = 0.5 * (dir.y() + 1.0);
+            let expected = 2.0 * Vec3::new(1.0 - t, 0.5, t);
+            let color = Vec3::new(pixel[0], pixel[1], pixel[2]);
+            assert!((color - expected).length() < 1e-5, "pixel {x}, {y} is {color:?}, not {expected:?}");
+        }
+    }
+
+    #[test]
+    fn frames_average_like_the_gpu() {
+        let mut scene = Scene::default_scene();
+        scene.settings.samples = 2;
+        scene.settings.seed = 7;
+        let size = [9, 6];
+        // Three frames of two samples, the first of which has index 7.
+        let output = render_reference(&scene, &Viewpoint::default(), size, 5);
+        let camera = Viewpoint::default().camera();
+        let tracer = Tracer {
+            scene: &scene,
+            flat: FlatScene::new(&scene),
+            origin: camera.origin().xyz(),
+            forward: camera.forward().xyz(),
+            fov: Viewpoint::default().zoom,
+            size,
+            frames: 3,
+        };
+        let pixel = [4, 3];
+        let expected = (7..10).map(|frame| tracer.trace_pixel(pixel, frame)).fold(Vec3::zero(), |a, b| a + b) / 3.0;
+        let [r, g, b, _] = output.beauty[(pixel[1] * size[0] + pixel[0]) as usize];
+        assert_eq!(Vec3::new(r, g, b), expected);
+    }
+
+    /// Sphere traces `distance`, which never overestimates the distance to the surface, for where
+    /// `ray` first comes within 1e-5 of it.
+    fn march(distance: &dyn Fn(Vec3) -> f32, ray: &Ray) -> Option<f32> {
+        let mut t = 0.0;
+        for _ in 0..10_000 {
+            let step = distance(ray.at(t));
+            if step < 1e-5 {
+                return Some(t);
+            }
+            t += step;
+            if t > 10.0 {
+                break;
+            }
+        }
+        None
+    }
+
+    /// The distance from a point to a surface, negative inside.
+    type Distance = Box<dyn Fn(Vec3) -> f32>;
+
+    /// Each solid around an oblique axis with its distance function, or a bound on it that is exact
+    /// near the surface away from the edges.
+    fn solids() -> Vec<(Shape, Distance)> {
+        let (base, axis) = (Vec3::new(0.1, -0.2, 0.05), Vec3::new(0.3, 1.0, -0.2));
+        let (w, height) = (axis.normalized(), axis.length());
+        let along = move |p: Vec3| (p - base).dot(&w);
+        let out = move |p: Vec3| (p - base - along(p) * w).length();
+        let slab = move |p: Vec3| (along(p) - height / 2.0).abs() - height / 2.0;
+        let slope = (0.1 - 0.5) / height;
+        vec![
+            (Shape::Cylinder { base, axis, radius: 0.4 }, Box::new(move |p| (out(p) - 0.4).max(slab(p)))),
+            (
+                Shape::Cone { base, axis, base_radius: 0.5, top_radius: 0.1 },
+                Box::new(move |p| ((out(p) - 0.5 - slope * along(p)) / slope.hypot(1.0)).max(slab(p))),
+            ),
+            (
+                Shape::Capsule { base, axis, radius: 0.3 },
+                Box::new(move |p| (p - base - along(p).clamp(0.0, height) * w).length() - 0.3),
+            ),
+            (
+                Shape::Torus { center: base, axis, major_radius: 0.6, minor_radius: 0.2 },
+                Box::new(move |p| (out(p) - 0.6).hypot(along(p)) - 0.2),
+            ),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn solids_are_hit_where_their_distance_vanishes(
+            from in prop::array::uniform3(-1.0f32..1.0),
+            to in prop::array::uniform3(-0.8f32..0.8),
+        ) {
+            let from = Vec3::from(from);
+            prop_assume!(from.length() > 0.1);
+            let materials = [Material::new("metal", Vec4::all(1.0), 0.0)];
+            for (shape, distance) in solids() {
+                let ray = Ray::new(3.0 * from.normalized(), Vec3::from(to) - 3.0 * from.normalized());
+                let dir = ray.dir.normalized();
+                let hit = hit_primitive(&Primitive::new("solid", shape, 0).to_gpu(&materials), &ray);
+                let marched = march(&distance, &Ray::new(ray.origin, dir));
+                match hit {
+                    Some(hit) => {
+                        let t = marched.unwrap_or(f32::INFINITY);
+                        // The march stops anywhere within 1e-5 of the surface, which a grazing ray
+                        // passes through over a long stretch.
+                        let slack = 1e-5 / hit.normal.dot(&dir).abs();
+                        prop_assert!((hit.time - t).abs() < 1e-3 + slack, "{shape:?} hit at {} but marched to {t}", hit.time);
+                        prop_assert!(distance(hit.pos).abs() < 1e-4, "{shape:?} hit off its surface at {:?}", hit.pos);
+                        let h = 1e-3;
+                        let gradient = |side: f32| {
+                            let slope = |e: Vec3| (distance(hit.pos + side * h * e) - distance(hit.pos)) / (side * h);
+                            Vec3::new(slope(Vec3::new(1.0, 0.0, 0.0)), slope(Vec3::new(0.0, 1.0, 0.0)), slope(Vec3::new(0.0, 0.0, 1.0)))
+                        };
+                        let (ahead, behind) = (gradient(1.0), gradient(-1.0));
+                        // Across an edge the gradient of the bound changes, and is not that of either face.
+                        if (ahead - behind).length() < 1e-2 {
+                            let gradient = (ahead + behind).normalized();
+                            prop_assert!((gradient - hit.normal).length() < 2e-2, "{shape:?} has normal {:?}, not {gradient:?}", hit.normal);
+                        }
+                    }
+                    // Rays that only graze the surface may come close enough to stop the march.
+                    None => prop_assert!(
+                        marched.is_none_or(|t| distance(ray.origin + (t + 0.01) * dir) > -1e-3),
+                        "{shape:?} was missed, but marched to {marched:?}"
+                    ),
+                }
+            }
+        }
+    }
+}
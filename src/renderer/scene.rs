@@ -0,0 +1,1170 @@
+use {
+    anyhow::{bail, Context, Result},
+    bytemuck::{Pod, Zeroable},
+    serde::{Deserialize, Serialize},
+    std::path::Path,
+};
+
+use super::{
+    math::{Camera, Mat4, Quat, Transform, Vec3, Vec4},
+    settings::RenderSettings,
+};
+
+/// A named material. Several primitives can share one material by index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    pub name: String,
+    pub color: Vec4,
+    /// Blend between a diffuse bounce (0) and a mirror reflection (1).
+    pub reflections: f32,
+    pub emission: Vec4,
+    pub transparency: f32,
+    pub refractive_index: f32,
+}
+
+/// A surface the tracer intersects analytically, in the space of the object it belongs to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Shape {
+    Sphere { center: Vec3, radius: f32 },
+    /// The infinite plane through `point` perpendicular to `normal`.
+    Plane { point: Vec3, normal: Vec3 },
+    /// The parallelogram with a corner at `corner` and edges `u` and `v`. With an emissive
+    /// material it is an area light.
+    Quad { corner: Vec3, u: Vec3, v: Vec3 },
+    /// The disk around `center` perpendicular to `normal`.
+    Disk { center: Vec3, normal: Vec3, radius: f32 },
+    /// A box with its faces along the axes, which a node can rotate.
+    Box { min: Vec3, max: Vec3 },
+    /// A solid cylinder with flat caps, from `base` to `base + axis`.
+    Cylinder { base: Vec3, axis: Vec3, radius: f32 },
+    /// A solid cone with flat caps, from `base_radius` at `base` to `top_radius` at `base + axis`.
+    /// It comes to a point where a radius is 0.
+    Cone { base: Vec3, axis: Vec3, base_radius: f32, top_radius: f32 },
+    /// Everything within `radius` of the segment from `base` to `base + axis`: a cylinder with a
+    /// hemisphere on each end.
+    Capsule { base: Vec3, axis: Vec3, radius: f32 },
+    /// A ring around `center` in the plane perpendicular to `axis`, whose tube of `minor_radius`
+    /// follows the circle of `major_radius`.
+    Torus { center: Vec3, axis: Vec3, major_radius: f32, minor_radius: f32 },
+}
+
+/// A named shape drawn with one material.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Primitive {
+    pub name: String,
+    pub shape: Shape,
+    /// Index into `Scene::materials`.
+    pub material: usize,
+}
+
+/// A shape shared by every node that draws it, in its own local space. Instancing a geometry many
+/// times costs one transform per node rather than a copy of its primitives.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Geometry {
+    pub name: String,
+    pub primitives: Vec<Primitive>,
+}
+
+/// A node of the scene graph, placed by `transform` relative to its parent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub name: String,
+    pub transform: Transform,
+    /// Index into `Scene::nodes` of the node this one is placed in. Parents come before their
+    /// children.
+    pub parent: Option<usize>,
+    /// Index into `Scene::geometries` drawn at this node, or `None` for a node that only groups
+    /// its children.
+    pub geometry: Option<usize>,
+}
+
+/// Something the tracer can hit, as numbered by object IDs in the shader, the AOVs, Cryptomattes
+/// and `Pick`: first every loose primitive, then every node that draws a geometry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object<'a> {
+    pub name: &'a str,
+    /// Places `primitives` in the world.
+    pub transform: Mat4,
+    pub primitives: &'a [Primitive],
+    /// Index into `Scene::nodes`, or `None` for a loose primitive.
+    pub node: Option<usize>,
+}
+
+/// The sky seen by rays that leave the scene, blended by height from `nadir` straight down to
+/// `zenith` straight up.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Environment {
+    pub nadir: Vec4,
+    pub zenith: Vec4,
+    /// Scales the whole sky.
+    pub intensity: f32,
+}
+
+impl Default for Environment {
+    fn default() -> Environment {
+        Environment {
+            nadir: Vec4::new(1.0, 1.0, 1.0, 0.0),
+            zenith: Vec4::new(0.3, 0.5, 1.0, 0.0),
+            intensity: 1.0,
+        }
+    }
+}
+
+/// Where the camera is and where it looks, as saved with a scene.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Viewpoint {
+    pub position: Vec4,
+    /// Rotation about the horizontal axis in radians, positive looking down.
+    pub pitch: f32,
+    /// Rotation about the vertical axis in radians.
+    pub yaw: f32,
+    /// Zoom factor of the camera; larger values narrow the view.
+    pub zoom: f32,
+}
+
+impl Default for Viewpoint {
+    /// Where the tracer starts without one: at the origin, looking down the negative z axis.
+    fn default() -> Viewpoint {
+        Viewpoint {
+            position: Vec4::zero(),
+            pitch: 0.0,
+            yaw: 0.0,
+            zoom: 10.0,
+        }
+    }
+}
+
+impl Viewpoint {
+    /// A camera placed here, as `PathTracer::set_viewpoint` places its own. The zoom is kept
+    /// separately.
+    pub fn camera(&self) -> Camera {
+        let mut camera = Camera::new(self.position);
+        camera.rotate(self.pitch, self.yaw);
+        camera.set_w();
+        camera
+    }
+}
+
+/// Primitives and the materials they use. Names identify objects and materials in Cryptomattes and
+/// debug output.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Scene {
+    /// Primitives placed directly in the world.
+    pub primitives: Vec<Primitive>,
+    pub materials: Vec<Material>,
+    pub geometries: Vec<Geometry>,
+    /// The scene graph, which places instances of `geometries` in the world.
+    pub nodes: Vec<Node>,
+    pub environment: Environment,
+    /// Settings the tracer starts with when rendering this scene.
+    pub settings: RenderSettings,
+    /// Camera the tracer starts with, or `None` for `Viewpoint::default`.
+    pub camera: Option<Viewpoint>,
+}
+
+/// Mirrors `Material` in shaders.wgsl.
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub(super) struct GpuMaterial {
+    color: [f32; 4],
+    reflections: f32,
+    _pad0: [f32; 3],
+    emission: [f32; 3],
+    transparency: f32,
+    refractive_index: f32,
+    _pad1: [f32; 3],
+}
+
+/// Mirrors `Primitive` in shaders.wgsl, which describes how each `kind` uses the fields. Materials
+/// are copied into each primitive so that a hit carries everything the integrator needs.
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub(super) struct GpuPrimitive {
+    pub position: [f32; 3],
+    pub radius: f32,
+    pub u: [f32; 3],
+    /// One of the `PRIMITIVE_*` constants.
+    pub kind: u32,
+    pub v: [f32; 3],
+    /// Index into `Scene::materials`.
+    pub material_id: u32,
+    material: GpuMaterial,
+}
+
+/// Values of `GpuPrimitive::kind`, matching the `PRIMITIVE_*` constants in shaders.wgsl.
+pub(super) const PRIMITIVE_SPHERE: u32 = 0;
+pub(super) const PRIMITIVE_PLANE: u32 = 1;
+pub(super) const PRIMITIVE_QUAD: u32 = 2;
+pub(super) const PRIMITIVE_DISK: u32 = 3;
+pub(super) const PRIMITIVE_BOX: u32 = 4;
+pub(super) const PRIMITIVE_CYLINDER: u32 = 5;
+pub(super) const PRIMITIVE_CONE: u32 = 6;
+pub(super) const PRIMITIVE_CAPSULE: u32 = 7;
+pub(super) const PRIMITIVE_TORUS: u32 = 8;
+
+/// Mirrors `Environment` in shaders.wgsl.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub(super) struct GpuEnvironment {
+    nadir: [f32; 3],
+    intensity: f32,
+    zenith: [f32; 3],
+    _pad: f32,
+}
+
+/// The TOML scene file layout. Primitives refer to materials by name and pick their shape with
+/// `type`: a `sphere` with a `center` and `radius`, a `plane` through `point` with a `normal`, a
+/// `quad` with a `corner` and edges `u` and `v`, a `disk` with a `center`, `normal` and `radius`,
+/// a `box` from `min` to `max`, a `cylinder` or `capsule` from `base` to `base + axis` with a
+/// `radius`, a `cone` from `base` to `base + axis` with a `base_radius` and an optional
+/// `top_radius`, or a `torus` around `center` and `axis` with a `major_radius` and `minor_radius`.
+/// Nodes place them with any rotation and scale. Files written before there were other shapes list
+/// spheres in `[[spheres]]` tables without a `type`, which are read before the `[[primitives]]`.
+///
+/// The optional `[settings]` and `[environment]` tables override individual `RenderSettings` and
+/// `Environment` fields. The optional `[camera]` table places the camera, with angles in degrees.
+/// Geometries group primitives in a local space, and nodes draw them with a transform relative to
+/// an earlier parent node, again with rotations in degrees about x, then y, then z:
+///
+/// ```toml
+/// [settings]
+/// max_bounces = 16
+///
+/// [environment]
+/// zenith = [0.1, 0.2, 0.6]
+///
+/// [camera]
+/// position = [0.0, 0.05, 0.0]
+/// pitch = 5.0
+/// yaw = 0.0
+/// zoom = 10.0
+///
+/// [[materials]]
+/// name = "glass"
+/// color = [1.0, 1.0, 1.0]
+/// reflections = 1.0
+/// transparency = 0.6
+/// refractive_index = 1.6
+///
+/// [[primitives]]
+/// type = "sphere"
+/// name = "glass ball"
+/// center = [0.0, 0.02, -2.5]
+/// radius = 0.05
+/// material = "glass"
+///
+/// [[primitives]]
+/// type = "plane"
+/// name = "ground"
+/// point = [0.0, 0.0, 0.0]
+/// normal = [0.0, 1.0, 0.0]
+/// material = "glass"
+///
+/// [[geometries]]
+/// name = "pebble"
+///
+/// [[geometries.primitives]]
+/// type = "sphere"
+/// name = "pebble"
+/// center = [0.0, 0.0, 0.0]
+/// radius = 1.0
+/// material = "glass"
+///
+/// [[nodes]]
+/// name = "pile"
+/// translation = [0.2, 0.0, -2.5]
+/// rotation = [0.0, 45.0, 0.0]
+///
+/// [[nodes]]
+/// name = "pebble 1"
+/// parent = "pile"
+/// geometry = "pebble"
+/// scale = [0.02, 0.01, 0.02]
+/// ```
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    settings: SettingsFile,
+    #[serde(default)]
+    environment: EnvironmentFile,
+    camera: Option<CameraFile>,
+    #[serde(default)]
+    materials: Vec<MaterialFile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    spheres: Vec<SphereFile>,
+    #[serde(default)]
+    primitives: Vec<PrimitiveFile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    geometries: Vec<GeometryFile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    nodes: Vec<NodeFile>,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(deny_unknown_fields)]
+struct SettingsFile {
+    max_bounces: Option<u32>,
+    samples: Option<u32>,
+    max_transparent_bounces: Option<u32>,
+    anti_aliasing: Option<bool>,
+    anti_aliasing_radius: Option<u32>,
+    anti_aliasing_weight: Option<f32>,
+    seed: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentFile {
+    nadir: Option<[f32; 3]>,
+    zenith: Option<[f32; 3]>,
+    intensity: Option<f32>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct CameraFile {
+    position: [f32; 3],
+    #[serde(default)]
+    pitch: f32,
+    #[serde(default)]
+    yaw: f32,
+    zoom: f32,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialFile {
+    name: String,
+    color: [f32; 3],
+    #[serde(default)]
+    reflections: f32,
+    #[serde(default)]
+    emission: [f32; 3],
+    #[serde(default)]
+    transparency: f32,
+    #[serde(default)]
+    refractive_index: f32,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct SphereFile {
+    name: String,
+    center: [f32; 3],
+    radius: f32,
+    material: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum PrimitiveFile {
+    Sphere { name: String, center: [f32; 3], radius: f32, material: String },
+    Plane { name: String, point: [f32; 3], normal: [f32; 3], material: String },
+    Quad { name: String, corner: [f32; 3], u: [f32; 3], v: [f32; 3], material: String },
+    Disk { name: String, center: [f32; 3], normal: [f32; 3], radius: f32, material: String },
+    Box { name: String, min: [f32; 3], max: [f32; 3], material: String },
+    Cylinder { name: String, base: [f32; 3], axis: [f32; 3], radius: f32, material: String },
+    Cone {
+        name: String,
+        base: [f32; 3],
+        axis: [f32; 3],
+        base_radius: f32,
+        #[serde(default)]
+        top_radius: f32,
+        material: String,
+    },
+    Capsule { name: String, base: [f32; 3], axis: [f32; 3], radius: f32, material: String },
+    Torus { name: String, center: [f32; 3], axis: [f32; 3], major_radius: f32, minor_radius: f32, material: String },
+}
+
+impl From<SphereFile> for PrimitiveFile {
+    fn from(sphere: SphereFile) -> PrimitiveFile {
+        let SphereFile { name, center, radius, material } = sphere;
+        PrimitiveFile::Sphere { name, center, radius, material }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct GeometryFile {
+    name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    spheres: Vec<SphereFile>,
+    #[serde(default)]
+    primitives: Vec<PrimitiveFile>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct NodeFile {
+    name: String,
+    parent: Option<String>,
+    geometry: Option<String>,
+    #[serde(default)]
+    translation: [f32; 3],
+    #[serde(default)]
+    rotation: [f32; 3],
+    #[serde(default = "unit_scale")]
+    scale: [f32; 3],
+}
+
+fn unit_scale() -> [f32; 3] {
+    [1.0; 3]
+}
+
+impl Material {
+    /// An opaque, non-emissive material.
+    pub fn new(name: &str, color: Vec4, reflections: f32) -> Material {
+        Material {
+            name: name.to_string(),
+            color,
+            reflections,
+            emission: Vec4::zero(),
+            transparency: 0.0,
+            refractive_index: 0.0,
+        }
+    }
+
+    fn to_gpu(&self) -> GpuMaterial {
+        GpuMaterial {
+            color: [self.color.x(), self.color.y(), self.color.z(), self.color.w()],
+            reflections: self.reflections,
+            _pad0: [0.0; 3],
+            emission: [self.emission.x(), self.emission.y(), self.emission.z()],
+            transparency: self.transparency,
+            refractive_index: self.refractive_index,
+            _pad1: [0.0; 3],
+        }
+    }
+}
+
+impl Primitive {
+    pub fn new(name: &str, shape: Shape, material: usize) -> Primitive {
+        Primitive {
+            name: name.to_string(),
+            shape,
+            material,
+        }
+    }
+
+    pub fn sphere(name: &str, center: Vec3, radius: f32, material: usize) -> Primitive {
+        Primitive::new(name, Shape::Sphere { center, radius }, material)
+    }
+
+    pub(super) fn to_gpu(&self, materials: &[Material]) -> GpuPrimitive {
+        let none = Vec3::zero();
+        let (kind, position, radius, u, v) = match self.shape {
+            Shape::Sphere { center, radius } => (PRIMITIVE_SPHERE, center, radius, none, none),
+            Shape::Plane { point, normal } => (PRIMITIVE_PLANE, point, 0.0, normal.normalized(), none),
+            Shape::Quad { corner, u, v } => (PRIMITIVE_QUAD, corner, 0.0, u, v),
+            Shape::Disk { center, normal, radius } => (PRIMITIVE_DISK, center, radius, normal.normalized(), none),
+            Shape::Box { min, max } => (PRIMITIVE_BOX, min, 0.0, max, none),
+            Shape::Cylinder { base, axis, radius } => (PRIMITIVE_CYLINDER, base, radius, axis, none),
+            Shape::Cone { base, axis, base_radius, top_radius } => {
+                (PRIMITIVE_CONE, base, base_radius, axis, Vec3::new(top_radius, 0.0, 0.0))
+            }
+            Shape::Capsule { base, axis, radius } => (PRIMITIVE_CAPSULE, base, radius, axis, none),
+            Shape::Torus { center, axis, major_radius, minor_radius } => {
+                (PRIMITIVE_TORUS, center, major_radius, axis.normalized(), Vec3::new(minor_radius, 0.0, 0.0))
+            }
+        };
+        GpuPrimitive {
+            position: position.into(),
+            radius,
+            u: u.into(),
+            kind,
+            v: v.into(),
+            material_id: self.material as u32,
+            material: materials[self.material].to_gpu(),
+        }
+    }
+}
+
+impl Shape {
+    /// The middle of the shape: the point a plane passes through, or the center of the others.
+    pub fn center(&self) -> Vec3 {
+        match *self {
+            Shape::Sphere { center, .. } | Shape::Disk { center, .. } => center,
+            Shape::Plane { point, .. } => point,
+            Shape::Quad { corner, u, v } => corner + (u + v) / 2.0,
+            Shape::Box { min, max } => (min + max) / 2.0,
+            Shape::Cylinder { base, axis, .. } | Shape::Cone { base, axis, .. } | Shape::Capsule { base, axis, .. } => {
+                base + axis / 2.0
+            }
+            Shape::Torus { center, .. } => center,
+        }
+    }
+
+    /// The radius of the smallest sphere around `center` that holds the shape, or 1 for a plane,
+    /// which has no size.
+    pub fn size(&self) -> f32 {
+        match *self {
+            Shape::Sphere { radius, .. } | Shape::Disk { radius, .. } => radius,
+            Shape::Plane { .. } => 1.0,
+            Shape::Quad { u, v, .. } => (u + v).length().max((u - v).length()) / 2.0,
+            Shape::Box { min, max } => (max - min).length() / 2.0,
+            Shape::Cylinder { axis, radius, .. } => (axis / 2.0).length().hypot(radius),
+            Shape::Cone { axis, base_radius, top_radius, .. } => (axis / 2.0).length().hypot(base_radius.max(top_radius)),
+            Shape::Capsule { axis, radius, .. } => axis.length() / 2.0 + radius,
+            Shape::Torus { major_radius, minor_radius, .. } => major_radius + minor_radius,
+        }
+    }
+
+    /// The shape moved to `center` and scaled about it to `size`, as `center` and `size` measure
+    /// them.
+    pub fn placed(&self, center: Vec3, size: f32) -> Shape {
+        let scale = size / self.size();
+        match *self {
+            Shape::Sphere { radius, .. } => Shape::Sphere { center, radius: radius * scale },
+            Shape::Plane { normal, .. } => Shape::Plane { point: center, normal },
+            Shape::Quad { u, v, .. } => Shape::Quad {
+                corner: center - (u + v) * scale / 2.0,
+                u: u * scale,
+                v: v * scale,
+            },
+            Shape::Disk { normal, radius, .. } => Shape::Disk { center, normal, radius: radius * scale },
+            Shape::Box { min, max } => {
+                let half = (max - min) * scale / 2.0;
+                Shape::Box { min: center - half, max: center + half }
+            }
+            Shape::Cylinder { axis, radius, .. } => Shape::Cylinder {
+                base: center - axis * scale / 2.0,
+                axis: axis * scale,
+                radius: radius * scale,
+            },
+            Shape::Cone { axis, base_radius, top_radius, .. } => Shape::Cone {
+                base: center - axis * scale / 2.0,
+                axis: axis * scale,
+                base_radius: base_radius * scale,
+                top_radius: top_radius * scale,
+            },
+            Shape::Capsule { axis, radius, .. } => Shape::Capsule {
+                base: center - axis * scale / 2.0,
+                axis: axis * scale,
+                radius: radius * scale,
+            },
+            Shape::Torus { axis, major_radius, minor_radius, .. } => Shape::Torus {
+                center,
+                axis,
+                major_radius: major_radius * scale,
+                minor_radius: minor_radius * scale,
+            },
+        }
+    }
+
+    /// The shape turned by `rotation` about its `center`. Spheres look the same and boxes stay
+    /// along the axes, so turning them changes nothing.
+    pub fn rotated(&self, rotation: Quat) -> Shape {
+        let center = self.center();
+        let turn = |point: Vec3| center + rotation.rotate(point - center);
+        match *self {
+            Shape::Sphere { .. } | Shape::Box { .. } => *self,
+            Shape::Plane { point, normal } => Shape::Plane { point, normal: rotation.rotate(normal) },
+            Shape::Quad { corner, u, v } => Shape::Quad {
+                corner: turn(corner),
+                u: rotation.rotate(u),
+                v: rotation.rotate(v),
+            },
+            Shape::Disk { center, normal, radius } => Shape::Disk { center, normal: rotation.rotate(normal), radius },
+            Shape::Cylinder { base, axis, radius } => Shape::Cylinder {
+                base: turn(base),
+                axis: rotation.rotate(axis),
+                radius,
+            },
+            Shape::Cone { base, axis, base_radius, top_radius } => Shape::Cone {
+                base: turn(base),
+                axis: rotation.rotate(axis),
+                base_radius,
+                top_radius,
+            },
+            Shape::Capsule { base, axis, radius } => Shape::Capsule {
+                base: turn(base),
+                axis: rotation.rotate(axis),
+                radius,
+            },
+            Shape::Torus { center, axis, major_radius, minor_radius } => Shape::Torus {
+                center,
+                axis: rotation.rotate(axis),
+                major_radius,
+                minor_radius,
+            },
+        }
+    }
+}
+
+impl Node {
+    /// A node drawing `geometry` at `transform` in the world.
+    pub fn new(name: &str, transform: Transform, geometry: Option<usize>) -> Node {
+        Node {
+            name: name.to_string(),
+            transform,
+            parent: None,
+            geometry,
+        }
+    }
+}
+
+impl Environment {
+    pub(super) fn to_gpu(self) -> GpuEnvironment {
+        GpuEnvironment {
+            nadir: [self.nadir.x(), self.nadir.y(), self.nadir.z()],
+            intensity: self.intensity,
+            zenith: [self.zenith.x(), self.zenith.y(), self.zenith.z()],
+            _pad: 0.0,
+        }
+    }
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        Scene::default()
+    }
+
+    /// Adds a material and returns its index for `Primitive::material`.
+    pub fn add_material(&mut self, material: Material) -> usize {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    pub fn add_primitive(&mut self, primitive: Primitive) {
+        self.primitives.push(primitive);
+    }
+
+    /// Adds a geometry and returns its index for `Node::geometry`.
+    pub fn add_geometry(&mut self, geometry: Geometry) -> usize {
+        self.geometries.push(geometry);
+        self.geometries.len() - 1
+    }
+
+    /// Adds a node and returns its index for `Node::parent`. Its parent must already be in the
+    /// scene.
+    pub fn add_node(&mut self, node: Node) -> usize {
+        assert!(node.parent.is_none_or(|parent| parent < self.nodes.len()), "parent of {:?} is not in the scene", node.name);
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    /// Where each node places its children and geometry in the world.
+    pub fn world_transforms(&self) -> Vec<Mat4> {
+        let mut transforms: Vec<Mat4> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let local = node.transform.to_mat4();
+            transforms.push(match node.parent {
+                Some(parent) => transforms[parent] * local,
+                None => local,
+            });
+        }
+        transforms
+    }
+
+    /// Every object in the scene, in object ID order.
+    pub fn objects(&self) -> Vec<Object<'_>> {
+        let loose = self.primitives.iter().map(|primitive| Object {
+            name: &primitive.name,
+            transform: Mat4::identity(),
+            primitives: std::slice::from_ref(primitive),
+            node: None,
+        });
+        let transforms = self.world_transforms();
+        let instances = self.nodes.iter().enumerate().filter_map(|(index, node)| {
+            Some(Object {
+                name: &node.name,
+                transform: transforms[index],
+                primitives: &self.geometries[node.geometry?].primitives,
+                node: Some(index),
+            })
+        });
+        loose.chain(instances).collect()
+    }
+
+    /// Reads a TOML scene file.
+    pub fn load(path: &Path) -> Result<Scene> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Scene::from_toml(&text).with_context(|| format!("invalid scene file {}", path.display()))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Scene> {
+        let file: SceneFile = toml::from_str(text)?;
+        let mut scene = Scene::new();
+        let defaults = RenderSettings::default();
+        let settings = file.settings;
+        scene.settings = RenderSettings {
+            max_bounces: settings.max_bounces.unwrap_or(defaults.max_bounces),
+            samples: settings.samples.unwrap_or(defaults.samples),
+            max_transparent_bounces: settings
+                .max_transparent_bounces
+                .unwrap_or(defaults.max_transparent_bounces),
+            anti_aliasing: settings.anti_aliasing.unwrap_or(defaults.anti_aliasing),
+            anti_aliasing_radius: settings.anti_aliasing_radius.unwrap_or(defaults.anti_aliasing_radius),
+            anti_aliasing_weight: settings.anti_aliasing_weight.unwrap_or(defaults.anti_aliasing_weight),
+            seed: settings.seed.unwrap_or(defaults.seed),
+        };
+        scene.settings.validate()?;
+        let defaults = Environment::default();
+        let environment = file.environment;
+        let color = |color: Option<[f32; 3]>, default| match color {
+            Some([r, g, b]) => Vec4::new(r, g, b, 0.0),
+            None => default,
+        };
+        scene.environment = Environment {
+            nadir: color(environment.nadir, defaults.nadir),
+            zenith: color(environment.zenith, defaults.zenith),
+            intensity: environment.intensity.unwrap_or(defaults.intensity),
+        };
+        if !(scene.environment.intensity >= 0.0 && scene.environment.intensity.is_finite()) {
+            bail!("the environment intensity must be a non-negative number");
+        }
+        if let Some(camera) = file.camera {
+            if !(camera.zoom > 0.0 && camera.zoom.is_finite()) {
+                bail!("the camera zoom must be a positive number");
+            }
+            if !(-90.0..=90.0).contains(&camera.pitch) {
+                bail!("the camera pitch must be between -90 and 90 degrees");
+            }
+            let [x, y, z] = camera.position;
+            scene.camera = Some(Viewpoint {
+                position: Vec4::new(x, y, z, 0.0),
+                pitch: camera.pitch.to_radians(),
+                yaw: camera.yaw.to_radians(),
+                zoom: camera.zoom,
+            });
+        }
+        for material in file.materials {
+            if scene.material_index(&material.name).is_some() {
+                bail!("material {:?} is defined twice", material.name);
+            }
+            let [r, g, b] = material.color;
+            let [er, eg, eb] = material.emission;
+            scene.add_material(Material {
+                transparency: material.transparency,
+                refractive_index: material.refractive_index,
+                emission: Vec4::new(er, eg, eb, 0.0),
+                ..Material::new(&material.name, Vec4::new(r, g, b, 1.0), material.reflections)
+            });
+        }
+        for primitive in file.spheres.into_iter().map(PrimitiveFile::from).chain(file.primitives) {
+            let primitive = scene.primitive_from_file(primitive)?;
+            scene.add_primitive(primitive);
+        }
+        for geometry in file.geometries {
+            if scene.geometries.iter().any(|other| other.name == geometry.name) {
+                bail!("geometry {:?} is defined twice", geometry.name);
+            }
+            let primitives = geometry
+                .spheres
+                .into_iter()
+                .map(PrimitiveFile::from)
+                .chain(geometry.primitives)
+                .map(|primitive| scene.primitive_from_file(primitive))
+                .collect::<Result<_>>()?;
+            scene.add_geometry(Geometry { name: geometry.name, primitives });
+        }
+        for node in file.nodes {
+            if scene.node_index(&node.name).is_some() {
+                bail!("node {:?} is defined twice", node.name);
+            }
+            let parent = match &node.parent {
+                Some(parent) => Some(scene.node_index(parent).with_context(|| {
+                    format!("node {:?} has unknown parent {parent:?}; parents must come first", node.name)
+                })?),
+                None => None,
+            };
+            let geometry = match &node.geometry {
+                Some(geometry) => Some(
+                    scene
+                        .geometries
+                        .iter()
+                        .position(|other| &other.name == geometry)
+                        .with_context(|| format!("node {:?} uses unknown geometry {geometry:?}", node.name))?,
+                ),
+                None => None,
+            };
+            if node.scale.contains(&0.0) {
+                bail!("node {:?} has a zero scale", node.name);
+            }
+            let [x, y, z] = node.rotation.map(f32::to_radians);
+            scene.add_node(Node {
+                parent,
+                ..Node::new(
+                    &node.name,
+                    Transform {
+                        translation: node.translation.into(),
+                        rotation: Quat::from_euler(x, y, z),
+                        scale: node.scale.into(),
+                    },
+                    geometry,
+                )
+            });
+        }
+        Ok(scene)
+    }
+
+    fn primitive_from_file(&self, primitive: PrimitiveFile) -> Result<Primitive> {
+        let (kind, name, material, shape) = match primitive {
+            PrimitiveFile::Sphere { name, center, radius, material } => {
+                ("sphere", name, material, Shape::Sphere { center: center.into(), radius })
+            }
+            PrimitiveFile::Plane { name, point, normal, material } => {
+                ("plane", name, material, Shape::Plane { point: point.into(), normal: normal.into() })
+            }
+            PrimitiveFile::Quad { name, corner, u, v, material } => {
+                ("quad", name, material, Shape::Quad { corner: corner.into(), u: u.into(), v: v.into() })
+            }
+            PrimitiveFile::Disk { name, center, normal, radius, material } => {
+                ("disk", name, material, Shape::Disk { center: center.into(), normal: normal.into(), radius })
+            }
+            PrimitiveFile::Box { name, min, max, material } => {
+                ("box", name, material, Shape::Box { min: min.into(), max: max.into() })
+            }
+            PrimitiveFile::Cylinder { name, base, axis, radius, material } => {
+                ("cylinder", name, material, Shape::Cylinder { base: base.into(), axis: axis.into(), radius })
+            }
+            PrimitiveFile::Cone { name, base, axis, base_radius, top_radius, material } => (
+                "cone",
+                name,
+                material,
+                Shape::Cone { base: base.into(), axis: axis.into(), base_radius, top_radius },
+            ),
+            PrimitiveFile::Capsule { name, base, axis, radius, material } => {
+                ("capsule", name, material, Shape::Capsule { base: base.into(), axis: axis.into(), radius })
+            }
+            PrimitiveFile::Torus { name, center, axis, major_radius, minor_radius, material } => (
+                "torus",
+                name,
+                material,
+                Shape::Torus { center: center.into(), axis: axis.into(), major_radius, minor_radius },
+            ),
+        };
+        let material = self
+            .material_index(&material)
+            .with_context(|| format!("{kind} {name:?} uses unknown material {material:?}"))?;
+        match shape {
+            Shape::Sphere { radius, .. }
+            | Shape::Disk { radius, .. }
+            | Shape::Cylinder { radius, .. }
+            | Shape::Capsule { radius, .. }
+                if radius <= 0.0 =>
+            {
+                bail!("{kind} {name:?} has a non-positive radius");
+            }
+            Shape::Torus { major_radius, minor_radius, .. } if major_radius <= 0.0 || minor_radius <= 0.0 => {
+                bail!("torus {name:?} has a non-positive radius");
+            }
+            Shape::Cone { base_radius, top_radius, .. }
+                if base_radius < 0.0 || top_radius < 0.0 || base_radius.max(top_radius) <= 0.0 =>
+            {
+                bail!("cone {name:?} needs non-negative radii that are not both 0");
+            }
+            Shape::Cylinder { axis, .. } | Shape::Cone { axis, .. } | Shape::Capsule { axis, .. } | Shape::Torus { axis, .. }
+                if axis == Vec3::zero() =>
+            {
+                bail!("{kind} {name:?} has a zero axis");
+            }
+            Shape::Plane { normal, .. } | Shape::Disk { normal, .. } if normal == Vec3::zero() => {
+                bail!("{kind} {name:?} has a zero normal");
+            }
+            Shape::Quad { u, v, .. } if u.cross(&v) == Vec3::zero() => {
+                bail!("quad {name:?} has parallel edges");
+            }
+            Shape::Box { min, max } if (0..3).any(|axis| min.get(axis) >= max.get(axis)) => {
+                bail!("box {name:?} has a min corner that is not below its max corner");
+            }
+            _ => {}
+        }
+        Ok(Primitive::new(&name, shape, material))
+    }
+
+    /// Writes the scene to a TOML scene file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_toml()?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// The scene in the scene file format, which `from_toml` reads back unchanged.
+    pub fn to_toml(&self) -> Result<String> {
+        let rgb = |v: Vec4| [v.x(), v.y(), v.z()];
+        let settings = self.settings;
+        let file = SceneFile {
+            settings: SettingsFile {
+                max_bounces: Some(settings.max_bounces),
+                samples: Some(settings.samples),
+                max_transparent_bounces: Some(settings.max_transparent_bounces),
+                anti_aliasing: Some(settings.anti_aliasing),
+                anti_aliasing_radius: Some(settings.anti_aliasing_radius),
+                anti_aliasing_weight: Some(settings.anti_aliasing_weight),
+                seed: Some(settings.seed),
+            },
+            environment: EnvironmentFile {
+                nadir: Some(rgb(self.environment.nadir)),
+                zenith: Some(rgb(self.environment.zenith)),
+                intensity: Some(self.environment.intensity),
+            },
+            camera: self.camera.map(|camera| CameraFile {
+                position: rgb(camera.position),
+                pitch: camera.pitch.to_degrees(),
+                yaw: camera.yaw.to_degrees(),
+                zoom: camera.zoom,
+            }),
+            materials: self
+                .materials
+                .iter()
+                .map(|material| MaterialFile {
+                    name: material.name.clone(),
+                    color: rgb(material.color),
+                    reflections: material.reflections,
+                    emission: rgb(material.emission),
+                    transparency: material.transparency,
+                    refractive_index: material.refractive_index,
+                })
+                .collect(),
+            spheres: Vec::new(),
+            primitives: self.primitives.iter().map(|primitive| self.primitive_file(primitive)).collect(),
+            geometries: self
+                .geometries
+                .iter()
+                .map(|geometry| GeometryFile {
+                    name: geometry.name.clone(),
+                    spheres: Vec::new(),
+                    primitives: geometry.primitives.iter().map(|primitive| self.primitive_file(primitive)).collect(),
+                })
+                .collect(),
+            nodes: self
+                .nodes
+                .iter()
+                .map(|node| NodeFile {
+                    name: node.name.clone(),
+                    parent: node.parent.map(|parent| self.nodes[parent].name.clone()),
+                    geometry: node.geometry.map(|geometry| self.geometries[geometry].name.clone()),
+                    translation: node.transform.translation.into(),
+                    rotation: node.transform.rotation.to_euler().map(f32::to_degrees),
+                    scale: node.transform.scale.into(),
+                })
+                .collect(),
+        };
+        let mut value = toml::Value::try_from(file)?;
+        shorten_floats(&mut value);
+        Ok(toml::to_string(&value)?)
+    }
+
+    fn primitive_file(&self, primitive: &Primitive) -> PrimitiveFile {
+        let name = primitive.name.clone();
+        let material = self.materials[primitive.material].name.clone();
+        match primitive.shape {
+            Shape::Sphere { center, radius } => PrimitiveFile::Sphere { name, center: center.into(), radius, material },
+            Shape::Plane { point, normal } => {
+                PrimitiveFile::Plane { name, point: point.into(), normal: normal.into(), material }
+            }
+            Shape::Quad { corner, u, v } => {
+                PrimitiveFile::Quad { name, corner: corner.into(), u: u.into(), v: v.into(), material }
+            }
+            Shape::Disk { center, normal, radius } => {
+                PrimitiveFile::Disk { name, center: center.into(), normal: normal.into(), radius, material }
+            }
+            Shape::Box { min, max } => PrimitiveFile::Box { name, min: min.into(), max: max.into(), material },
+            Shape::Cylinder { base, axis, radius } => {
+                PrimitiveFile::Cylinder { name, base: base.into(), axis: axis.into(), radius, material }
+            }
+            Shape::Cone { base, axis, base_radius, top_radius } => PrimitiveFile::Cone {
+                name,
+                base: base.into(),
+                axis: axis.into(),
+                base_radius,
+                top_radius,
+                material,
+            },
+            Shape::Capsule { base, axis, radius } => {
+                PrimitiveFile::Capsule { name, base: base.into(), axis: axis.into(), radius, material }
+            }
+            Shape::Torus { center, axis, major_radius, minor_radius } => PrimitiveFile::Torus {
+                name,
+                center: center.into(),
+                axis: axis.into(),
+                major_radius,
+                minor_radius,
+                material,
+            },
+        }
+    }
+
+    /// Whether both scenes name the same objects and materials in the same order.
+    pub(super) fn same_names(&self, other: &Scene) -> bool {
+        self.objects().iter().map(|object| object.name).eq(other.objects().iter().map(|object| object.name))
+            && self
+                .materials
+                .iter()
+                .map(|material| &material.name)
+                .eq(other.materials.iter().map(|material| &material.name))
+    }
+
+    fn material_index(&self, name: &str) -> Option<usize> {
+        self.materials.iter().position(|material| material.name == name)
+    }
+
+    fn node_index(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name == name)
+    }
+
+    /// The spheres on a green ground that the tracer has always shown.
+    pub fn default_scene() -> Scene {
+        let materials = vec![
+            Material::new("magenta", Vec4::new(0.9, 0.0, 0.9, 1.0), 0.0),
+            Material::new("mirror", Vec4::new(0.9, 0.9, 0.9, 1.0), 1.0),
+            Material::new("grass", Vec4::new(0.1, 0.9, 0.1, 1.0), 0.0),
+            Material {
+                transparency: 0.6,
+                refractive_index: 1.6,
+                ..Material::new("glass", Vec4::all(1.0), 1.0)
+            },
+            Material::new("glossy yellow", Vec4::new(0.9, 0.9, 0.0, 1.0), 0.2),
+            Material::new("cyan", Vec4::new(0.0, 1.0, 1.0, 1.0), 0.0),
+            Material::new("yellow", Vec4::new(1.0, 1.0, 0.0, 1.0), 0.0),
+        ];
+        let primitives = vec![
+            Primitive::sphere("magenta ball", Vec3::new(-0.05, 0.02, -2.7), 0.05, 0),
+            Primitive::sphere("mirror ball", Vec3::new(0.1, 0.03, -2.4), 0.04, 1),
+            // Just below the camera, which starts at the origin.
+            Primitive::new("ground", Shape::Plane { point: Vec3::new(0.0, -0.05, 0.0), normal: Vec3::new(0.0, 1.0, 0.0) }, 2),
+            Primitive::sphere("glass ball", Vec3::new(0.0, 0.02, -2.5), 0.05, 3),
+            Primitive::sphere("glossy ball", Vec3::new(-0.05, 0.07, -2.6), 0.04, 4),
+            Primitive::sphere("small magenta ball", Vec3::new(0.05, 0.07, -2.3), 0.04, 0),
+            Primitive::sphere("cyan ball", Vec3::new(-0.3, 0.09, -2.5), 0.1, 5),
+            Primitive::sphere("yellow ball", Vec3::new(0.3, 0.11, -2.6), 0.07, 6),
+        ];
+        Scene {
+            primitives,
+            materials,
+            geometries: Vec::new(),
+            nodes: Vec::new(),
+            environment: Environment::default(),
+            settings: RenderSettings::default(),
+            camera: None,
+        }
+    }
+}
+
+/// Serde writes `f32`s as the `f64`s they widen to, so 0.9 would be saved as 0.8999999761581421.
+/// Rounds every float back to the shortest decimal that reads as the same `f32`.
+fn shorten_floats(value: &mut toml::Value) {
+    match value {
+        toml::Value::Float(float) => {
+            *float = (*float as f32).to_string().parse().unwrap_or(*float);
+        }
+        toml::Value::Array(values) => values.iter_mut().for_each(shorten_floats),
+        toml::Value::Table(table) => table.iter_mut().for_each(|(_, value)| shorten_floats(value)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::mem::{offset_of, size_of},
+    };
+
+    #[test]
+    fn gpu_primitive_layout() {
+        crate::renderer::tests::assert_matches_shader(
+            "Primitive",
+            size_of::<GpuPrimitive>(),
+            &[
+                ("position", offset_of!(GpuPrimitive, position)),
+                ("radius", offset_of!(GpuPrimitive, radius)),
+                ("u", offset_of!(GpuPrimitive, u)),
+                ("kind", offset_of!(GpuPrimitive, kind)),
+                ("v", offset_of!(GpuPrimitive, v)),
+                ("material_id", offset_of!(GpuPrimitive, material_id)),
+                ("material", offset_of!(GpuPrimitive, material)),
+            ],
+        );
+    }
+
+    #[test]
+    fn gpu_environment_layout() {
+        crate::renderer::tests::assert_matches_shader(
+            "Environment",
+            size_of::<GpuEnvironment>(),
+            &[
+                ("nadir", offset_of!(GpuEnvironment, nadir)),
+                ("intensity", offset_of!(GpuEnvironment, intensity)),
+                ("zenith", offset_of!(GpuEnvironment, zenith)),
+                ("_pad", offset_of!(GpuEnvironment, _pad)),
+            ],
+        );
+    }
+
+    #[test]
+    fn scene_file_round_trip() {
+        let mut scene = Scene::default_scene();
+        scene.camera = Some(Viewpoint {
+            position: Vec4::new(0.1, 0.05, -0.2, 0.0),
+            pitch: 0.1,
+            yaw: -0.3,
+            zoom: 12.5,
+        });
+        scene.materials[1].emission = Vec4::new(2.0, 1.5, 0.0, 0.0);
+        let (u, v) = (Vec3::new(0.1, 0.0, 0.0), Vec3::new(0.0, 0.1, -0.05));
+        scene.add_primitive(Primitive::new("lamp", Shape::Quad { corner: Vec3::new(0.0, 0.3, -2.5), u, v }, 1));
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        scene.add_primitive(Primitive::new("coin", Shape::Disk { center: Vec3::new(0.2, 0.05, -2.5), normal, radius: 0.03 }, 4));
+        let (min, max) = (Vec3::new(-0.2, 0.0, -2.4), Vec3::new(-0.1, 0.1, -2.3));
+        scene.add_primitive(Primitive::new("crate", Shape::Box { min, max }, 5));
+        let (base, axis) = (Vec3::new(0.3, 0.0, -2.2), Vec3::new(0.0, 0.1, 0.0));
+        scene.add_primitive(Primitive::new("shaft", Shape::Cylinder { base, axis, radius: 0.02 }, 5));
+        let shape = Shape::Cone { base, axis, base_radius: 0.03, top_radius: 0.0 };
+        scene.add_primitive(Primitive::new("tip", shape, 5));
+        scene.add_primitive(Primitive::new("pin", Shape::Capsule { base, axis, radius: 0.01 }, 1));
+        let shape = Shape::Torus { center: base, axis, major_radius: 0.04, minor_radius: 0.01 };
+        scene.add_primitive(Primitive::new("ring", shape, 6));
+        let pebble = scene.add_geometry(Geometry {
+            name: "pebble".to_string(),
+            primitives: vec![Primitive::sphere("pebble", Vec3::zero(), 1.0, 3)],
+        });
+        let pile = scene.add_node(Node::new("pile", Transform::identity(), None));
+        scene.add_node(Node {
+            parent: Some(pile),
+            ..Node::new(
+                "pebble 1",
+                Transform {
+                    translation: Vec3::new(0.2, 0.0, -2.5),
+                    rotation: Quat::from_euler(0.5, -0.25, 1.0),
+                    scale: Vec3::new(0.02, 0.01, 0.02),
+                },
+                Some(pebble),
+            )
+        });
+        let text = scene.to_toml().unwrap();
+        assert!(text.contains("color = [0.9, 0.0, 0.9]"), "{text}");
+        let mut loaded = Scene::from_toml(&text).unwrap();
+        let camera = loaded.camera.unwrap();
+        assert!((camera.pitch - 0.1).abs() < 1e-6 && (camera.yaw + 0.3).abs() < 1e-6);
+        // Rotations are saved as angles, which do not convert back exactly.
+        let [a, b] = [&loaded, &scene].map(|scene| scene.world_transforms()[1]);
+        assert!((0..4).all(|i| (a.col(i) - b.col(i)).length() < 1e-6), "{a:?} != {b:?}");
+        loaded.nodes[1].transform.rotation = scene.nodes[1].transform.rotation;
+        assert_eq!(Scene { camera: scene.camera, ..loaded }, scene);
+    }
+
+    #[test]
+    fn rotated_shapes_keep_their_place_and_size() {
+        let (base, axis) = (Vec3::new(0.3, 0.0, -2.2), Vec3::new(0.0, 0.4, 0.0));
+        let shapes = [
+            Shape::Quad { corner: base, u: Vec3::new(0.2, 0.0, 0.0), v: Vec3::new(0.0, 0.1, -0.1) },
+            Shape::Cone { base, axis, base_radius: 0.1, top_radius: 0.05 },
+            Shape::Capsule { base, axis, radius: 0.1 },
+        ];
+        let rotation = Quat::from_euler(0.5, -0.25, 1.0);
+        for shape in shapes {
+            let rotated = shape.rotated(rotation);
+            assert!((rotated.center() - shape.center()).length() < 1e-6, "{rotated:?}");
+            assert!((rotated.size() - shape.size()).abs() < 1e-6, "{rotated:?}");
+            assert!((rotated.rotated(rotation.conjugate()).center() - shape.center()).length() < 1e-6);
+        }
+        let Shape::Cone { axis: turned, .. } = shapes[1].rotated(rotation) else {
+            unreachable!()
+        };
+        assert!((turned - rotation.rotate(axis)).length() < 1e-6);
+    }
+}
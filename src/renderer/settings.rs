@@ -0,0 +1,108 @@
+use {
+    anyhow::{bail, Result},
+    bytemuck::{Pod, Zeroable},
+};
+
+/// Tracer parameters that can change between frames. Changing any of them restarts
+/// accumulation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderSettings {
+    /// Longest path traced per sample.
+    pub max_bounces: u32,
+    /// Samples traced per pixel in each frame.
+    pub samples: u32,
+    /// Most transparent surfaces a path passes through. The path ends at the next one, as it does
+    /// when it runs out of `max_bounces`, which these count towards as well.
+    pub max_transparent_bounces: u32,
+    /// Blurs the displayed image with a box filter. Only affects the viewer.
+    pub anti_aliasing: bool,
+    /// Half the width of the anti-aliasing box, in pixels.
+    pub anti_aliasing_radius: u32,
+    /// Extra weight of the center pixel in the anti-aliasing box.
+    pub anti_aliasing_weight: f32,
+    /// Offsets the random sequence, so renders with different seeds get independent noise.
+    pub seed: u32,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            max_bounces: 100,
+            samples: 1,
+            max_transparent_bounces: 1000,
+            anti_aliasing: false,
+            anti_aliasing_radius: 10,
+            anti_aliasing_weight: 1.0,
+            seed: 0,
+        }
+    }
+}
+
+/// Mirrors `RenderSettings` in shaders.wgsl. The seed only offsets the frame index on the CPU.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub(super) struct GpuSettings {
+    max_bounces: u32,
+    samples: u32,
+    max_transparent_bounces: u32,
+    anti_aliasing: u32,
+    anti_aliasing_radius: u32,
+    anti_aliasing_weight: f32,
+    _pad: [u32; 2],
+}
+
+impl RenderSettings {
+    /// Checks that the tracer can run with these settings.
+    pub fn validate(&self) -> Result<()> {
+        if self.max_bounces == 0 {
+            bail!("max_bounces must be at least 1");
+        }
+        if self.samples == 0 {
+            bail!("samples must be at least 1");
+        }
+        if self.max_transparent_bounces == 0 {
+            bail!("max_transparent_bounces must be at least 1");
+        }
+        if !(self.anti_aliasing_weight >= 0.0 && self.anti_aliasing_weight.is_finite()) {
+            bail!("anti_aliasing_weight must be a non-negative number");
+        }
+        Ok(())
+    }
+
+    pub(super) fn to_gpu(self) -> GpuSettings {
+        GpuSettings {
+            max_bounces: self.max_bounces,
+            samples: self.samples,
+            max_transparent_bounces: self.max_transparent_bounces,
+            anti_aliasing: self.anti_aliasing as u32,
+            anti_aliasing_radius: self.anti_aliasing_radius,
+            anti_aliasing_weight: self.anti_aliasing_weight,
+            _pad: [0; 2],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::mem::{offset_of, size_of},
+    };
+
+    #[test]
+    fn gpu_settings_layout() {
+        crate::renderer::tests::assert_matches_shader(
+            "RenderSettings",
+            size_of::<GpuSettings>(),
+            &[
+                ("max_bounces", offset_of!(GpuSettings, max_bounces)),
+                ("samples", offset_of!(GpuSettings, samples)),
+                ("max_transparent_bounces", offset_of!(GpuSettings, max_transparent_bounces)),
+                ("anti_aliasing", offset_of!(GpuSettings, anti_aliasing)),
+                ("anti_aliasing_radius", offset_of!(GpuSettings, anti_aliasing_radius)),
+                ("anti_aliasing_weight", offset_of!(GpuSettings, anti_aliasing_weight)),
+                ("_pad", offset_of!(GpuSettings, _pad)),
+            ],
+        );
+    }
+}
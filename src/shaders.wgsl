@@ -5,38 +5,12 @@ var<private> vertices: TriangleVertices = TriangleVertices(
   vec2f( -1.0,  3.0),
 );
 
-const lambertian:f32 = 0.0;
-
-
-var<private> length:i32 = 8;
-var<private> spheres: array<Sphere, 8> = array<Sphere, 8>
-(Sphere(0.05,  vec3(-0.05,0.02,-2.7), Material(vec4(0.9, 0.0, 0.9, 1), lambertian, vec3<f32>(0), 0.0, 0.0)), 
-Sphere(0.04,  vec3(0.1,0.03,-2.4), Material(vec4(0.9, 0.9, 0.9, 1), 1.0, vec3<f32>(0), 0, 0)), 
-Sphere(1,  vec3(0,-1,-3), Material(vec4(0.1, 0.9, 0.1, 1.0), lambertian, vec3<f32>(0,0,0), 0, 0)),
-Sphere(0.05,  vec3(0.0,0.02,-2.5), Material(vec4(1.0), 1.0, vec3<f32>(0), 0.6, 1.6)),
-Sphere(0.04,  vec3(-0.05,0.07,-2.6), Material(vec4(0.9, 0.9, 0.0, 1), lambertian+0.2, vec3<f32>(0), 0, 0)),
-Sphere(0.04,  vec3(0.05,0.07,-2.3), Material(vec4(0.9, 0.0, 0.9, 1), lambertian, vec3<f32>(0), 0.0, 0.00)),
-Sphere(0.1,  vec3(-0.3,0.09,-2.5), Material(vec4(0,1,1, 1), lambertian, 0*vec3<f32>(1), 0, 0)),
-Sphere(0.07,  vec3(0.3,0.11,-2.6), Material(vec4(1,1,0, 1), lambertian, 0*vec3<f32>(1), 0, 0)));
-
 var<private> sunDir:vec3<f32> = vec3<f32>(1,1,1);
 
-var<private> MaxBounces:i32 = 100;
-
-var<private> MaxTransBounces = 1000;
-
 var<private> FOV:f32 = 1;
 
-var<private> Samples:i32 = 1;
-
 var<private> focus_distance = 1.0;
 
-var<private> antiAliasing = false;
-
-var<private> level = 1.0;
-
-var<private> times: i32 = 10;
-
 
 fn rand(p: vec3<f32>) -> f32 {
     let h = sin(dot(p, vec3<f32>(127.1, 311.7, 191.91))) * 43758.5453;
@@ -54,102 +28,322 @@ fn random_direction(seed: vec3<f32>) -> vec3<f32> {
 }
 
 
-struct Sphere{
-  radius: f32,
+// Kinds of `Primitive`. Match the `PRIMITIVE_*` constants on the CPU.
+const PRIMITIVE_SPHERE = 0u;
+const PRIMITIVE_PLANE = 1u;
+const PRIMITIVE_QUAD = 2u;
+const PRIMITIVE_DISK = 3u;
+const PRIMITIVE_BOX = 4u;
+const PRIMITIVE_CYLINDER = 5u;
+const PRIMITIVE_CONE = 6u;
+const PRIMITIVE_CAPSULE = 7u;
+const PRIMITIVE_TORUS = 8u;
+
+// A surface of the kind `kind`, in the local space of its instance. A sphere is centered on
+// `position` with `radius`; a plane passes through `position` with the unit normal `u`; a quad has
+// a corner at `position` and edges `u` and `v`; a disk is centered on `position` with the unit
+// normal `u` and `radius`; a box spans `position` to `u`. Cylinders, cones and capsules run from
+// `position` to `position + u` with `radius` at the base, and a cone has `v.x` at the top. A torus
+// is centered on `position` around the unit axis `u`, with `radius` to the middle of its tube and
+// `v.x` across the tube. Matches `GpuPrimitive` on the CPU.
+struct Primitive{
   position: vec3f,
-  material: Material
+  radius: f32,
+  u: vec3f,
+  kind: u32,
+  v: vec3f,
+  material_id: u32,
+  material: Material,
 }
 struct Ray{
   origin: vec3f,
   dir: vec3f
 }
 
+// Camera position and basis: `u` points right, `v` up and `w` forward. Matches
+// `math::CameraUniforms` on the CPU.
 struct CameraUniforms {
-    origin: vec3<f32>,
-};
-
+  origin: vec4f,
+  u: vec4f,
+  v: vec4f,
+  w: vec4f,
+}
 
+// The layout of the structs shared with the CPU is checked against their Rust mirrors by the
+// `layout` tests.
 struct Uniforms {
   width: u32,
   height: u32,
-  time:f32,
-  _pad:f32,
-  u:vec4f,
-  v:vec4f,
-  w:vec4f,
-  ww:vec4f,
+  // Zoom factor passed to `camera_dir`; larger values narrow the view.
+  fov: f32,
+  // Frame index offset by the seed, hashed into the random numbers.
+  frame: u32,
+  camera: CameraUniforms,
+  debug_mode: u32,
+  // Object outlined in the displayed image, or -1.
+  selected: i32,
+  // Pixel whose first sample is logged to `path_log`, or -1.
+  debug_pixel: vec2<i32>,
 }
+
+// Debug visualizations, selected by `uniforms.debug_mode`. Matches `DebugMode` on the CPU.
+const DEBUG_OFF = 0u;
+const DEBUG_NORMALS = 1u;
+const DEBUG_HIT_DISTANCE = 2u;
+const DEBUG_ALBEDO = 3u;
+const DEBUG_BOUNCES = 4u;
+const DEBUG_PRIMITIVE_ID = 5u;
+const DEBUG_NAN_INF = 6u;
+const DEBUG_VARIANCE = 7u;
+const DEBUG_UV = 8u;
+
+// Bounce count and standard deviation that map to the hot end of the heat maps.
+const DEBUG_MAX_BOUNCES = 16.0;
+const DEBUG_MAX_STD_DEV = 0.25;
 @group(0) @binding(0) var<uniform> uniforms: Uniforms;
+@group(0) @binding(1) var<storage, read> primitives: array<Primitive>;
+
+// Tracer parameters set at runtime. Matches `GpuSettings` on the CPU.
+struct RenderSettings {
+  max_bounces: u32,
+  samples: u32,
+  max_transparent_bounces: u32,
+  // Box blur applied by `display_fs`.
+  anti_aliasing: u32,
+  anti_aliasing_radius: u32,
+  anti_aliasing_weight: f32,
+  _pad: vec2<u32>,
+}
+@group(0) @binding(2) var<uniform> settings: RenderSettings;
+
+// Sky gradient seen by escaping rays. Matches `GpuEnvironment` on the CPU.
+struct Environment {
+  nadir: vec3f,
+  intensity: f32,
+  zenith: vec3f,
+  _pad: f32,
+}
+@group(0) @binding(3) var<uniform> environment: Environment;
+
+// Bounding volume hierarchy node. Matches `GpuBvhNode` on the CPU. Inner nodes have a `count` of
+// 0 and their children at `first` and `first + 1`; leaves hold `count` instances or primitives
+// starting at `first`.
+struct BvhNode {
+  min: vec3f,
+  first: u32,
+  max: vec3f,
+  count: u32,
+}
+
+// An object placing the bottom-level tree at `root` in the world. Matches `GpuInstance` on the
+// CPU.
+struct Instance {
+  to_world: mat4x4f,
+  to_local: mat4x4f,
+  root: u32,
+  // Object ID reported in `HitInfo::id`.
+  object: u32,
+  _pad: vec2<u32>,
+}
 
-@group(0) @binding(1) var texture1: texture_storage_2d<rgba8unorm, read>;
-@group(0) @binding(2) var texture2: texture_storage_2d<rgba8unorm, write>;
+// Node 0 roots the top-level tree over `instances`; the bottom-level trees over `primitives`, in
+// the local space of their instances, follow it.
+@group(0) @binding(4) var<storage, read> bvh: array<BvhNode>;
+@group(0) @binding(5) var<storage, read> instances: array<Instance>;
+
+// Deepest tree `RayBounce` traverses. The trees are balanced, so this covers millions of
+// items.
+const BVH_STACK_SIZE = 24;
+
+@group(1) @binding(0) var radiance_out: texture_storage_2d<rgba32float, write>;
+@group(1) @binding(1) var gbuffer_out: texture_storage_2d<rgba32float, write>;
+
+// Arbitrary output variables. Geometry layers: albedo, shading normal, world position with the
+// hit distance in w, and object/material IDs. Lighting layers, accumulated over `aov.samples`
+// frames: direct diffuse, indirect diffuse, specular and emission.
+struct AovUniforms {
+  enabled: u32,
+  samples: u32,
+  _pad: vec2<u32>,
+}
+@group(1) @binding(2) var aov_geometry_out: texture_storage_2d_array<rgba32float, write>;
+@group(1) @binding(3) var aov_lighting_out: texture_storage_2d_array<rgba32float, write>;
+@group(1) @binding(4) var aov_lighting_prev: texture_2d_array<f32>;
+@group(1) @binding(5) var<uniform> aov: AovUniforms;
 
-@vertex fn path_tracer_vs(@builtin(vertex_index) vid: u32) -> @builtin(position) vec4f {
-  return vec4f(vertices[vid], 0.0, 1.0);
+// Vertices of the path traced for `uniforms.debug_pixel`.
+struct PathRecord {
+  position: vec3f,
+  kind: u32,
+  normal: vec3f,
+  primitive: i32,
+  // Outgoing direction, or the ray direction for an escaped path.
+  direction: vec3f,
+  // Density of the cosine-weighted diffuse sample, negative for a delta (specular) bounce and
+  // zero where the path ended.
+  pdf: f32,
+  throughput: vec3f,
+  material: i32,
+}
+struct PathLog {
+  count: u32,
+  records: array<PathRecord>,
 }
+@group(1) @binding(6) var<storage, read_write> path_log: PathLog;
 
+// Rays traced by each 8x8 workgroup of path_tracer_cs this frame, for the performance stats. One
+// counter per workgroup keeps the totals from overflowing at high sample counts.
+@group(1) @binding(7) var<storage, read_write> ray_counts: array<atomic<u32>>;
 
+const VERTEX_SURFACE = 0u;
+const VERTEX_ESCAPED = 1u;
+const VERTEX_EMITTER = 2u;
 
-@fragment
-fn path_tracer_fs(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
-    
+fn log_path_vertex(record: PathRecord) {
+  if(path_log.count < arrayLength(&path_log.records)){
+    path_log.records[path_log.count] = record;
+    path_log.count += 1u;
+  }
+}
 
+// Which lighting AOV a path contributes to, decided by its first bounce.
+const PATH_DIRECT = 0;
+const PATH_INDIRECT = 1;
+const PATH_SPECULAR = 2;
+const PATH_EMISSION = 3;
 
+@vertex fn path_tracer_vs(@builtin(vertex_index) vid: u32) -> @builtin(position) vec4f {
+  return vec4f(vertices[vid], 0.0, 1.0);
+}
 
-    var frame = uniforms._pad;
+// Screen space coordinates of a pixel position, matching the camera model below.
+fn screen_pos(pos: vec2f) -> vec2f {
     let x = (pos.x/f32(uniforms.width - 1) - 0.5)*2* f32(uniforms.width - 1) / f32(uniforms.height - 1);
     let y = -(pos.y/f32(uniforms.height - 1) - 0.5)*2;
-    var uv = pos.xy / vec2(f32(uniforms.width), f32(uniforms.height));
+    return vec2f(x, y);
+}
 
+// Inverse of screen_pos.
+fn pixel_pos(screen: vec2f) -> vec2f {
+    let x = (screen.x * f32(uniforms.height - 1) / f32(uniforms.width - 1) / 2 + 0.5) * f32(uniforms.width - 1);
+    let y = (-screen.y / 2 + 0.5) * f32(uniforms.height - 1);
+    return vec2f(x, y);
+}
 
-    if(x < 0.01 && x > -0.01){
-      if(y < 0.01 && y > -0.01){
-        return vec4f(0.0,0.0,0.0,1.0);
-      }
+// Primary ray direction through a screen point for a camera looking along `forward`.
+fn camera_dir(forward: vec3f, fov: f32, screen: vec2f) -> vec3f {
+    let fovv = vec3(screen.x/fov, screen.y/fov, -1);
+    var dir = forward + fovv/abs(fovv);
+    dir /= abs(dir);
+    return dir;
+}
+
+// Inverse of camera_dir: the screen point whose primary ray points along `dir`. The z component
+// is 0 when no such point exists (the direction is behind the camera).
+fn camera_project(forward: vec3f, fov: f32, dir: vec3f) -> vec3f {
+    // camera_dir(s) is parallel to forward + n with |n| = 1, so n = k*dir - forward with k = 2*dot(dir, forward).
+    let k = 2.0 * dot(dir, forward);
+    let n = k * dir - forward;
+    if(k <= 0.0 || n.z >= -0.0001){
+      return vec3f(0.0);
+    }
+    return vec3f(fov * n.x / -n.z, fov * n.y / -n.z, 1.0);
+}
+
+@compute @workgroup_size(8, 8)
+fn path_tracer_cs(@builtin(global_invocation_id) id: vec3<u32>) {
+    if(id.x >= uniforms.width || id.y >= uniforms.height){
+      return;
     }
-    //spheres[0].position = uniforms.u;
+    let pos = vec2f(id.xy) + vec2f(0.5);
 
+    let frame = f32(uniforms.frame);
+    let screen = screen_pos(pos);
+    let x = screen.x;
+    let y = screen.y;
 
-    let camera_rotation = mat3x3(uniforms.u.xyz, uniforms.v.xyz, uniforms.w.xyz);
-    let direction = camera_rotation * vec3(x, y, focus_distance);
-    
-    var thisray = Ray(uniforms.u.xyz, vec3(0,0,0));
+    var thisray = Ray(uniforms.camera.origin.xyz, vec3(0,0,0));
     var colors = vec3<f32>(0, 0, 0);
-    let coord:vec2<i32> = vec2<i32>(i32(pos.x),i32(pos.y));
-    for(var j = 0; j < Samples; j++){
-    var fovv =  vec3(x/uniforms.time,y/uniforms.time,-1);
-    thisray = Ray(uniforms.u.xyz, uniforms.ww.xyz + fovv/abs(fovv));
-    thisray.dir /= abs(thisray.dir);
+    let coord:vec2<i32> = vec2<i32>(id.xy);
+    let logging = all(coord == uniforms.debug_pixel);
+    if(logging){
+      path_log.count = 0u;
+    }
+
+    // First hit of the first sample, used by the denoiser for reprojection and edge stopping.
+    var first_normal = vec3<f32>(0.0);
+    var first_t = -1.0;
+    var first_albedo = vec3<f32>(0.0);
+    var first_pos = vec3<f32>(0.0);
+    var first_ids = vec2<f32>(-1.0);
+    var first_uv = vec2<f32>(0.0);
+    var lighting = array<vec3<f32>, 4>();
+    var bounce_sum = 0;
+    var rays = 0u;
+    for(var j = 0; j < i32(settings.samples); j++){
+    thisray = Ray(uniforms.camera.origin.xyz, camera_dir(uniforms.camera.w.xyz, uniforms.fov, screen));
 
     // Transparency shit
 
     var color = vec3<f32>(1 ,1 , 1);
     var transs = 1.0;
-    for(var i = 0; i < MaxBounces; i++){
+    var path_kind = PATH_EMISSION;
+    var bounces = 0;
+    var transparent_bounces = 0u;
+    for(var i = 0; i < i32(settings.max_bounces); i++){
       let hit = RayBounce(thisray);
+      rays += 1u;
+      if(i == 0 && j == 0 && hit.hit){
+        first_normal = hit.normal;
+        first_t = hit.time;
+        first_albedo = hit.primitive.material.color.xyz;
+        first_pos = hit.pos;
+        first_ids = vec2<f32>(f32(hit.id), f32(hit.primitive.material_id));
+        first_uv = hit.uv;
+      }
       if(!hit.hit){
+        if(i == 1 && path_kind == PATH_INDIRECT){
+          path_kind = PATH_DIRECT;
+        }
         let k = 5*pow(dot(thisray.dir, sunDir)/(abs(sunDir)*abs(thisray.dir)), 51);
         //color *= vec3f(0.8, 0.8, 1.0);
         //color *= vec3f(joicy( 1 - 0.2*joicy(thisray.dir.y*1.0)),joicy(1 - 0.2*joicy(thisray.dir.y*1.0)), 1.0);
         let t = 0.5 * (normalize(thisray.dir).y + 1.);
-        let sk = (1. - t) * vec3(1.) + t * vec3(0.3, 0.5, 1.);
+        let sk = environment.intensity * mix(environment.nadir, environment.zenith, t);
         color*=sk;
         //color+=k;
+        if(logging && j == 0){
+          log_path_vertex(PathRecord(thisray.origin + thisray.dir, VERTEX_ESCAPED, vec3f(0.0), -1, thisray.dir, 0.0, color, -1));
+        }
         break;
       }
-      if((hit.sphere.material.emission != vec3<f32>(0.0)).x||(hit.sphere.material.emission != vec3<f32>(0.0)).y||(hit.sphere.material.emission != vec3<f32>(0.0)).z){
-        color += hit.sphere.material.emission;
+      bounces += 1;
+      if((hit.primitive.material.emission != vec3<f32>(0.0)).x||(hit.primitive.material.emission != vec3<f32>(0.0)).y||(hit.primitive.material.emission != vec3<f32>(0.0)).z){
+        // A diffuse bounce straight into a light is direct lighting, as it is into the sky.
+        if(i == 1 && path_kind == PATH_INDIRECT){
+          path_kind = PATH_DIRECT;
+        }
+        color *= hit.primitive.material.emission;
+        if(logging && j == 0){
+          log_path_vertex(PathRecord(hit.pos, VERTEX_EMITTER, hit.normal, hit.id, vec3f(0.0), 0.0, color, i32(hit.primitive.material_id)));
+        }
         break;
       }
+      if(i == 0){
+        let specular = hit.primitive.material.reflections >= 0.5 || hit.primitive.material.transparency > 0.5;
+        path_kind = select(PATH_INDIRECT, PATH_SPECULAR, specular);
+      }
 
-      var h = hit.sphere.material.color.xyz;
+      var h = hit.primitive.material.color.xyz;
 
       let cos1 = dot(hit.normal, thisray.dir)/(abs(thisray.dir)*abs(hit.normal));
-      let sin1 = sqrt(1 - cos1*cos1);
-      let sin2 = sin1 / hit.sphere.material.refractiveIndex;
+      // Rounding can push |cos1| past 1, and a ray along the normal has no tangent to
+      // normalize; either would make the direction NaN.
+      let sin1 = sqrt(max(0.0, 1 - cos1*cos1));
+      let sin2 = sin1 / hit.primitive.material.refractiveIndex;
       var diss = thisray.dir - dot(thisray.dir, hit.normal)*hit.normal/abs(hit.normal);
-      diss /= abs(diss);
-      var p12 = diss*sin2 - hit.normal*sqrt(1 - sin2*sin2);
+      diss /= max(abs(diss), 1e-8);
+      var p12 = diss*sin2 - hit.normal*sqrt(max(0.0, 1 - sin2*sin2));
       p12/=abs(p12);
       var curr_pos = hit.pos;
       var kk = true;
@@ -160,41 +354,28 @@ fn path_tracer_fs(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
       }
 
 
-      var firstRef = refract(thisray.dir,  hit.normal, hit.sphere.material.refractiveIndex);
+      var firstRef = refract(thisray.dir,  hit.normal, hit.primitive.material.refractiveIndex);
       var transexes = transexit(hit.pos,p12);
       var fisray= Ray(transexes.exit_pos, transexes.exit_dir);
-      var cols = vec3f(1);
-      /*
-      for(var i = 0; i < MaxTransBounces; i++){
-        var random = random_direction(vec3<f32>(frame%112 + f32(j), x + f32(j)*2, y + f32(j)*6));
-        let pit = RayBounce(fisray);
-        if(!pit.hit){
-          let k = 5*pow(dot(thisray.dir, sunDir)/(abs(sunDir)*abs(thisray.dir)), 51);
-          //cols -= vec3f(1);
-          //color*=k;
-          //color *= vec3f(joicy( 1 - joicy(thisray.dir.y*0.5)),joicy(1 - joicy(thisray.dir.y*0.5)), 1.0);
-           let t = 0.5 * (normalize(thisray.dir).y + 1.);
-           let sk = (1. - t) * vec3(1.) + t * vec3(0.3, 0.5, 1.);
-           cols*=sk;
-          //color+=k;
-          break;
-        }
-        random = (random + pit.normal)/abs(random + pit.normal);
-        cols*=pit.sphere.material.color.xyz;
-        var dis = random*(1-pit.sphere.material.reflections) - (2*dot(pit.normal, thisray.dir)*pit.normal - thisray.dir) * pit.sphere.material.reflections;
-        fisray = Ray(hit.pos, dis);
-      }
-      */
-      h = h *(1-hit.sphere.material.transparency) + cols * hit.sphere.material.transparency;
+      // Reflected and transmitted light are both filtered by the material color, so no surface
+      // returns more light than its color lets through.
       color *= h;
-      transs *= (1-hit.sphere.material.transparency);
+      transs *= (1-hit.primitive.material.transparency);
       
       
-      if(hit.sphere.material.transparency > 0.5){
+      if(hit.primitive.material.transparency > 0.5){
+        // Past the limit the path ends here, as it does when it runs out of bounces.
+        if(transparent_bounces == settings.max_transparent_bounces){
+          break;
+        }
+        transparent_bounces += 1u;
+        if(logging && j == 0){
+          log_path_vertex(PathRecord(hit.pos, VERTEX_SURFACE, hit.normal, hit.id, fisray.dir, -1.0, color, i32(hit.primitive.material_id)));
+        }
         thisray = fisray;
         continue;
       }
-      var random = random_direction(vec3<f32>(frame%10 + f32(j), x + f32(j)*2, y + f32(j)*3));
+      var random = random_direction(vec3<f32>(frame%1000 + f32(j), x + f32(j)*2, y + f32(j)*3));
 
       if(dot(random, hit.normal) < 0){
         random *= -1;
@@ -204,20 +385,177 @@ fn path_tracer_fs(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
       let cos_theta = max(0.0, dot(hit.normal, random));
       //color*=cos_theta*2;
 
-      var dis = random*(1-hit.sphere.material.reflections) - (2*dot(hit.normal, thisray.dir)*hit.normal - thisray.dir) * hit.sphere.material.reflections;
+      var dis = random*(1-hit.primitive.material.reflections) - (2*dot(hit.normal, thisray.dir)*hit.normal - thisray.dir) * hit.primitive.material.reflections;
       thisray = Ray(hit.pos, dis);
+      if(logging && j == 0){
+        let pdf = select(cos_theta / 3.14159265, -1.0, hit.primitive.material.reflections >= 1.0);
+        log_path_vertex(PathRecord(hit.pos, VERTEX_SURFACE, hit.normal, hit.id, normalize(dis), pdf, color, i32(hit.primitive.material_id)));
+      }
     }
     colors+=color;
+    lighting[path_kind] += color;
+    bounce_sum += bounces;
   }
 
-  colors/=f32(Samples);
+  colors/=f32(settings.samples);
   //colors*=16;
-    frame = 5;
-    var aa = (textureLoad(texture1, coord)*(frame-1) + (vec4(colors, 1.0)))/frame;
-    textureStore(texture2, coord, aa);
+    var shown = colors;
+    switch(uniforms.debug_mode){
+      case DEBUG_NORMALS: {
+        shown = select(vec3f(0.0), first_normal * 0.5 + 0.5, first_t >= 0.0);
+      }
+      case DEBUG_HIT_DISTANCE: {
+        shown = select(vec3f(0.0), heat(1.0 - exp(-0.5 * first_t)), first_t >= 0.0);
+      }
+      case DEBUG_ALBEDO: {
+        shown = first_albedo;
+      }
+      case DEBUG_BOUNCES: {
+        shown = heat(f32(bounce_sum) / f32(settings.samples) / DEBUG_MAX_BOUNCES);
+      }
+      case DEBUG_PRIMITIVE_ID: {
+        shown = select(vec3f(0.0), id_color(u32(first_ids.x)), first_t >= 0.0);
+      }
+      case DEBUG_NAN_INF: {
+        shown = vec3f(luminance(colors) / (1.0 + luminance(colors)));
+        if(any(is_inf(colors))){
+          shown = vec3f(1.0, 1.0, 0.0);
+        }
+        if(any(is_nan(colors))){
+          shown = vec3f(1.0, 0.0, 1.0);
+        }
+      }
+      case DEBUG_UV: {
+        shown = select(vec3f(0.0), vec3f(fract(first_uv), 0.0), first_t >= 0.0);
+      }
+      default: {}
+    }
+    textureStore(radiance_out, coord, vec4(shown, 1.0));
+    textureStore(gbuffer_out, coord, vec4(first_normal, first_t));
+    atomicAdd(&ray_counts[(id.y / 8u) * ((uniforms.width + 7u) / 8u) + id.x / 8u], rays);
+
+    if(aov.enabled != 0u){
+      textureStore(aov_geometry_out, coord, 0, vec4(first_albedo, 1.0));
+      textureStore(aov_geometry_out, coord, 1, vec4(first_normal, 0.0));
+      textureStore(aov_geometry_out, coord, 2, vec4(first_pos, first_t));
+      textureStore(aov_geometry_out, coord, 3, vec4(first_ids, 0.0, 0.0));
+      let alpha = 1.0 / f32(aov.samples + 1u);
+      for(var k = 0; k < 4; k++){
+        let prev = textureLoad(aov_lighting_prev, coord, k, 0);
+        textureStore(aov_lighting_out, coord, k, mix(prev, vec4(lighting[k] / f32(settings.samples), 1.0), alpha));
+      }
+    }
+}
+
+@group(1) @binding(0) var display_in: texture_2d<f32>;
+@group(1) @binding(0) var<storage, read> path_overlay: PathLog;
+
+// Draws the logged path as a line strip through the projected vertices. Vertices past the end
+// repeat the last one, so a fixed vertex count can be drawn.
+@vertex fn path_overlay_vs(@builtin(vertex_index) vid: u32) -> @builtin(position) vec4f {
+  let hidden = vec4f(0.0, 0.0, 2.0, 1.0);
+  let count = path_overlay.count;
+  if(count == 0u){
+    return hidden;
+  }
+  let to_vertex = path_overlay.records[min(vid, count - 1u)].position - uniforms.camera.origin.xyz;
+  let projected = camera_project(uniforms.camera.w.xyz, uniforms.fov, to_vertex / abs(to_vertex));
+  if(projected.z == 0.0){
+    return hidden;
+  }
+  let aspect = f32(uniforms.width - 1) / f32(uniforms.height - 1);
+  return vec4f(projected.x / aspect, projected.y, 0.0, 1.0);
+}
+
+@fragment fn path_overlay_fs() -> @location(0) vec4f {
+  return vec4f(encode_display_color(vec3f(1.0, 0.9, 0.0)), 1.0);
+}
+
+// How the display passes encode colors for the render target, set per pipeline from the target
+// format by `output_transform` on the CPU.
+override OUTPUT_TRANSFORM: u32 = 0u;
+// 8-bit UNORM target: tone map, then gamma-encode in the shader.
+const OUTPUT_GAMMA = 0u;
+// sRGB target: tone map; the hardware applies the sRGB curve on store.
+const OUTPUT_SRGB = 1u;
+// Rgba16Float HDR target in extended linear sRGB: no tone mapping, so highlights above 1 reach
+// the display.
+const OUTPUT_HDR = 2u;
+
+// Encodes linear radiance for the target.
+fn encode_radiance(radiance: vec3f) -> vec3f {
+  switch(OUTPUT_TRANSFORM){
+    case OUTPUT_HDR: {
+      return max(radiance, vec3f(0.0));
+    }
+    case OUTPUT_SRGB: {
+      return ACES(radiance);
+    }
+    default: {
+      return pow(ACES(radiance), vec3f(1.0 / 2.2));
+    }
+  }
+}
+
+// Encodes a color that is already meant for display, like the debug views, so that it looks the
+// same on every target.
+fn encode_display_color(color: vec3f) -> vec3f {
+  if(OUTPUT_TRANSFORM == OUTPUT_GAMMA){
+    return color;
+  }
+  return pow(max(color, vec3f(0.0)), vec3f(2.2));
+}
+
+// Width of the selection outline in pixels.
+const OUTLINE_WIDTH = 2.0;
+
+// Whether the primary ray through a pixel position first hits the selected object.
+fn shows_selected(pos: vec2f) -> bool {
+  let ray = Ray(uniforms.camera.origin.xyz, camera_dir(uniforms.camera.w.xyz, uniforms.fov, screen_pos(pos)));
+  let hit = RayBounce(ray);
+  return hit.hit && hit.id == uniforms.selected;
+}
+
+// Whether a pixel lies just outside the visible part of the selected object.
+fn on_outline(pos: vec2f) -> bool {
+  if(uniforms.selected < 0 || u32(uniforms.selected) >= arrayLength(&instances) || shows_selected(pos)){
+    return false;
+  }
+  for(var i = 0; i < 8; i++){
+    let angle = f32(i) * 0.785398;
+    if(shows_selected(pos + OUTLINE_WIDTH * vec2f(cos(angle), sin(angle)))){
+      return true;
+    }
+  }
+  return false;
+}
+
+@fragment
+fn display_fs(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    let screen = screen_pos(pos.xy);
+    if(screen.x < 0.01 && screen.x > -0.01){
+      if(screen.y < 0.01 && screen.y > -0.01){
+        return vec4f(0.0,0.0,0.0,1.0);
+      }
+    }
+    if(on_outline(pos.xy)){
+      return vec4f(encode_display_color(vec3f(1.0, 0.6, 0.1)), 1.0);
+    }
+
+    let coord:vec2<i32> = vec2<i32>(i32(pos.x),i32(pos.y));
+    var aa = textureLoad(display_in, coord, 0);
+    if(uniforms.debug_mode == DEBUG_VARIANCE){
+      // The denoiser keeps the luminance variance in w.
+      return vec4f(encode_display_color(heat(sqrt(max(aa.w, 0.0)) / DEBUG_MAX_STD_DEV)), 1.0);
+    }
+    if(uniforms.debug_mode != DEBUG_OFF){
+      return vec4f(encode_display_color(aa.xyz), 1.0);
+    }
     var divisor = 0.0;
     //  Anti - aliasing
-    if(antiAliasing){
+    if(settings.anti_aliasing != 0u){
+      let level = settings.anti_aliasing_weight;
+      let times = i32(settings.anti_aliasing_radius);
       aa*=level;
 
       for(var ind1:i32 = -times; ind1 <= times; ind1 += 1){
@@ -225,7 +563,7 @@ fn path_tracer_fs(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
           if(coord.x + ind1 < 0 || coord.x + ind1 >= i32(uniforms.width) || coord.y + ind2 < 0 || coord.y + ind2 >= i32(uniforms.height)){
             continue;
           }
-          aa += textureLoad(texture1, coord+vec2(ind1,ind2));
+          aa += textureLoad(display_in, coord+vec2(ind1,ind2), 0);
           divisor+=1.0;
         }
       }
@@ -233,7 +571,301 @@ fn path_tracer_fs(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
       aa /= (level+divisor);
     }
     
-    return vec4f(ACES(aa.xyz), 1.0);
+    return vec4f(encode_radiance(aa.xyz), 1.0);
+}
+
+// ---------------------------------------------------------------------------------------------
+// SVGF-style denoising: temporal reprojection with moment tracking, then an edge-avoiding
+// a-trous wavelet filter guided by the estimated variance.
+
+struct TemporalUniforms {
+  prev_camera: CameraUniforms,
+  prev_fov: f32,
+  history_cap: f32,
+  reset: u32,
+  _pad: u32,
+}
+
+@group(1) @binding(0) var noisy_in: texture_2d<f32>;
+@group(1) @binding(1) var gbuffer_in: texture_2d<f32>;
+@group(1) @binding(2) var prev_gbuffer_in: texture_2d<f32>;
+@group(1) @binding(3) var prev_color_in: texture_2d<f32>;
+@group(1) @binding(4) var prev_moments_in: texture_2d<f32>;
+@group(1) @binding(5) var color_out: texture_storage_2d<rgba32float, write>;
+@group(1) @binding(6) var moments_out: texture_storage_2d<rgba32float, write>;
+@group(1) @binding(7) var<uniform> temporal: TemporalUniforms;
+
+fn luminance(c: vec3f) -> f32 {
+  return dot(c, vec3f(0.2126, 0.7152, 0.0722));
+}
+
+// World space position seen by a pixel this frame, reconstructed from its G-buffer depth. Misses
+// are pushed far away so that they reproject by direction only.
+fn gbuffer_world_pos(coord: vec2<i32>, t: f32) -> vec3f {
+  let dir = camera_dir(uniforms.camera.w.xyz, uniforms.fov, screen_pos(vec2f(coord) + vec2f(0.5)));
+  if(t < 0.0){
+    return uniforms.camera.origin.xyz + dir * 100000.0;
+  }
+  return uniforms.camera.origin.xyz + dir * t;
+}
+
+// Whether the previous frame's G-buffer sample at `coord` saw the same surface.
+fn history_consistent(coord: vec2<i32>, normal: vec3f, t: f32, prev_t: f32) -> bool {
+  if(coord.x < 0 || coord.y < 0 || coord.x >= i32(uniforms.width) || coord.y >= i32(uniforms.height)){
+    return false;
+  }
+  let prev = textureLoad(prev_gbuffer_in, coord, 0);
+  if(t < 0.0 || prev.w < 0.0){
+    return t < 0.0 && prev.w < 0.0;
+  }
+  return distance(prev.w, prev_t) < 0.05 * prev_t && dot(prev.xyz, normal) > 0.9;
+}
+
+@compute @workgroup_size(8, 8)
+fn temporal_cs(@builtin(global_invocation_id) id: vec3<u32>) {
+  if(id.x >= uniforms.width || id.y >= uniforms.height){
+    return;
+  }
+  let coord = vec2<i32>(id.xy);
+  let color = textureLoad(noisy_in, coord, 0).xyz;
+  let g = textureLoad(gbuffer_in, coord, 0);
+  let lum = luminance(color);
+
+  // Reproject into the previous frame using the previous camera.
+  let world = gbuffer_world_pos(coord, g.w);
+  let to_prev = world - temporal.prev_camera.origin.xyz;
+  let projected = camera_project(temporal.prev_camera.w.xyz, temporal.prev_fov, to_prev / abs(to_prev));
+  let prev_t = abs(to_prev);
+
+  var prev_color = vec3f(0.0);
+  var prev_moments = vec3f(0.0);
+  var weight_sum = 0.0;
+  if(projected.z > 0.0 && temporal.reset == 0u){
+    // Bilinear fetch from the four texels around the reprojected position, dropping
+    // the ones that saw a different surface.
+    // A still camera reprojects each pixel onto itself up to rounding, which would otherwise blend a
+    // sliver of the neighbors into the history every frame.
+    let unsnapped = pixel_pos(projected.xy) - vec2f(0.5);
+    let rounded = round(unsnapped);
+    let off = unsnapped - rounded;
+    let p = select(unsnapped, rounded, off * off < vec2f(1e-6));
+    let base = vec2<i32>(floor(p));
+    let f = fract(p);
+    for(var dy = 0; dy <= 1; dy++){
+      for(var dx = 0; dx <= 1; dx++){
+        let tap = base + vec2<i32>(dx, dy);
+        if(!history_consistent(tap, g.xyz, g.w, prev_t)){
+          continue;
+        }
+        let w = select(1.0 - f.x, f.x, dx == 1) * select(1.0 - f.y, f.y, dy == 1);
+        prev_color += w * textureLoad(prev_color_in, tap, 0).xyz;
+        prev_moments += w * textureLoad(prev_moments_in, tap, 0).xyz;
+        weight_sum += w;
+      }
+    }
+  }
+
+  var history_len = 0.0;
+  if(weight_sum > 0.001){
+    prev_color /= weight_sum;
+    prev_moments /= weight_sum;
+    history_len = prev_moments.z;
+  }
+  history_len = min(history_len + 1.0, temporal.history_cap);
+
+  // With an empty history alpha is 1 and the new sample replaces it.
+  let alpha = 1.0 / history_len;
+  let moments = mix(prev_moments.xy, vec2f(lum, lum * lum), alpha);
+  let integrated = mix(prev_color, color, alpha);
+
+  var variance = max(moments.y - moments.x * moments.x, 0.0);
+  if(history_len < 4.0){
+    // Too little history for temporal moments; estimate the variance spatially instead.
+    var m1 = 0.0;
+    var m2 = 0.0;
+    var count = 0.0;
+    for(var dy = -1; dy <= 1; dy++){
+      for(var dx = -1; dx <= 1; dx++){
+        let tap = clamp(coord + vec2<i32>(dx, dy), vec2<i32>(0), vec2<i32>(i32(uniforms.width) - 1, i32(uniforms.height) - 1));
+        let l = luminance(textureLoad(noisy_in, tap, 0).xyz);
+        m1 += l;
+        m2 += l * l;
+        count += 1.0;
+      }
+    }
+    m1 /= count;
+    m2 /= count;
+    variance = max(m2 - m1 * m1, 0.0) * 4.0 / history_len;
+  }
+
+  textureStore(color_out, coord, vec4f(integrated, variance));
+  textureStore(moments_out, coord, vec4f(moments, history_len, 0.0));
+}
+
+struct AtrousUniforms {
+  step: i32,
+  _pad0: i32,
+  _pad1: i32,
+  _pad2: i32,
+}
+
+@group(1) @binding(0) var atrous_color_in: texture_2d<f32>;
+@group(1) @binding(1) var atrous_gbuffer_in: texture_2d<f32>;
+@group(1) @binding(2) var atrous_color_out: texture_storage_2d<rgba32float, write>;
+@group(1) @binding(3) var<uniform> atrous: AtrousUniforms;
+
+@compute @workgroup_size(8, 8)
+fn atrous_cs(@builtin(global_invocation_id) id: vec3<u32>) {
+  if(id.x >= uniforms.width || id.y >= uniforms.height){
+    return;
+  }
+  let coord = vec2<i32>(id.xy);
+  let size = vec2<i32>(i32(uniforms.width), i32(uniforms.height));
+  let center = textureLoad(atrous_color_in, coord, 0);
+  let g = textureLoad(atrous_gbuffer_in, coord, 0);
+  let lum = luminance(center.xyz);
+
+  // Blur the variance a little before using it to steer the luminance weight.
+  var variance = 0.0;
+  for(var dy = -1; dy <= 1; dy++){
+    for(var dx = -1; dx <= 1; dx++){
+      let tap = clamp(coord + vec2<i32>(dx, dy), vec2<i32>(0), size - 1);
+      let k = select(0.5, 1.0, dx == 0) * select(0.5, 1.0, dy == 0);
+      variance += k * textureLoad(atrous_color_in, tap, 0).w;
+    }
+  }
+  variance /= 4.0;
+  let phi_l = 4.0 * sqrt(variance) + 0.0001;
+
+  let kernel = array<f32, 3>(3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0);
+  var color_sum = vec3f(0.0);
+  var variance_sum = 0.0;
+  var weight_sum = 0.0;
+  for(var dy = -2; dy <= 2; dy++){
+    for(var dx = -2; dx <= 2; dx++){
+      let tap = coord + vec2<i32>(dx, dy) * atrous.step;
+      if(tap.x < 0 || tap.y < 0 || tap.x >= size.x || tap.y >= size.y){
+        continue;
+      }
+      let c = textureLoad(atrous_color_in, tap, 0);
+      let gq = textureLoad(atrous_gbuffer_in, tap, 0);
+
+      var w = kernel[max(dx, -dx)] * kernel[max(dy, -dy)];
+      if(dx != 0 || dy != 0){
+        let w_normal = pow(max(dot(g.xyz, gq.xyz), 0.0), 128.0);
+        let w_depth = exp(-distance(g.w, gq.w) / (0.02 * f32(atrous.step) * max(g.w, 0.001)));
+        let w_lum = exp(-distance(lum, luminance(c.xyz)) / phi_l);
+        w *= w_normal * w_depth * w_lum;
+      }
+      color_sum += w * c.xyz;
+      variance_sum += w * w * c.w;
+      weight_sum += w;
+    }
+  }
+
+  textureStore(atrous_color_out, coord, vec4f(color_sum / weight_sum, variance_sum / (weight_sum * weight_sum)));
+}
+
+// ---------------------------------------------------------------------------------------------
+// Cryptomatte: per-pixel coverage of hashed object and material IDs, accumulated over jittered
+// primary rays.
+
+const CRYPTO_RANKS = 6u;
+
+struct CryptoRank {
+  id: u32,
+  weight: f32,
+}
+
+struct CryptoUniforms {
+  rays: u32,
+  seed: u32,
+  reset: u32,
+  _pad: u32,
+}
+
+@group(1) @binding(0) var<storage, read_write> crypto_objects: array<CryptoRank>;
+@group(1) @binding(1) var<storage, read_write> crypto_materials: array<CryptoRank>;
+// IDs by object ID and by material index.
+@group(1) @binding(2) var<storage, read> crypto_object_ids: array<u32>;
+@group(1) @binding(3) var<uniform> crypto: CryptoUniforms;
+@group(1) @binding(4) var<storage, read> crypto_material_ids: array<u32>;
+
+fn pcg_hash(v: u32) -> u32 {
+  let state = v * 747796405u + 2891336453u;
+  let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+  return (word >> 22u) ^ word;
+}
+
+// Adds one sample of `id`. IDs that do not fit once all ranks are taken are dropped.
+fn crypto_insert(ranks: ptr<function, array<CryptoRank, CRYPTO_RANKS>>, id: u32) {
+  for(var r = 0u; r < CRYPTO_RANKS; r++){
+    if((*ranks)[r].weight == 0.0){
+      (*ranks)[r] = CryptoRank(id, 1.0);
+      return;
+    }
+    if((*ranks)[r].id == id){
+      (*ranks)[r].weight += 1.0;
+      return;
+    }
+  }
+}
+
+@compute @workgroup_size(8, 8)
+fn cryptomatte_cs(@builtin(global_invocation_id) id: vec3<u32>) {
+  if(id.x >= uniforms.width || id.y >= uniforms.height){
+    return;
+  }
+  let pixel = id.y * uniforms.width + id.x;
+  let base = pixel * CRYPTO_RANKS;
+
+  var objects = array<CryptoRank, CRYPTO_RANKS>();
+  var materials = array<CryptoRank, CRYPTO_RANKS>();
+  if(crypto.reset == 0u){
+    for(var r = 0u; r < CRYPTO_RANKS; r++){
+      objects[r] = crypto_objects[base + r];
+      materials[r] = crypto_materials[base + r];
+    }
+  }
+
+  for(var k = 0u; k < crypto.rays; k++){
+    let h1 = pcg_hash(pixel ^ pcg_hash(crypto.seed + k));
+    let h2 = pcg_hash(h1);
+    let jitter = vec2f(f32(h1), f32(h2)) / 4294967296.0;
+    let ray = Ray(uniforms.camera.origin.xyz, camera_dir(uniforms.camera.w.xyz, uniforms.fov, screen_pos(vec2f(id.xy) + jitter)));
+    let hit = RayBounce(ray);
+    if(hit.hit){
+      crypto_insert(&objects, crypto_object_ids[hit.id]);
+      crypto_insert(&materials, crypto_material_ids[hit.primitive.material_id]);
+    }
+  }
+
+  for(var r = 0u; r < CRYPTO_RANKS; r++){
+    crypto_objects[base + r] = objects[r];
+    crypto_materials[base + r] = materials[r];
+  }
+}
+
+// Blue to cyan, green, yellow and red as `t` goes from 0 to 1.
+fn heat(t: f32) -> vec3f {
+  let x = 4.0 * saturate(t);
+  return saturate(vec3f(1.5 - distance(x, 3.0), 1.5 - distance(x, 2.0), 1.5 - distance(x, 1.0)));
+}
+
+// An arbitrary but stable color per ID.
+fn id_color(id: u32) -> vec3f {
+  let h = pcg_hash(id + 1u);
+  return vec3f(f32(h & 255u), f32((h >> 8u) & 255u), f32((h >> 16u) & 255u)) / 255.0;
+}
+
+// Checked on the bits, as shader compilers may assume floats are always finite.
+fn is_nan(v: vec3f) -> vec3<bool> {
+  let bits = bitcast<vec3<u32>>(v);
+  return ((bits & vec3(0x7f800000u)) == vec3(0x7f800000u)) & ((bits & vec3(0x007fffffu)) != vec3(0u));
+}
+
+fn is_inf(v: vec3f) -> vec3<bool> {
+  return (bitcast<vec3<u32>>(v) & vec3(0x7fffffffu)) == vec3(0x7f800000u);
 }
 
 fn ACES(x: vec3f) -> vec3f{
@@ -242,7 +874,7 @@ fn ACES(x: vec3f) -> vec3f{
   let c = 2.43f;
   let d = 0.59f;
   let e = 0.14f;
-  return pow(saturate((x*(a*x+b))/(x*(c*x+d)+e)), vec3f(1/2.2));
+  return saturate((x*(a*x+b))/(x*(c*x+d)+e));
 }
 
 fn dothis(hello: vec3<f32>) -> vec3<f32>{
@@ -259,7 +891,10 @@ struct HitInfo{
   normal:vec3<f32>,
   pos:vec3<f32>,
   time:f32,
-  sphere:Sphere
+  primitive:Primitive,
+  id:i32,
+  // Surface coordinates of the hit, running from 0 to 1 across each part of the shape.
+  uv:vec2<f32>
 }
 
 struct Material{
@@ -271,27 +906,428 @@ struct Material{
 }
 
 fn RayBounce(ray:Ray) -> HitInfo{
-  var getHit:HitInfo = HitInfo(false, vec3(1,1,1), vec3(0,0,0), 999999, spheres[0]);
-  var dist:f32 = getHit.time;
-  for(var i = 0; i < length; i++){
-    var hit = Hit_sphere(spheres[i], ray);
-    if(hit.hit){
-      if(getHit.time > hit.time || !getHit.hit){getHit=hit;}
+  var getHit:HitInfo = HitInfo(false, vec3(1,1,1), vec3(0,0,0), 999999, primitives[0], -1, vec2f(0.0));
+  // Nearest hit so far as a multiple of `ray.dir`, which unlike the distance is the same in the
+  // local space of every instance.
+  var nearest = 3.4e38;
+  let inv = 1.0 / ray.dir;
+  var stack: array<u32, BVH_STACK_SIZE>;
+  var top = 1;
+  stack[0] = 0u;
+  while(top > 0){
+    top--;
+    let node = bvh[stack[top]];
+    if(!hits_box(node, ray, inv, nearest)){
+      continue;
+    }
+    if(node.count == 0u){
+      stack[top] = node.first;
+      stack[top + 1] = node.first + 1u;
+      top = min(top + 2, BVH_STACK_SIZE);
+      continue;
+    }
+    for(var i = node.first; i < node.first + node.count; i++){
+      let hit = hit_instance(instances[i], ray, nearest);
+      if(hit.hit){
+        getHit = hit;
+        nearest = hit.time / abs(ray.dir);
+      }
+    }
+  }
+  return getHit;
+}
+
+// Whether `ray` passes through the box of `node` before reaching `ray.origin + limit * ray.dir`.
+// `inv` is `1.0 / ray.dir`. The empty leaf of an empty tree has its min above its max, which the
+// slab test alone would treat like a box with the corners swapped.
+fn hits_box(node: BvhNode, ray: Ray, inv: vec3f, limit: f32) -> bool {
+  let t0 = (node.min - ray.origin) * inv;
+  let t1 = (node.max - ray.origin) * inv;
+  let near = min(t0, t1);
+  let far = max(t0, t1);
+  return all(node.min <= node.max)
+    && max(max(near.x, near.y), max(near.z, 0.0)) <= min(min(far.x, far.y), min(far.z, limit));
+}
+
+// The nearest primitive of `instance` that `ray` hits before `ray.origin + limit * ray.dir`, in world
+// space.
+fn hit_instance(instance: Instance, ray: Ray, limit: f32) -> HitInfo{
+  let local = Ray((instance.to_local * vec4(ray.origin, 1.0)).xyz, (instance.to_local * vec4(ray.dir, 0.0)).xyz);
+  var getHit:HitInfo = HitInfo(false, vec3(1,1,1), vec3(0,0,0), 999999, primitives[0], -1, vec2f(0.0));
+  var nearest = limit;
+  let inv = 1.0 / local.dir;
+  var stack: array<u32, BVH_STACK_SIZE>;
+  var top = 1;
+  stack[0] = instance.root;
+  while(top > 0){
+    top--;
+    let node = bvh[stack[top]];
+    if(!hits_box(node, local, inv, nearest)){
+      continue;
     }
+    if(node.count == 0u){
+      stack[top] = node.first;
+      stack[top + 1] = node.first + 1u;
+      top = min(top + 2, BVH_STACK_SIZE);
+      continue;
+    }
+    for(var i = node.first; i < node.first + node.count; i++){
+      let hit = hit_primitive(primitives[i], local);
+      let t = hit.time / abs(local.dir);
+      if(hit.hit && t < nearest){
+        getHit = hit;
+        nearest = t;
+      }
+    }
+  }
+  if(getHit.hit){
+    getHit.pos = (instance.to_world * vec4(getHit.pos, 1.0)).xyz;
+    // Normals take the inverse transpose so that they stay perpendicular under scaling.
+    let to_local = mat3x3f(instance.to_local[0].xyz, instance.to_local[1].xyz, instance.to_local[2].xyz);
+    getHit.normal = normalize(transpose(to_local) * getHit.normal);
+    getHit.time = abs(getHit.pos - ray.origin);
+    getHit.id = i32(instance.object);
   }
   return getHit;
 }
 
-fn Hit_sphere(sphere:Sphere, ray:Ray) -> HitInfo{
+// Where `ray` meets `primitive`, in the space of `ray`, if that lies ahead of its origin.
+fn hit_primitive(primitive: Primitive, ray: Ray) -> HitInfo{
+  switch(primitive.kind){
+    case PRIMITIVE_PLANE: {
+      return hit_flat(primitive, ray, primitive.u);
+    }
+    case PRIMITIVE_QUAD: {
+      return hit_quad(primitive, ray);
+    }
+    case PRIMITIVE_DISK: {
+      var hit = hit_flat(primitive, ray, primitive.u);
+      let d = hit.pos - primitive.position;
+      hit.hit = hit.hit && abs(d) <= primitive.radius;
+      hit.uv = vec2f(turn(basis(primitive.u), d), abs(d) / primitive.radius);
+      return hit;
+    }
+    case PRIMITIVE_BOX: {
+      return hit_box(primitive, ray);
+    }
+    case PRIMITIVE_CYLINDER: {
+      return hit_cone(primitive, ray, primitive.radius);
+    }
+    case PRIMITIVE_CONE: {
+      return hit_cone(primitive, ray, primitive.v.x);
+    }
+    case PRIMITIVE_CAPSULE: {
+      return hit_capsule(primitive, ray);
+    }
+    case PRIMITIVE_TORUS: {
+      return hit_torus(primitive, ray);
+    }
+    default: {
+      return Hit_sphere(primitive, ray);
+    }
+  }
+}
+
+fn miss(primitive: Primitive) -> HitInfo{
+  return HitInfo(false, vec3(1,1,1), vec3(0,0,0), 99999, primitive, -1, vec2f(0.0));
+}
+
+const PI = 3.14159265;
+
+// An orthonormal basis whose last column is the unit vector `n` (Duff et al., "Building an
+// Orthonormal Basis, Revisited"), which gives shapes turned about an axis a direction to measure
+// their UVs from.
+fn basis(n: vec3f) -> mat3x3f{
+  let s = select(-1.0, 1.0, n.z >= 0.0);
+  let a = -1.0 / (s + n.z);
+  let b = n.x * n.y * a;
+  return mat3x3f(vec3f(1.0 + s * n.x * n.x * a, s * b, -s * n.x), vec3f(b, s + n.y * n.y * a, -n.y), n);
+}
+
+// How far `d` is turned about the last axis of `frame`, as a fraction of a turn.
+fn turn(frame: mat3x3f, d: vec3f) -> f32{
+  return atan2(dot(d, frame[1]), dot(d, frame[0])) / (2.0 * PI) + 0.5;
+}
+
+// Where `ray` crosses the plane through `primitive.position` perpendicular to `normal`, with the
+// distances along the first two axes of `basis(normal)` as its UV. The normal is turned to face
+// the ray, so that both sides shade alike.
+fn hit_flat(primitive: Primitive, ray: Ray, normal: vec3f) -> HitInfo{
+  let facing = dot(normal, ray.dir);
+  let pos = ray.origin + dot(primitive.position - ray.origin, normal) / facing * ray.dir;
+  if(facing != 0.0 && dot(ray.dir, pos - ray.origin) > 0.01){
+    let frame = basis(normal);
+    let d = pos - primitive.position;
+    let uv = vec2f(dot(d, frame[0]), dot(d, frame[1]));
+    return HitInfo(true, select(normal, -normal, facing > 0.0), pos, abs(pos - ray.origin), primitive, -1, uv);
+  }
+  return miss(primitive);
+}
+
+fn hit_quad(primitive: Primitive, ray: Ray) -> HitInfo{
+  let normal = cross(primitive.u, primitive.v);
+  var hit = hit_flat(primitive, ray, normalize(normal));
+  // The hit is at `position + a * u + b * v`, which lies on the quad for `a` and `b` in 0..1.
+  let p = hit.pos - primitive.position;
+  let a = dot(normal, cross(p, primitive.v)) / dot(normal, normal);
+  let b = dot(normal, cross(primitive.u, p)) / dot(normal, normal);
+  hit.hit = hit.hit && min(a, b) >= 0.0 && max(a, b) <= 1.0;
+  hit.uv = vec2f(a, b);
+  return hit;
+}
+
+// Where `ray` enters the box, by the slab method. Like a sphere, the box is missed by rays that
+// start inside it. Each face has the UV of its two axes across the box.
+fn hit_box(primitive: Primitive, ray: Ray) -> HitInfo{
+  let t0 = (primitive.position - ray.origin) / ray.dir;
+  let t1 = (primitive.u - ray.origin) / ray.dir;
+  let near = min(t0, t1);
+  let far = max(t0, t1);
+  let entry = max(max(near.x, near.y), near.z);
+  let pos = ray.origin + entry * ray.dir;
+  if(entry <= min(min(far.x, far.y), far.z) && dot(ray.dir, pos - ray.origin) > 0.01){
+    // The ray enters through the face of the slab it enters last.
+    let normal = select(vec3f(0.0), -sign(ray.dir), near == vec3f(entry));
+    let q = (pos - primitive.position) / (primitive.u - primitive.position);
+    let uv = select(select(q.xy, q.xz, normal.y != 0.0), q.zy, normal.x != 0.0);
+    return HitInfo(true, normalize(normal), pos, abs(pos - ray.origin), primitive, -1, uv);
+  }
+  return miss(primitive);
+}
+
+// Where `ray` enters the capped cone from `position` to `position + u`, with `primitive.radius`
+// at its base and `top` at its top. Between the planes of its caps the cone is convex, so the ray
+// enters through the cap where it enters that slab if it is inside the cone there, and otherwise
+// where it first reaches the side within the slab. The UV runs around the axis, then up the side
+// or out across the caps.
+fn hit_cone(primitive: Primitive, ray: Ray, top: f32) -> HitInfo{
+  let height = abs(primitive.u);
+  let w = primitive.u / height;
+  let dir = ray.dir / abs(ray.dir);
+  let o = ray.origin - primitive.position;
+  let ow = dot(o, w);
+  let dw = dot(dir, w);
+  // Where the ray is between the caps.
+  var near = -3.4e38;
+  var far = 3.4e38;
+  if(dw != 0.0){
+    near = min(-ow / dw, (height - ow) / dw);
+    far = max(-ow / dw, (height - ow) / dw);
+  } else if(ow < 0.0 || ow > height){
+    return miss(primitive);
+  }
+  // The radius grows by `slope` per unit of height, so the points of the ray inside the cone are
+  // where a * t² + 2 * b * t + c is not positive.
+  let slope = (top - primitive.radius) / height;
+  let op = o - ow * w;
+  let dp = dir - dw * w;
+  let r0 = primitive.radius + slope * ow;
+  let r1 = slope * dw;
+  let a = dot(dp, dp) - r1 * r1;
+  let b = dot(op, dp) - r0 * r1;
+  let c = dot(op, op) - r0 * r0;
+  var t = near;
+  let on_side = (a * near + 2.0 * b) * near + c > 0.0;
+  if(on_side){
+    let disc = b * b - a * c;
+    if(disc < 0.0){
+      return miss(primitive);
+    }
+    var first = -c / (2.0 * b);
+    var second = first;
+    if(a != 0.0){
+      first = (-b - sqrt(disc)) / a;
+      second = (-b + sqrt(disc)) / a;
+    }
+    t = select(max(first, second), min(first, second), min(first, second) > near);
+    if(t <= near || t > far){
+      return miss(primitive);
+    }
+  }
+  let p = o + t * dir;
+  let pos = primitive.position + p;
+  if(dot(ray.dir, pos - ray.origin) <= 0.01){
+    return miss(primitive);
+  }
+  let out = p - dot(p, w) * w;
+  let frame = basis(w);
+  if(on_side){
+    let normal = normalize(out / max(abs(out), 1e-12) - slope * w);
+    return HitInfo(true, normal, pos, abs(pos - ray.origin), primitive, -1, vec2f(turn(frame, p), dot(p, w) / height));
+  }
+  // A ray going up the axis enters through the base.
+  let cap = select(top, primitive.radius, dw > 0.0);
+  let uv = vec2f(turn(frame, p), abs(out) / max(cap, 1e-12));
+  return HitInfo(true, -sign(dw) * w, pos, abs(pos - ray.origin), primitive, -1, uv);
+}
+
+// Where `ray` enters the capsule around the segment from `position` to `position + u`. Where the
+// ray enters the infinite cylinder around the segment between its ends, it enters the capsule
+// there; otherwise it can only enter through the sphere on the end it entered the cylinder beyond.
+// The UV runs around the axis, then up from the bottom of the lower end to the top of the upper.
+fn hit_capsule(primitive: Primitive, ray: Ray) -> HitInfo{
+  let height = abs(primitive.u);
+  let w = primitive.u / height;
+  let r = primitive.radius;
+  let dir = ray.dir / abs(ray.dir);
+  let o = ray.origin - primitive.position;
+  let dw = dot(dir, w);
+  let op = o - dot(o, w) * w;
+  let dp = dir - dw * w;
+  let a = dot(dp, dp);
+  let b = dot(op, dp);
+  let c = dot(op, op) - r * r;
+  let disc = b * b - a * c;
+  if(disc < 0.0 || (a < 1e-12 && c > 0.0)){
+    return miss(primitive);
+  }
+  var t = (-b - sqrt(disc)) / max(a, 1e-12);
+  var h = dot(o, w) + t * dw;
+  if(a < 1e-12){
+    // Along the axis, the ray reaches the end it points at first.
+    h = select(-1.0, height + 1.0, dw < 0.0);
+  }
+  var normal = op + t * dp;
+  if(h < 0.0 || h > height){
+    let oc = o - clamp(h, 0.0, height) * w;
+    let k = dot(oc, dir);
+    let end_disc = k * k - dot(oc, oc) + r * r;
+    if(end_disc < 0.0){
+      return miss(primitive);
+    }
+    t = -k - sqrt(end_disc);
+    normal = oc + t * dir;
+  }
+  let p = o + t * dir;
+  let pos = primitive.position + p;
+  if(dot(ray.dir, pos - ray.origin) <= 0.01){
+    return miss(primitive);
+  }
+  let uv = vec2f(turn(basis(w), p), clamp((dot(p, w) + r) / (height + 2.0 * r), 0.0, 1.0));
+  return HitInfo(true, normalize(normal), pos, abs(pos - ray.origin), primitive, -1, uv);
+}
+
+// Radius of the sphere where `hit_torus` starts its search, in units of the torus's bounding
+// radius. The bounding sphere itself touches the torus all around its rim, where the quartic is 0
+// at the start of the search and rounding can hide the ray's entry.
+const TORUS_SEARCH_RADIUS = 1.25;
+
+// Where `ray` enters the torus: the first point where
+// (|p|² + R² - r²)² - 4R²(|p|² - (p·u)²), a quartic in the distance along the ray, turns from
+// positive outside the torus to negative inside. The search runs in units of the bounding radius
+// from where the ray enters a sphere a little larger, which keeps the coefficients near 1, and no
+// nearer than the 0.01 that every hit must lie ahead, so that rays leaving the surface do not find
+// it again at once. The UV runs around the axis, then around the tube.
+fn hit_torus(primitive: Primitive, ray: Ray) -> HitInfo{
+  let bound = primitive.radius + primitive.v.x;
+  let major = primitive.radius / bound;
+  let minor = primitive.v.x / bound;
+  let axis = primitive.u;
+  let dir = ray.dir / abs(ray.dir);
+  let o = (ray.origin - primitive.position) / bound;
+  let k0 = dot(o, dir);
+  let disc = k0 * k0 - dot(o, o) + TORUS_SEARCH_RADIUS * TORUS_SEARCH_RADIUS;
+  if(disc < 0.0){
+    return miss(primitive);
+  }
+  let start = max(-k0 - sqrt(disc), 0.01 / abs(ray.dir) / bound);
+  let end = -k0 + sqrt(disc);
+  if(start >= end){
+    return miss(primitive);
+  }
+  let o1 = o + start * dir;
+  let m = dot(o1, o1);
+  let k = dot(o1, dir);
+  let q = dot(o1, axis);
+  let e = dot(dir, axis);
+  let s = m + major * major - minor * minor;
+  let rr = 4.0 * major * major;
+  let coefficients = vec4f(
+    s * s - rr * (m - q * q),
+    4.0 * k * s - 2.0 * rr * (k - q * e),
+    4.0 * k * k + 2.0 * s - rr * (1.0 - e * e),
+    4.0 * k,
+  );
+  let t = first_entry(coefficients, 0.0, end - start);
+  if(t < 0.0){
+    return miss(primitive);
+  }
+  let p = o1 + t * dir;
+  let pos = primitive.position + bound * p;
+  let along = dot(p, axis);
+  let normal = normalize((dot(p, p) + major * major - minor * minor) * p - 2.0 * major * major * (p - along * axis));
+  let frame = basis(axis);
+  let x = dot(p, frame[0]);
+  let y = dot(p, frame[1]);
+  let uv = vec2f(turn(frame, p), atan2(along, sqrt(x * x + y * y) - major) / (2.0 * PI) + 0.5);
+  return HitInfo(true, normal, pos, abs(pos - ray.origin), primitive, -1, uv);
+}
+
+// The quartic t⁴ + c.w t³ + c.z t² + c.y t + c.x.
+fn quartic(c: vec4f, t: f32) -> f32{
+  return (((t + c.w) * t + c.z) * t + c.y) * t + c.x;
+}
+
+fn quartic_slope(c: vec4f, t: f32) -> f32{
+  return ((4.0 * t + 3.0 * c.w) * t + 2.0 * c.z) * t + c.y;
+}
+
+// Where the quartic, or its derivative with `slope`, changes sign between `a` and `b`, given that
+// it does.
+fn bisect(c: vec4f, slope: bool, a: f32, b: f32) -> f32{
+  let negative = select(quartic(c, a), quartic_slope(c, a), slope) < 0.0;
+  var lo = a;
+  var hi = b;
+  for(var i = 0; i < 32; i++){
+    let mid = 0.5 * (lo + hi);
+    if((select(quartic(c, mid), quartic_slope(c, mid), slope) < 0.0) == negative){
+      lo = mid;
+    } else {
+      hi = mid;
+    }
+  }
+  return 0.5 * (lo + hi);
+}
+
+// The first t from `start` to `end` where the quartic turns from positive to not positive, or -1.
+// Between the roots of its second derivative the first derivative changes sign at most once, and
+// between those sign changes the quartic crosses zero at most once, where bisection finds it.
+fn first_entry(c: vec4f, start: f32, end: f32) -> f32{
+  var bends = array<f32, 4>(start, end, end, end);
+  let disc = 36.0 * c.w * c.w - 96.0 * c.z;
+  if(disc > 0.0){
+    bends[1] = clamp((-6.0 * c.w - sqrt(disc)) / 24.0, start, end);
+    bends[2] = clamp((-6.0 * c.w + sqrt(disc)) / 24.0, start, end);
+  }
+  var stops = array<f32, 5>(start, end, end, end, end);
+  var count = 1;
+  for(var i = 0; i < 3; i++){
+    if((quartic_slope(c, bends[i]) < 0.0) != (quartic_slope(c, bends[i + 1]) < 0.0)){
+      stops[count] = bisect(c, true, bends[i], bends[i + 1]);
+      count++;
+    }
+  }
+  stops[count] = end;
+  for(var i = 0; i < count; i++){
+    if(quartic(c, stops[i]) > 0.0 && quartic(c, stops[i + 1]) <= 0.0){
+      return bisect(c, false, stops[i], stops[i + 1]);
+    }
+  }
+  return -1.0;
+}
+
+// The near side of the sphere, with the UV of its longitude and latitude about y.
+fn Hit_sphere(sphere:Primitive, ray:Ray) -> HitInfo{
   let a:f32 = dot(sphere.position - ray.origin, ray.dir) / abs(ray.dir);
   let b:f32 = abs(sphere.position - ray.origin);
   let final1 = get_pos(ray.origin, ray.dir, sphere.position, sphere.radius);
   let final2 = gets_pos(ray.origin, ray.dir, sphere.position, sphere.radius);
   if(b*b - a*a < sphere.radius*sphere.radius && dot(ray.dir, final1-ray.origin) > 0.01){
     let normal = (final1-sphere.position)/abs(final1-sphere.position);
-    return HitInfo(true, normal, final1, abs(final1 - ray.origin), sphere);
+    let uv = vec2f(atan2(normal.x, normal.z) / (2.0 * PI) + 0.5, asin(clamp(normal.y, -1.0, 1.0)) / PI + 0.5);
+    return HitInfo(true, normal, final1, abs(final1 - ray.origin), sphere, -1, uv);
   }
-  return HitInfo(false, vec3(1,1,1), vec3(0,0,0), 99999, sphere);
+  return miss(sphere);
 }
 
 fn abs(vector:vec3f) -> f32{
@@ -376,7 +1412,7 @@ fn refractions(
     entry_pos: vec3<f32>,
     ray_dir: vec3<f32>,
     normal: vec3<f32>,
-    sphere: Sphere
+    sphere: Primitive
 ) -> transexit {
 
     let I = normalize(ray_dir);
@@ -0,0 +1,669 @@
+use {
+    anyhow::Result,
+    code::{
+        math::{Mat4, Quat, Vec3, Vec4},
+        DebugMode, Material, PathTracer, Scene, Shape,
+    },
+    winit::{
+        event::{ElementState, MouseButton, WindowEvent},
+        window::Window,
+    },
+};
+
+mod gizmo;
+mod history;
+mod input;
+mod painter;
+
+/// Overlay with panels for the camera, render settings, objects, lights and environment. Edits
+/// apply to the tracer right away, so changing anything restarts accumulation. Clicking the image
+/// selects the object under the cursor, which is outlined and shown in a properties window, and
+/// dragging its gizmo moves, turns or resizes it. Scene edits can be undone and redone.
+pub struct Overlay {
+    context: egui::Context,
+    input: input::Input,
+    painter: painter::Painter,
+    /// Whether the panels are shown. The viewer frees the cursor for them while they are.
+    pub visible: bool,
+    /// Index into `Scene::objects` of the selected object.
+    selected: Option<usize>,
+    gizmo: gizmo::Gizmo,
+    history: history::History,
+    /// Cursor position in pixels.
+    cursor: egui::Pos2,
+    /// Where the image was clicked since the last frame, to grab the gizmo or pick the object
+    /// there.
+    click: Option<egui::Pos2>,
+    /// Whether the left button was released since the last frame, which ends a gizmo drag.
+    released: bool,
+}
+
+impl Overlay {
+    /// An overlay drawn into textures of `target_format`.
+    pub fn new(device: &wgpu::Device, target_format: wgpu::TextureFormat) -> Overlay {
+        Overlay {
+            context: egui::Context::default(),
+            input: input::Input::new(),
+            painter: painter::Painter::new(device, target_format),
+            visible: false,
+            selected: None,
+            gizmo: gizmo::Gizmo::new(),
+            history: history::History::new(),
+            cursor: egui::Pos2::ZERO,
+            click: None,
+            released: false,
+        }
+    }
+
+    /// Starts over on a new device after the old one was lost. The selection and the edit
+    /// history are kept.
+    pub fn reconnect(&mut self, device: &wgpu::Device, target_format: wgpu::TextureFormat) {
+        // A new context uploads its textures to the new painter again.
+        self.context = egui::Context::default();
+        self.painter = painter::Painter::new(device, target_format);
+    }
+
+    /// Reverts the last scene edit.
+    pub fn undo(&mut self, renderer: &mut PathTracer) -> Result<()> {
+        match self.history.undo(renderer.scene()) {
+            Some(scene) => renderer.update_scene(scene),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies the last undone scene edit again.
+    pub fn redo(&mut self, renderer: &mut PathTracer) -> Result<()> {
+        match self.history.redo(renderer.scene()) {
+            Some(scene) => renderer.update_scene(scene),
+            None => Ok(()),
+        }
+    }
+
+    /// Passes `event` on to the panels. Returns whether they used it, in which case the viewer
+    /// should not react to it as well. Left clicks outside the panels are kept for the gizmo and
+    /// for picking.
+    pub fn on_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        if !self.visible {
+            return false;
+        }
+        self.input.on_event(event, window.scale_factor() as f32);
+        match event {
+            WindowEvent::KeyboardInput { .. } => self.context.wants_keyboard_input(),
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = egui::pos2(position.x as f32, position.y as f32);
+                self.wants_pointer()
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.released = true;
+                self.wants_pointer()
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if !self.wants_pointer() => {
+                self.click = Some(self.cursor);
+                true
+            }
+            WindowEvent::MouseInput { .. } | WindowEvent::MouseWheel { .. } => self.wants_pointer(),
+            _ => false,
+        }
+    }
+
+    /// Whether the pointer is over the panels or dragging one of their widgets.
+    pub fn wants_pointer(&self) -> bool {
+        self.visible && (self.context.is_pointer_over_area() || self.context.wants_pointer_input())
+    }
+
+    /// Lays out the panels for `renderer`, applies the edits made in them and draws them over
+    /// `target`, which has the window's size.
+    pub fn draw(
+        &mut self,
+        window: &Window,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        target: &wgpu::TextureView,
+        renderer: &mut PathTracer,
+    ) -> Result<()> {
+        if !self.visible {
+            renderer.selection = None;
+            return Ok(());
+        }
+        let size = window.inner_size();
+        let pixels_per_point = window.scale_factor() as f32;
+        let snap = self.input.modifiers().ctrl;
+        let input = self.input.take(size, pixels_per_point);
+
+        let mut scene = renderer.scene().clone();
+        self.selected = self.selected.filter(|&index| index < scene.objects().len());
+        if let Some(cursor) = self.click.take() {
+            let grabbed = self
+                .selected
+                .is_some_and(|index| self.gizmo.begin(renderer, placement(&scene, index), cursor));
+            if !grabbed {
+                self.selected = renderer.pick(cursor.x, cursor.y).map(|pick| pick.object);
+            }
+        }
+        match self.selected {
+            Some(index) => {
+                let before = placement(&scene, index);
+                let mut after = before;
+                self.gizmo.update(&mut after, self.cursor, snap);
+                if after != before {
+                    set_placement(&mut scene, index, after);
+                }
+            }
+            None => self.gizmo.end(),
+        }
+        if std::mem::take(&mut self.released) {
+            self.gizmo.end();
+        }
+
+        let selected = &mut self.selected;
+        let gizmo = &mut self.gizmo;
+        let cursor = self.cursor;
+        let (can_undo, can_redo) = (self.history.can_undo(), self.history.can_redo());
+        let (mut undo, mut redo) = (false, false);
+        let output = self.context.run(input, |ctx| {
+            egui::Window::new("Scene")
+                .default_width(300.0)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        undo = ui
+                            .add_enabled(can_undo, egui::Button::new("Undo"))
+                            .on_hover_text("Ctrl+Z")
+                            .clicked();
+                        redo = ui
+                            .add_enabled(can_redo, egui::Button::new("Redo"))
+                            .on_hover_text("Ctrl+Y")
+                            .clicked();
+                        ui.label("Ctrl+S saves the scene.");
+                    });
+                    ui.label(renderer.stats().to_string());
+                    egui::CollapsingHeader::new("Camera")
+                        .default_open(true)
+                        .show(ui, |ui| camera_panel(ui, renderer));
+                    egui::CollapsingHeader::new("Render settings")
+                        .show(ui, |ui| settings_panel(ui, renderer));
+                    egui::CollapsingHeader::new("Objects")
+                        .show(ui, |ui| objects_panel(ui, &scene, selected));
+                    egui::CollapsingHeader::new("Lights")
+                        .show(ui, |ui| lights_panel(ui, &mut scene, selected));
+                    egui::CollapsingHeader::new("Environment")
+                        .show(ui, |ui| environment_panel(ui, &mut scene));
+                });
+            if let Some(index) = *selected {
+                // Drawn beneath the windows, straight over the traced image.
+                let painter = ctx.layer_painter(egui::LayerId::background());
+                gizmo.paint(&painter, renderer, placement(&scene, index), cursor, pixels_per_point);
+                let mut open = true;
+                egui::Window::new("Properties")
+                    .open(&mut open)
+                    .pivot(egui::Align2::RIGHT_BOTTOM)
+                    .default_pos(ctx.content_rect().right_bottom() - egui::vec2(16.0, 16.0))
+                    .default_width(300.0)
+                    .show(ctx, |ui| {
+                        gizmo_panel(ui, gizmo);
+                        ui.separator();
+                        properties_panel(ui, &mut scene, index);
+                    });
+                if !open {
+                    *selected = None;
+                }
+            }
+        });
+        if scene != *renderer.scene() {
+            self.history.edit(renderer.scene());
+            renderer.update_scene(scene)?;
+        }
+        // Changes made while a button is held or a field is being typed into are one edit.
+        let editing = self.gizmo.dragging()
+            || self.context.input(|input| input.pointer.any_down())
+            || self.context.wants_keyboard_input();
+        if !editing {
+            self.history.finish_edit();
+        }
+        if undo {
+            self.undo(renderer)?;
+        }
+        if redo {
+            self.redo(renderer)?;
+        }
+        renderer.selection = self.selected;
+
+        let primitives = self.context.tessellate(output.shapes, output.pixels_per_point);
+        self.painter.update_textures(device, queue, &output.textures_delta);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("ui"),
+        });
+        self.painter.paint(
+            device,
+            queue,
+            &mut encoder,
+            target,
+            [size.width, size.height],
+            output.pixels_per_point,
+            &primitives,
+        );
+        queue.submit(Some(encoder.finish()));
+        self.painter.free_textures(&output.textures_delta);
+        Ok(())
+    }
+}
+
+fn camera_panel(ui: &mut egui::Ui, renderer: &mut PathTracer) {
+    let camera = &mut renderer.camera;
+    let mut origin = rgb(camera.origin());
+    let moved = ui
+        .horizontal(|ui| {
+            ui.label("Position");
+            origin
+                .iter_mut()
+                .map(|value| ui.add(egui::DragValue::new(value).speed(0.01)).changed())
+                .fold(false, |a, b| a | b)
+        })
+        .inner;
+    if moved {
+        camera.set_origin(with_rgb(camera.origin(), origin));
+    }
+
+    // Only applied when edited, since the round trip through degrees is not exact.
+    let (mut pitch, mut yaw) = (camera.pitch().to_degrees(), camera.yaw().to_degrees());
+    let turned = ui
+        .horizontal(|ui| {
+            ui.label("Pitch");
+            let pitch_changed = ui
+                .add(egui::DragValue::new(&mut pitch).range(-90.0..=90.0).suffix("°"))
+                .changed();
+            ui.label("Yaw");
+            pitch_changed | ui.add(egui::DragValue::new(&mut yaw).suffix("°")).changed()
+        })
+        .inner;
+    if turned {
+        camera.rotate(pitch.to_radians() - camera.pitch(), yaw.to_radians() - camera.yaw());
+        camera.set_w();
+    }
+    ui.add(
+        egui::Slider::new(&mut renderer.fov, 0.25..=1000.0)
+            .logarithmic(true)
+            .text("Zoom"),
+    );
+}
+
+fn settings_panel(ui: &mut egui::Ui, renderer: &mut PathTracer) {
+    let settings = &mut renderer.settings;
+    egui::Grid::new("settings").num_columns(2).show(ui, |ui| {
+        ui.label("Max bounces");
+        ui.add(egui::DragValue::new(&mut settings.max_bounces).range(1..=10_000));
+        ui.end_row();
+        ui.label("Samples per frame");
+        ui.add(egui::DragValue::new(&mut settings.samples).range(1..=1024));
+        ui.end_row();
+        ui.label("Max transparent bounces");
+        ui.add(egui::DragValue::new(&mut settings.max_transparent_bounces).range(1..=10_000));
+        ui.end_row();
+        ui.label("Seed");
+        ui.add(egui::DragValue::new(&mut settings.seed));
+        ui.end_row();
+        ui.label("Anti-aliasing");
+        ui.checkbox(&mut settings.anti_aliasing, "");
+        ui.end_row();
+        ui.label("Anti-aliasing radius");
+        ui.add(egui::DragValue::new(&mut settings.anti_aliasing_radius).range(0..=64));
+        ui.end_row();
+        ui.label("Anti-aliasing weight");
+        ui.add(egui::DragValue::new(&mut settings.anti_aliasing_weight).speed(0.05).range(0.0..=100.0));
+        ui.end_row();
+    });
+    ui.checkbox(&mut renderer.denoise, "Denoise");
+    egui::ComboBox::from_label("Debug view")
+        .selected_text(renderer.debug_mode.name())
+        .show_ui(ui, |ui| {
+            for mode in DebugMode::ALL {
+                ui.selectable_value(&mut renderer.debug_mode, mode, mode.name());
+            }
+        });
+}
+
+fn objects_panel(ui: &mut egui::Ui, scene: &Scene, selected: &mut Option<usize>) {
+    let objects = scene.objects();
+    if objects.is_empty() {
+        ui.label("The scene is empty.");
+        return;
+    }
+    for (index, object) in objects.iter().enumerate() {
+        if ui.selectable_label(*selected == Some(index), object.name).clicked() {
+            *selected = Some(index);
+        }
+    }
+}
+
+fn gizmo_panel(ui: &mut egui::Ui, gizmo: &mut gizmo::Gizmo) {
+    ui.horizontal(|ui| {
+        ui.selectable_value(&mut gizmo.mode, gizmo::Mode::Translate, "Move");
+        ui.selectable_value(&mut gizmo.mode, gizmo::Mode::Rotate, "Rotate");
+        ui.selectable_value(&mut gizmo.mode, gizmo::Mode::Scale, "Scale");
+    });
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut gizmo.snap, "Snap").on_hover_text("Hold Ctrl while dragging to snap as well.");
+        ui.add(egui::DragValue::new(&mut gizmo.translate_step).speed(0.001).range(0.001..=10.0).prefix("move "));
+        ui.add(egui::DragValue::new(&mut gizmo.rotate_step).speed(0.5).range(1.0..=90.0).prefix("rotate ").suffix("°"));
+        ui.add(egui::DragValue::new(&mut gizmo.scale_step).speed(0.01).range(0.01..=10.0).prefix("scale "));
+    });
+}
+
+/// Editor for the object at `index`, shown for the selection.
+fn properties_panel(ui: &mut egui::Ui, scene: &mut Scene, index: usize) {
+    match scene.objects()[index].node {
+        Some(node) => node_properties(ui, scene, node),
+        None => primitive_properties(ui, scene, index),
+    }
+}
+
+fn primitive_properties(ui: &mut egui::Ui, scene: &mut Scene, index: usize) {
+    let primitive = &mut scene.primitives[index];
+    ui.heading(&primitive.name);
+    egui::Grid::new("shape").num_columns(2).show(ui, |ui| match &mut primitive.shape {
+        Shape::Sphere { center, radius } => {
+            vector_row(ui, "Center", center);
+            radius_row(ui, "Radius", radius, 0.001);
+        }
+        Shape::Plane { point, normal } => {
+            vector_row(ui, "Point", point);
+            vector_row(ui, "Normal", normal);
+        }
+        Shape::Quad { corner, u, v } => {
+            vector_row(ui, "Corner", corner);
+            vector_row(ui, "Edge u", u);
+            vector_row(ui, "Edge v", v);
+        }
+        Shape::Disk { center, normal, radius } => {
+            vector_row(ui, "Center", center);
+            vector_row(ui, "Normal", normal);
+            radius_row(ui, "Radius", radius, 0.001);
+        }
+        Shape::Box { min, max } => {
+            vector_row(ui, "Min", min);
+            vector_row(ui, "Max", max);
+        }
+        Shape::Cylinder { base, axis, radius } | Shape::Capsule { base, axis, radius } => {
+            vector_row(ui, "Base", base);
+            vector_row(ui, "Axis", axis);
+            radius_row(ui, "Radius", radius, 0.001);
+        }
+        Shape::Cone { base, axis, base_radius, top_radius } => {
+            vector_row(ui, "Base", base);
+            vector_row(ui, "Axis", axis);
+            // Either end can come to a point, but not both.
+            radius_row(ui, "Base radius", base_radius, if *top_radius > 0.0 { 0.0 } else { 0.001 });
+            radius_row(ui, "Top radius", top_radius, if *base_radius > 0.0 { 0.0 } else { 0.001 });
+        }
+        Shape::Torus { center, axis, major_radius, minor_radius } => {
+            vector_row(ui, "Center", center);
+            vector_row(ui, "Axis", axis);
+            radius_row(ui, "Major radius", major_radius, 0.001);
+            radius_row(ui, "Minor radius", minor_radius, 0.001);
+        }
+    });
+
+    ui.separator();
+    // Materials are shared, so editing one changes every object that uses it.
+    egui::ComboBox::from_label("Material")
+        .selected_text(&scene.materials[primitive.material].name)
+        .show_ui(ui, |ui| {
+            for (index, material) in scene.materials.iter().enumerate() {
+                ui.selectable_value(&mut primitive.material, index, &material.name);
+            }
+        });
+    material_panel(ui, &mut scene.materials[primitive.material]);
+}
+
+fn vector_row(ui: &mut egui::Ui, label: &str, vector: &mut Vec3) {
+    ui.label(label);
+    let mut values: [f32; 3] = (*vector).into();
+    ui.horizontal(|ui| {
+        for value in &mut values {
+            ui.add(egui::DragValue::new(value).speed(0.005));
+        }
+    });
+    *vector = values.into();
+    ui.end_row();
+}
+
+fn radius_row(ui: &mut egui::Ui, label: &str, radius: &mut f32, min: f32) {
+    ui.label(label);
+    ui.add(egui::DragValue::new(radius).speed(0.002).range(min..=f32::MAX));
+    ui.end_row();
+}
+
+/// Editor for a node of the scene graph and the materials of the geometry it draws, which every
+/// other node drawing that geometry shares.
+fn node_properties(ui: &mut egui::Ui, scene: &mut Scene, index: usize) {
+    let node = &mut scene.nodes[index];
+    ui.heading(&node.name);
+    let geometry = node.geometry.map(|geometry| &scene.geometries[geometry]);
+    if let Some(geometry) = geometry {
+        ui.label(format!("Draws {}", geometry.name));
+    }
+    if let Some(parent) = node.parent {
+        ui.label(format!("Placed in {}", scene.nodes[parent].name));
+    }
+    let node = &mut scene.nodes[index];
+    let transform = &mut node.transform;
+    egui::Grid::new("transform").num_columns(2).show(ui, |ui| {
+        ui.label("Translation");
+        let mut translation: [f32; 3] = transform.translation.into();
+        ui.horizontal(|ui| {
+            for value in &mut translation {
+                ui.add(egui::DragValue::new(value).speed(0.005));
+            }
+        });
+        transform.translation = translation.into();
+        ui.end_row();
+
+        // Only applied when edited, since the round trip through angles is not exact.
+        ui.label("Rotation");
+        let mut angles = transform.rotation.to_euler().map(f32::to_degrees);
+        let turned = ui
+            .horizontal(|ui| {
+                angles
+                    .iter_mut()
+                    .map(|value| ui.add(egui::DragValue::new(value).suffix("°")).changed())
+                    .fold(false, |a, b| a | b)
+            })
+            .inner;
+        if turned {
+            let [x, y, z] = angles.map(f32::to_radians);
+            transform.rotation = Quat::from_euler(x, y, z);
+        }
+        ui.end_row();
+
+        ui.label("Scale");
+        let mut scale: [f32; 3] = transform.scale.into();
+        ui.horizontal(|ui| {
+            for value in &mut scale {
+                ui.add(egui::DragValue::new(value).speed(0.005).range(0.001..=f32::MAX));
+            }
+        });
+        transform.scale = scale.into();
+        ui.end_row();
+    });
+
+    let Some(geometry) = node.geometry else {
+        return;
+    };
+    let mut materials: Vec<usize> = scene.geometries[geometry]
+        .primitives
+        .iter()
+        .map(|primitive| primitive.material)
+        .collect();
+    materials.sort_unstable();
+    materials.dedup();
+    for material in materials {
+        ui.separator();
+        let material = &mut scene.materials[material];
+        ui.label(&material.name);
+        let id = material.name.clone();
+        ui.push_id(id, |ui| material_panel(ui, material));
+    }
+}
+
+fn material_panel(ui: &mut egui::Ui, material: &mut Material) {
+    egui::Grid::new("material").num_columns(2).show(ui, |ui| {
+        ui.label("Color");
+        color_edit(ui, &mut material.color);
+        ui.end_row();
+        ui.label("Reflections");
+        ui.add(egui::Slider::new(&mut material.reflections, 0.0..=1.0));
+        ui.end_row();
+        ui.label("Transparency");
+        ui.add(egui::Slider::new(&mut material.transparency, 0.0..=1.0));
+        ui.end_row();
+        ui.label("Refractive index");
+        ui.add(egui::DragValue::new(&mut material.refractive_index).speed(0.01).range(0.0..=4.0));
+        ui.end_row();
+        ui.label("Emission");
+        emission_edit(ui, &mut material.emission);
+        ui.end_row();
+    });
+}
+
+/// Where the gizmo of the object at `index` sits and how the object is turned and sized: the
+/// world origin, rotation and scale of a node, or the center and size of a loose primitive, as
+/// `Shape::center` and `Shape::size` measure them. Loose primitives only scale uniformly and have
+/// no rotation of their own, so theirs is always the identity.
+fn placement(scene: &Scene, index: usize) -> gizmo::Placement {
+    match scene.objects()[index].node {
+        Some(node) => gizmo::Placement {
+            center: scene.world_transforms()[node].transform_point(Vec3::zero()).extend(0.0),
+            rotation: world_rotation(scene, Some(node)),
+            scale: scene.nodes[node].transform.scale,
+            per_axis: true,
+        },
+        None => {
+            let shape = &scene.primitives[index].shape;
+            gizmo::Placement {
+                center: shape.center().extend(0.0),
+                rotation: Quat::identity(),
+                scale: Vec3::all(shape.size()),
+                per_axis: false,
+            }
+        }
+    }
+}
+
+/// Moves, turns and resizes the object at `index` to `placement`, as `placement` measures it.
+fn set_placement(scene: &mut Scene, index: usize, placement: gizmo::Placement) {
+    match scene.objects()[index].node {
+        Some(node) => {
+            // The translation and rotation are relative to the parent.
+            let parent = scene.nodes[node].parent;
+            let to_parent = match parent {
+                Some(parent) => scene.world_transforms()[parent].inverse(),
+                None => Some(Mat4::identity()),
+            };
+            let parent_rotation = world_rotation(scene, parent);
+            let transform = &mut scene.nodes[node].transform;
+            if let Some(to_parent) = to_parent {
+                transform.translation = to_parent.transform_point(placement.center.xyz());
+            }
+            transform.rotation = (parent_rotation.conjugate() * placement.rotation).normalized();
+            transform.scale = placement.scale;
+        }
+        None => {
+            // The rotation is a turn from the current orientation, which `placement` reports as
+            // the identity.
+            let shape = &mut scene.primitives[index].shape;
+            *shape = shape.rotated(placement.rotation).placed(placement.center.xyz(), placement.scale.x());
+        }
+    }
+}
+
+/// The rotation of `node` in the world, from its own and its ancestors' rotations, or the identity
+/// for the world itself.
+fn world_rotation(scene: &Scene, mut node: Option<usize>) -> Quat {
+    let mut rotation = Quat::identity();
+    while let Some(index) = node {
+        rotation = scene.nodes[index].transform.rotation * rotation;
+        node = scene.nodes[index].parent;
+    }
+    rotation
+}
+
+fn lights_panel(ui: &mut egui::Ui, scene: &mut Scene, selected: &mut Option<usize>) {
+    let mut any = false;
+    // Each light with the first emissive material it uses.
+    let lights: Vec<(usize, String, usize)> = scene
+        .objects()
+        .iter()
+        .enumerate()
+        .filter_map(|(index, object)| {
+            let material = object
+                .primitives
+                .iter()
+                .map(|primitive| primitive.material)
+                .find(|&material| scene.materials[material].emission != Vec4::zero())?;
+            Some((index, object.name.to_string(), material))
+        })
+        .collect();
+    for (index, name, material) in lights {
+        any = true;
+        ui.horizontal(|ui| {
+            if ui.selectable_label(*selected == Some(index), name).clicked() {
+                *selected = Some(index);
+            }
+            emission_edit(ui, &mut scene.materials[material].emission);
+        });
+    }
+    if !any {
+        ui.label("Nothing emits light. Give a material an emission in the properties of an object to add a light.");
+    }
+}
+
+fn environment_panel(ui: &mut egui::Ui, scene: &mut Scene) {
+    let environment = &mut scene.environment;
+    egui::Grid::new("environment").num_columns(2).show(ui, |ui| {
+        ui.label("Zenith");
+        color_edit(ui, &mut environment.zenith);
+        ui.end_row();
+        ui.label("Nadir");
+        color_edit(ui, &mut environment.nadir);
+        ui.end_row();
+        ui.label("Intensity");
+        ui.add(egui::Slider::new(&mut environment.intensity, 0.0..=10.0));
+        ui.end_row();
+    });
+}
+
+/// Color picker for the RGB part of `color`. The picker converts through HSV, which is not exact,
+/// so the color is only written back when edited.
+fn color_edit(ui: &mut egui::Ui, color: &mut Vec4) {
+    let mut values = rgb(*color);
+    if ui.color_edit_button_rgb(&mut values).changed() {
+        *color = with_rgb(*color, values);
+    }
+}
+
+/// Emitted radiance is not limited to 1, so it gets number fields rather than a color picker.
+fn emission_edit(ui: &mut egui::Ui, emission: &mut Vec4) {
+    let mut values = rgb(*emission);
+    ui.horizontal(|ui| {
+        for value in &mut values {
+            ui.add(egui::DragValue::new(value).speed(0.05).range(0.0..=f32::MAX));
+        }
+    });
+    *emission = with_rgb(*emission, values);
+}
+
+fn rgb(v: Vec4) -> [f32; 3] {
+    [v.x(), v.y(), v.z()]
+}
+
+/// `v` with its first three components replaced.
+fn with_rgb(v: Vec4, [x, y, z]: [f32; 3]) -> Vec4 {
+    Vec4::new(x, y, z, v.w())
+}
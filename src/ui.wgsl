@@ -0,0 +1,49 @@
+// Draws the meshes egui tessellates for the overlay. Vertex colors are sRGB with premultiplied
+// alpha, and textures are sampled from sRGB textures, so blending happens in linear light and the
+// result is encoded again for targets that are not sRGB themselves.
+
+struct UiUniforms {
+  // Size of the target in egui points.
+  screen_size: vec2f,
+  _pad: vec2f,
+}
+@group(0) @binding(0) var<uniform> ui: UiUniforms;
+@group(1) @binding(0) var ui_texture: texture_2d<f32>;
+@group(1) @binding(1) var ui_sampler: sampler;
+
+// Whether the target stores linear values (sRGB and float formats) or gamma encoded ones.
+override LINEAR_OUTPUT: bool = false;
+
+struct UiVertex {
+  @builtin(position) position: vec4f,
+  @location(0) uv: vec2f,
+  @location(1) color: vec4f,
+}
+
+fn linear_from_srgb(srgb: vec3f) -> vec3f {
+  let low = srgb / 12.92;
+  let high = pow((srgb + 0.055) / 1.055, vec3f(2.4));
+  return select(high, low, srgb <= vec3f(0.04045));
+}
+
+fn srgb_from_linear(linear: vec3f) -> vec3f {
+  let low = linear * 12.92;
+  let high = 1.055 * pow(linear, vec3f(1.0 / 2.4)) - 0.055;
+  return select(high, low, linear <= vec3f(0.0031308));
+}
+
+@vertex fn ui_vs(@location(0) pos: vec2f, @location(1) uv: vec2f, @location(2) color: vec4f) -> UiVertex {
+  var out: UiVertex;
+  out.position = vec4f(2.0 * pos.x / ui.screen_size.x - 1.0, 1.0 - 2.0 * pos.y / ui.screen_size.y, 0.0, 1.0);
+  out.uv = uv;
+  out.color = vec4f(linear_from_srgb(color.rgb), color.a);
+  return out;
+}
+
+@fragment fn ui_fs(in: UiVertex) -> @location(0) vec4f {
+  let color = in.color * textureSample(ui_texture, ui_sampler, in.uv);
+  if(LINEAR_OUTPUT){
+    return color;
+  }
+  return vec4f(srgb_from_linear(color.rgb), color.a);
+}
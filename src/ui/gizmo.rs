@@ -0,0 +1,399 @@
+use {
+    super::{rgb, with_rgb},
+    code::{
+        math::{Quat, Vec3, Vec4},
+        PathTracer,
+    },
+};
+
+/// Length of the gizmo's axes on screen, in pixels.
+const HANDLE_PIXELS: f32 = 80.0;
+
+/// How close the cursor has to be to a handle to grab it, in pixels.
+const GRAB_PIXELS: f32 = 8.0;
+
+/// Number of straight pieces each rotation ring is drawn with.
+const RING_SEGMENTS: usize = 48;
+
+const AXIS_COLORS: [egui::Color32; 3] = [
+    egui::Color32::from_rgb(230, 60, 60),
+    egui::Color32::from_rgb(80, 200, 60),
+    egui::Color32::from_rgb(60, 120, 240),
+];
+const CENTER_COLOR: egui::Color32 = egui::Color32::from_rgb(220, 220, 220);
+const ACTIVE_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 200, 40);
+
+/// What dragging the gizmo does to the selected object.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+/// Where an object is, how it is turned and how large it is, as the gizmo edits it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Placement {
+    pub center: Vec4,
+    /// Orientation in the world, which the scale handles follow.
+    pub rotation: Quat,
+    /// Size along each of the object's own axes.
+    pub scale: Vec3,
+    /// Whether the axes of `scale` change separately. Otherwise every scale handle resizes the
+    /// object as a whole.
+    pub per_axis: bool,
+}
+
+/// A part of the gizmo that can be dragged.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Handle {
+    /// Constrains the edit to the axis with this index: the world axis to move along or turn
+    /// about, or the object's own axis to scale along.
+    Axis(usize),
+    /// Moves in the view plane, or scales by the distance from the center.
+    Center,
+}
+
+/// Where the gizmo appears for an object, in pixels.
+#[derive(Copy, Clone)]
+struct Layout {
+    center: egui::Pos2,
+    /// Ends of the axes, where they are in front of the camera.
+    ends: [Option<egui::Pos2>; 3],
+    /// Points around the ring about each world axis, where they are in front of the camera.
+    rings: [[Option<egui::Pos2>; RING_SEGMENTS]; 3],
+    /// World directions of the axes, the object's own for scaling and the world's otherwise.
+    axes: [Vec3; 3],
+    /// World length of the axes, chosen so that they are `HANDLE_PIXELS` long on screen.
+    length: f32,
+    /// Camera directions spanning the view plane and looking into it.
+    right: Vec4,
+    up: Vec4,
+    forward: Vec4,
+}
+
+/// A drag in progress, with the state it started from.
+struct Drag {
+    handle: Handle,
+    cursor: egui::Pos2,
+    layout: Layout,
+    placement: Placement,
+    /// Where the cursor was at the last update, and the angle it has swept around the center
+    /// since the start, in radians.
+    last: egui::Pos2,
+    swept: f32,
+    /// Angle the object has been turned by so far, after snapping.
+    turned: f32,
+}
+
+/// Handles drawn over the selected object for moving, turning and resizing it.
+pub struct Gizmo {
+    pub mode: Mode,
+    /// Whether edits snap to `translate_step`, `rotate_step` and `scale_step`. Holding Ctrl snaps
+    /// as well.
+    pub snap: bool,
+    /// Grid that translated coordinates snap to, in world units.
+    pub translate_step: f32,
+    /// Step that rotation angles snap to, in degrees.
+    pub rotate_step: f32,
+    /// Step that scale factors snap to.
+    pub scale_step: f32,
+    drag: Option<Drag>,
+}
+
+impl Gizmo {
+    pub fn new() -> Gizmo {
+        Gizmo {
+            mode: Mode::Translate,
+            snap: false,
+            translate_step: 0.01,
+            rotate_step: 15.0,
+            scale_step: 0.1,
+            drag: None,
+        }
+    }
+
+    /// Starts dragging if `cursor` is on one of the handles of the gizmo for an object at
+    /// `placement`. Returns whether it was.
+    pub fn begin(&mut self, renderer: &PathTracer, placement: Placement, cursor: egui::Pos2) -> bool {
+        let Some(layout) = layout(renderer, self.axes(&placement), placement.center) else {
+            return false;
+        };
+        let Some(handle) = layout.handle_at(self.mode, cursor) else {
+            return false;
+        };
+        self.drag = Some(Drag {
+            handle,
+            cursor,
+            layout,
+            placement,
+            last: cursor,
+            swept: 0.0,
+            turned: 0.0,
+        });
+        true
+    }
+
+    pub fn dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    pub fn end(&mut self) {
+        self.drag = None;
+    }
+
+    /// Applies the drag in progress to the `placement` of the object for the cursor at `cursor`.
+    /// Moves and scales are measured from where the drag started, while turns apply the angle
+    /// swept since the last update to the current rotation.
+    pub fn update(&mut self, placement: &mut Placement, cursor: egui::Pos2, snap: bool) {
+        let Some(drag) = &mut self.drag else {
+            return;
+        };
+        let snap = snap || self.snap;
+        let moved = cursor - drag.cursor;
+        let layout = &drag.layout;
+        let start = &drag.placement;
+        match (self.mode, drag.handle) {
+            (Mode::Translate, Handle::Axis(axis)) => {
+                let Some(along) = layout.along_axis(axis, moved) else {
+                    return;
+                };
+                let mut moved = rgb(start.center);
+                moved[axis] += along * layout.length;
+                if snap {
+                    moved[axis] = snap_to(moved[axis], self.translate_step);
+                }
+                placement.center = with_rgb(start.center, moved);
+            }
+            (Mode::Translate, Handle::Center) => {
+                let scale = layout.length / HANDLE_PIXELS;
+                let offset = (moved.x * layout.right - moved.y * layout.up) * scale;
+                let mut moved = rgb(start.center + offset);
+                if snap {
+                    moved = moved.map(|value| snap_to(value, self.translate_step));
+                }
+                placement.center = with_rgb(start.center, moved);
+            }
+            (Mode::Rotate, Handle::Axis(axis)) => {
+                let (from, to) = (drag.last - layout.center, cursor - layout.center);
+                drag.last = cursor;
+                if from.length() < 1.0 || to.length() < 1.0 {
+                    return;
+                }
+                // Screen y points down, so positive angles go clockwise on screen, which is a
+                // positive turn about an axis pointing away from the camera.
+                drag.swept += (from.x * to.y - from.y * to.x).atan2(from.dot(to));
+                let mut angle = drag.swept;
+                if snap {
+                    angle = snap_to(angle.to_degrees(), self.rotate_step).to_radians();
+                }
+                let direction = layout.axes[axis];
+                let away = direction.extend(0.0).dot(&layout.forward) > 0.0;
+                let turn = if away { angle - drag.turned } else { drag.turned - angle };
+                drag.turned = angle;
+                placement.rotation = (Quat::from_axis_angle(direction, turn) * placement.rotation).normalized();
+            }
+            (Mode::Rotate, Handle::Center) => {}
+            (Mode::Scale, handle) => {
+                let factor = match handle {
+                    // Dragging by the length of an axis doubles the size.
+                    Handle::Axis(axis) => layout.along_axis(axis, moved).map(|along| 1.0 + along),
+                    Handle::Center => {
+                        let start = (drag.cursor - layout.center).length();
+                        (start > 0.0).then(|| (cursor - layout.center).length() / start)
+                    }
+                };
+                let Some(mut factor) = factor else {
+                    return;
+                };
+                if snap {
+                    factor = snap_to(factor, self.scale_step);
+                }
+                let factor = factor.max(0.001);
+                placement.scale = match handle {
+                    Handle::Axis(axis) if start.per_axis => {
+                        let mut scale = [start.scale.x(), start.scale.y(), start.scale.z()];
+                        scale[axis] *= factor;
+                        Vec3::from(scale)
+                    }
+                    _ => start.scale * factor,
+                };
+            }
+        }
+    }
+
+    /// Draws the gizmo for an object at `placement` with `painter`, highlighting the handle under
+    /// `cursor` or being dragged.
+    pub fn paint(
+        &self,
+        painter: &egui::Painter,
+        renderer: &PathTracer,
+        placement: Placement,
+        cursor: egui::Pos2,
+        pixels_per_point: f32,
+    ) {
+        let Some(layout) = layout(renderer, self.axes(&placement), placement.center) else {
+            return;
+        };
+        let active = match &self.drag {
+            Some(drag) => Some(drag.handle),
+            None => layout.handle_at(self.mode, cursor),
+        };
+        let points = |pos: egui::Pos2| (pos.to_vec2() / pixels_per_point).to_pos2();
+        let center = points(layout.center);
+        let color = |axis: usize| {
+            if active == Some(Handle::Axis(axis)) {
+                ACTIVE_COLOR
+            } else {
+                AXIS_COLORS[axis]
+            }
+        };
+        if self.mode == Mode::Rotate {
+            for (axis, ring) in layout.rings.iter().enumerate() {
+                let stroke = egui::Stroke::new(2.5, color(axis));
+                for (start, end) in ring_segments(ring) {
+                    painter.line_segment([points(start), points(end)], stroke);
+                }
+            }
+            return;
+        }
+        for (axis, end) in layout.ends.iter().enumerate() {
+            let Some(end) = end else {
+                continue;
+            };
+            let end = points(*end);
+            let color = color(axis);
+            painter.line_segment([center, end], egui::Stroke::new(2.5, color));
+            if self.mode == Mode::Translate {
+                painter.circle_filled(end, 5.0, color);
+            } else {
+                painter.rect_filled(egui::Rect::from_center_size(end, egui::vec2(9.0, 9.0)), 0.0, color);
+            }
+        }
+        let color = if active == Some(Handle::Center) {
+            ACTIVE_COLOR
+        } else {
+            CENTER_COLOR
+        };
+        painter.circle_stroke(center, GRAB_PIXELS / pixels_per_point, egui::Stroke::new(2.0, color));
+    }
+
+    /// Directions of the handles: the object's own axes when scaling, and the world's otherwise.
+    fn axes(&self, placement: &Placement) -> [Vec3; 3] {
+        std::array::from_fn(|axis| {
+            let mut direction = [0.0; 3];
+            direction[axis] = 1.0;
+            let direction = Vec3::from(direction);
+            match self.mode {
+                Mode::Scale => placement.rotation.rotate(direction),
+                Mode::Translate | Mode::Rotate => direction,
+            }
+        })
+    }
+}
+
+impl Layout {
+    /// The handle of `mode` at `cursor`, preferring the center where they overlap.
+    fn handle_at(&self, mode: Mode, cursor: egui::Pos2) -> Option<Handle> {
+        if mode == Mode::Rotate {
+            return self
+                .rings
+                .iter()
+                .enumerate()
+                .filter_map(|(axis, ring)| {
+                    let distance = ring_segments(ring)
+                        .map(|(start, end)| distance_to_segment(cursor, start, end))
+                        .min_by(f32::total_cmp)?;
+                    Some((axis, distance))
+                })
+                .filter(|&(_, distance)| distance <= GRAB_PIXELS)
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(axis, _)| Handle::Axis(axis));
+        }
+        if (cursor - self.center).length() <= GRAB_PIXELS {
+            return Some(Handle::Center);
+        }
+        self.ends
+            .iter()
+            .enumerate()
+            .filter_map(|(axis, end)| Some((axis, distance_to_segment(cursor, self.center, (*end)?))))
+            .filter(|&(_, distance)| distance <= GRAB_PIXELS)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(axis, _)| Handle::Axis(axis))
+    }
+
+    /// How far `moved` goes along `axis` on screen, as a fraction of the axis.
+    fn along_axis(&self, axis: usize, moved: egui::Vec2) -> Option<f32> {
+        let direction = self.ends[axis]? - self.center;
+        let length_squared = direction.length_sq();
+        (length_squared > 1.0).then(|| moved.dot(direction) / length_squared)
+    }
+}
+
+/// Lays the gizmo out around `position` with its axes along `axes`, as seen by `renderer`'s
+/// camera.
+fn layout(renderer: &PathTracer, axes: [Vec3; 3], position: Vec4) -> Option<Layout> {
+    let camera = &renderer.camera;
+    let origin = camera.origin();
+    let forward = camera.forward();
+    let forward = Vec4::new(forward.x(), forward.y(), forward.z(), 0.0).normalized();
+    let mut right = forward.cross(&Vec4::new(0.0, 1.0, 0.0, 0.0));
+    if right.length() < 1e-4 {
+        right = Vec4::new(1.0, 0.0, 0.0, 0.0);
+    }
+    let right = right.normalized();
+    let up = right.cross(&forward);
+
+    let center = renderer.project(position)?;
+    // Scale a probe along the view plane so that the axes keep their size on screen.
+    let probe = 0.1 * (position - origin).length();
+    let probe_pixels = (pos(renderer.project(position + probe * right)?) - pos(center)).length();
+    if probe_pixels <= 0.0 {
+        return None;
+    }
+    let length = probe * HANDLE_PIXELS / probe_pixels;
+    let ends = axes.map(|direction| renderer.project(position + (direction * length).extend(0.0)).map(pos));
+    // Each ring lies in the plane of the two world axes other than its own.
+    let rings = std::array::from_fn(|axis| {
+        std::array::from_fn(|segment| {
+            let angle = segment as f32 / RING_SEGMENTS as f32 * std::f32::consts::TAU;
+            let mut offset = [0.0; 3];
+            offset[(axis + 1) % 3] = length * angle.cos();
+            offset[(axis + 2) % 3] = length * angle.sin();
+            renderer.project(position + with_rgb(Vec4::zero(), offset)).map(pos)
+        })
+    });
+    Some(Layout {
+        center: pos(center),
+        ends,
+        rings,
+        axes,
+        length,
+        right,
+        up,
+        forward,
+    })
+}
+
+fn pos([x, y]: [f32; 2]) -> egui::Pos2 {
+    egui::pos2(x, y)
+}
+
+/// The pieces of a closed ring between points that are both in front of the camera.
+fn ring_segments(ring: &[Option<egui::Pos2>]) -> impl Iterator<Item = (egui::Pos2, egui::Pos2)> + '_ {
+    (0..ring.len()).filter_map(|i| Some((ring[i]?, ring[(i + 1) % ring.len()]?)))
+}
+
+fn distance_to_segment(point: egui::Pos2, start: egui::Pos2, end: egui::Pos2) -> f32 {
+    let segment = end - start;
+    let t = ((point - start).dot(segment) / segment.length_sq().max(1e-6)).clamp(0.0, 1.0);
+    (point - (start + t * segment)).length()
+}
+
+fn snap_to(value: f32, step: f32) -> f32 {
+    if step > 0.0 {
+        (value / step).round() * step
+    } else {
+        value
+    }
+}
@@ -0,0 +1,64 @@
+use code::Scene;
+
+/// Most edits kept for undoing.
+const MAX_STEPS: usize = 100;
+
+/// Scene states before each edit, for undo and redo. An edit that spans several frames, like
+/// dragging a slider or a gizmo, is recorded as one step.
+pub struct History {
+    undo: Vec<Scene>,
+    redo: Vec<Scene>,
+    /// Whether the last recorded edit is still going on, so that further changes belong to it.
+    editing: bool,
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            editing: false,
+        }
+    }
+
+    /// Records that the scene is changing from `before`, unless this continues the current edit.
+    pub fn edit(&mut self, before: &Scene) {
+        if !self.editing {
+            if self.undo.len() == MAX_STEPS {
+                self.undo.remove(0);
+            }
+            self.undo.push(before.clone());
+            self.redo.clear();
+            self.editing = true;
+        }
+    }
+
+    /// Ends the current edit; the next change starts a new step.
+    pub fn finish_edit(&mut self) {
+        self.editing = false;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// The scene before the last edit, given the `current` one.
+    pub fn undo(&mut self, current: &Scene) -> Option<Scene> {
+        self.editing = false;
+        let scene = self.undo.pop()?;
+        self.redo.push(current.clone());
+        Some(scene)
+    }
+
+    /// The scene after the last undone edit, given the `current` one.
+    pub fn redo(&mut self, current: &Scene) -> Option<Scene> {
+        self.editing = false;
+        let scene = self.redo.pop()?;
+        self.undo.push(current.clone());
+        Some(scene)
+    }
+}
@@ -0,0 +1,158 @@
+use {
+    std::time::Instant,
+    winit::{
+        dpi::PhysicalSize,
+        event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent},
+        keyboard::{Key, NamedKey},
+    },
+};
+
+/// Translates winit window events into egui input, collected between frames.
+pub struct Input {
+    events: Vec<egui::Event>,
+    modifiers: egui::Modifiers,
+    /// Last cursor position in points.
+    pointer: egui::Pos2,
+    focused: bool,
+    start: Instant,
+}
+
+impl Input {
+    pub fn new() -> Input {
+        Input {
+            events: Vec::new(),
+            modifiers: egui::Modifiers::default(),
+            pointer: egui::Pos2::ZERO,
+            focused: true,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn on_event(&mut self, event: &WindowEvent, pixels_per_point: f32) {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.pointer = egui::pos2(
+                    position.x as f32 / pixels_per_point,
+                    position.y as f32 / pixels_per_point,
+                );
+                self.events.push(egui::Event::PointerMoved(self.pointer));
+            }
+            WindowEvent::CursorLeft { .. } => self.events.push(egui::Event::PointerGone),
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button = match button {
+                    MouseButton::Left => egui::PointerButton::Primary,
+                    MouseButton::Right => egui::PointerButton::Secondary,
+                    MouseButton::Middle => egui::PointerButton::Middle,
+                    MouseButton::Back => egui::PointerButton::Extra1,
+                    MouseButton::Forward => egui::PointerButton::Extra2,
+                    MouseButton::Other(_) => return,
+                };
+                self.events.push(egui::Event::PointerButton {
+                    pos: self.pointer,
+                    button,
+                    pressed: *state == ElementState::Pressed,
+                    modifiers: self.modifiers,
+                });
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (unit, delta) = match *delta {
+                    MouseScrollDelta::LineDelta(x, y) => (egui::MouseWheelUnit::Line, egui::vec2(x, y)),
+                    MouseScrollDelta::PixelDelta(delta) => (
+                        egui::MouseWheelUnit::Point,
+                        egui::vec2(delta.x as f32, delta.y as f32) / pixels_per_point,
+                    ),
+                };
+                self.events.push(egui::Event::MouseWheel {
+                    unit,
+                    delta,
+                    modifiers: self.modifiers,
+                });
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                let state = modifiers.state();
+                self.modifiers = egui::Modifiers {
+                    alt: state.alt_key(),
+                    ctrl: state.control_key(),
+                    shift: state.shift_key(),
+                    mac_cmd: false,
+                    command: state.control_key(),
+                };
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
+                let pressed = event.state == ElementState::Pressed;
+                if let Some(key) = egui_key(&event.logical_key) {
+                    self.events.push(egui::Event::Key {
+                        key,
+                        physical_key: None,
+                        pressed,
+                        repeat: event.repeat,
+                        modifiers: self.modifiers,
+                    });
+                }
+                if let Some(text) = &event.text
+                    && pressed
+                    && !self.modifiers.ctrl
+                    && !text.chars().any(char::is_control)
+                {
+                    self.events.push(egui::Event::Text(text.to_string()));
+                }
+            }
+            WindowEvent::Focused(focused) => {
+                self.focused = *focused;
+                self.events.push(egui::Event::WindowFocused(*focused));
+            }
+            _ => {}
+        }
+    }
+
+    pub fn modifiers(&self) -> egui::Modifiers {
+        self.modifiers
+    }
+
+    /// The input collected since the last call, for a window of `size` pixels.
+    pub fn take(&mut self, size: PhysicalSize<u32>, pixels_per_point: f32) -> egui::RawInput {
+        let mut input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(size.width as f32, size.height as f32) / pixels_per_point,
+            )),
+            time: Some(self.start.elapsed().as_secs_f64()),
+            modifiers: self.modifiers,
+            events: std::mem::take(&mut self.events),
+            focused: self.focused,
+            ..Default::default()
+        };
+        input
+            .viewports
+            .entry(egui::ViewportId::ROOT)
+            .or_default()
+            .native_pixels_per_point = Some(pixels_per_point);
+        input
+    }
+}
+
+/// The egui key for the keys its widgets react to.
+fn egui_key(key: &Key) -> Option<egui::Key> {
+    match key {
+        Key::Named(named) => Some(match named {
+            NamedKey::ArrowDown => egui::Key::ArrowDown,
+            NamedKey::ArrowLeft => egui::Key::ArrowLeft,
+            NamedKey::ArrowRight => egui::Key::ArrowRight,
+            NamedKey::ArrowUp => egui::Key::ArrowUp,
+            NamedKey::Escape => egui::Key::Escape,
+            NamedKey::Tab => egui::Key::Tab,
+            NamedKey::Backspace => egui::Key::Backspace,
+            NamedKey::Enter => egui::Key::Enter,
+            NamedKey::Space => egui::Key::Space,
+            NamedKey::Insert => egui::Key::Insert,
+            NamedKey::Delete => egui::Key::Delete,
+            NamedKey::Home => egui::Key::Home,
+            NamedKey::End => egui::Key::End,
+            NamedKey::PageUp => egui::Key::PageUp,
+            NamedKey::PageDown => egui::Key::PageDown,
+            _ => return None,
+        }),
+        Key::Character(text) => egui::Key::from_name(&text.to_uppercase()),
+        _ => None,
+    }
+}
@@ -0,0 +1,351 @@
+use {
+    bytemuck::{Pod, Zeroable},
+    std::collections::HashMap,
+    wgpu::{self, PipelineCompilationOptions},
+};
+
+/// Mirrors `UiUniforms` in ui.wgsl.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct UiUniforms {
+    screen_size: [f32; 2],
+    _pad: [f32; 2],
+}
+
+/// A texture egui asked for, such as its font atlas.
+struct Texture {
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Draws egui's tessellated output with wgpu, on top of whatever the target already holds.
+pub struct Painter {
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    texture_layout: wgpu::BindGroupLayout,
+    textures: HashMap<egui::TextureId, Texture>,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+}
+
+impl Painter {
+    pub fn new(device: &wgpu::Device, target_format: wgpu::TextureFormat) -> Painter {
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("ui"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../ui.wgsl").into()),
+        });
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ui uniforms"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ui texture"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ui uniforms"),
+            size: std::mem::size_of::<UiUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ui uniforms"),
+            layout: &uniform_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ui"),
+            bind_group_layouts: &[&uniform_layout, &texture_layout],
+            push_constant_ranges: &[],
+        });
+        // Matches the target's encoding; see `output_constants` in the renderer.
+        let linear_output =
+            target_format.is_srgb() || target_format == wgpu::TextureFormat::Rgba16Float;
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("ui"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: Some("ui_vs"),
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<egui::epaint::Vertex>() as u64,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![
+                        0 => Float32x2,
+                        1 => Float32x2,
+                        2 => Unorm8x4,
+                    ],
+                }],
+                compilation_options: PipelineCompilationOptions::default(),
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point: Some("ui_fs"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: PipelineCompilationOptions {
+                    constants: &[("LINEAR_OUTPUT", linear_output as u32 as f64)],
+                    ..Default::default()
+                },
+            }),
+            multiview: None,
+            cache: None,
+        });
+
+        Painter {
+            pipeline,
+            uniform_buffer,
+            uniform_bind_group,
+            texture_layout,
+            textures: HashMap::new(),
+            vertex_buffer: create_buffer(device, "ui vertices", wgpu::BufferUsages::VERTEX, 1 << 16),
+            index_buffer: create_buffer(device, "ui indices", wgpu::BufferUsages::INDEX, 1 << 16),
+        }
+    }
+
+    /// Creates and updates the textures egui needs for the next `paint`.
+    pub fn update_textures(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        delta: &egui::TexturesDelta,
+    ) {
+        for (id, image_delta) in &delta.set {
+            let egui::ImageData::Color(image) = &image_delta.image;
+            let size = wgpu::Extent3d {
+                width: image.size[0] as u32,
+                height: image.size[1] as u32,
+                depth_or_array_layers: 1,
+            };
+            let origin = match image_delta.pos {
+                Some([x, y]) => wgpu::Origin3d { x: x as u32, y: y as u32, z: 0 },
+                None => {
+                    let texture = self.create_texture(device, size, image_delta.options);
+                    self.textures.insert(*id, texture);
+                    wgpu::Origin3d::ZERO
+                }
+            };
+            let Some(texture) = self.textures.get(id) else {
+                continue;
+            };
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture: &texture.texture,
+                    mip_level: 0,
+                    origin,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                bytemuck::cast_slice(&image.pixels),
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * size.width),
+                    rows_per_image: Some(size.height),
+                },
+                size,
+            );
+        }
+    }
+
+    /// Drops the textures egui no longer uses. Call after `paint`.
+    pub fn free_textures(&mut self, delta: &egui::TexturesDelta) {
+        for id in &delta.free {
+            self.textures.remove(id);
+        }
+    }
+
+    fn create_texture(
+        &self,
+        device: &wgpu::Device,
+        size: wgpu::Extent3d,
+        options: egui::TextureOptions,
+    ) -> Texture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("ui texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let filter = |filter| match filter {
+            egui::TextureFilter::Nearest => wgpu::FilterMode::Nearest,
+            egui::TextureFilter::Linear => wgpu::FilterMode::Linear,
+        };
+        let address_mode = match options.wrap_mode {
+            egui::TextureWrapMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+            egui::TextureWrapMode::Repeat => wgpu::AddressMode::Repeat,
+            egui::TextureWrapMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+        };
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("ui texture"),
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            mag_filter: filter(options.magnification),
+            min_filter: filter(options.minification),
+            ..Default::default()
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ui texture"),
+            layout: &self.texture_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+        Texture { texture, bind_group }
+    }
+
+    /// Draws `primitives` into `target`, which is `size` pixels large and shows
+    /// `pixels_per_point` pixels per egui point.
+    #[allow(clippy::too_many_arguments)]
+    pub fn paint(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        size: [u32; 2],
+        pixels_per_point: f32,
+        primitives: &[egui::ClippedPrimitive],
+    ) {
+        let uniforms = UiUniforms {
+            screen_size: [size[0] as f32 / pixels_per_point, size[1] as f32 / pixels_per_point],
+            _pad: [0.0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+
+        let meshes: Vec<_> = primitives
+            .iter()
+            .filter_map(|primitive| match &primitive.primitive {
+                egui::epaint::Primitive::Mesh(mesh) if !mesh.indices.is_empty() => {
+                    Some((primitive.clip_rect, mesh))
+                }
+                _ => None,
+            })
+            .collect();
+        let vertices: Vec<egui::epaint::Vertex> =
+            meshes.iter().flat_map(|(_, mesh)| mesh.vertices.iter().copied()).collect();
+        let indices: Vec<u32> = meshes.iter().flat_map(|(_, mesh)| mesh.indices.iter().copied()).collect();
+        if indices.is_empty() {
+            return;
+        }
+        let vertex_bytes: &[u8] = bytemuck::cast_slice(&vertices);
+        let index_bytes: &[u8] = bytemuck::cast_slice(&indices);
+        if self.vertex_buffer.size() < vertex_bytes.len() as u64 {
+            self.vertex_buffer =
+                create_buffer(device, "ui vertices", wgpu::BufferUsages::VERTEX, vertex_bytes.len() as u64);
+        }
+        if self.index_buffer.size() < index_bytes.len() as u64 {
+            self.index_buffer =
+                create_buffer(device, "ui indices", wgpu::BufferUsages::INDEX, index_bytes.len() as u64);
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, vertex_bytes);
+        queue.write_buffer(&self.index_buffer, 0, index_bytes);
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("ui"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                depth_slice: None,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+
+        let (mut first_index, mut base_vertex) = (0, 0);
+        for (clip_rect, mesh) in meshes {
+            let index_count = mesh.indices.len() as u32;
+            let indices = first_index..first_index + index_count;
+            let vertex_offset = base_vertex;
+            first_index += index_count;
+            base_vertex += mesh.vertices.len() as i32;
+
+            // The clip rectangle in pixels, clamped to the target.
+            let min_x = ((clip_rect.min.x * pixels_per_point).round() as u32).min(size[0]);
+            let min_y = ((clip_rect.min.y * pixels_per_point).round() as u32).min(size[1]);
+            let max_x = ((clip_rect.max.x * pixels_per_point).round() as u32).clamp(min_x, size[0]);
+            let max_y = ((clip_rect.max.y * pixels_per_point).round() as u32).clamp(min_y, size[1]);
+            if max_x == min_x || max_y == min_y {
+                continue;
+            }
+            let Some(texture) = self.textures.get(&mesh.texture_id) else {
+                continue;
+            };
+            pass.set_scissor_rect(min_x, min_y, max_x - min_x, max_y - min_y);
+            pass.set_bind_group(1, &texture.bind_group, &[]);
+            pass.draw_indexed(indices, vertex_offset, 0..1);
+        }
+    }
+}
+
+fn create_buffer(
+    device: &wgpu::Device,
+    label: &'static str,
+    usage: wgpu::BufferUsages,
+    size: u64,
+) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: size.next_power_of_two(),
+        usage: usage | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
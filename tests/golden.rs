@@ -0,0 +1,203 @@
+//! Renders the scenes in `tests/golden` headlessly on wgpu's fallback adapter, a software
+//! rasterizer, and compares them with the reference images checked in next to them.
+//!
+//! A render that is off by more than the tolerance is written with its error image to
+//! `target/tmp/golden`. Run with `UPDATE_GOLDEN=1` to replace the references with new renders.
+
+use {
+    anyhow::{Context, Result},
+    code::{
+        furnace_scene, math::Vec4, Aov, BuiltinScene, FurnaceReading, ImageComparison, Material, PathTracer,
+        RenderOutput, Scene, FURNACE_SIZE,
+    },
+    std::path::{Path, PathBuf},
+};
+
+const WIDTH: u32 = 96;
+const HEIGHT: u32 = 64;
+
+#[test]
+fn default_scene() {
+    check_golden("default", load("scenes/default.toml"), 32, false, 0.01);
+}
+
+#[test]
+fn materials() {
+    check_golden("materials", load("tests/golden/materials.toml"), 32, false, 0.01);
+}
+
+#[test]
+fn materials_denoised() {
+    check_golden("materials_denoised", load("tests/golden/materials.toml"), 32, true, 0.01);
+}
+
+#[test]
+fn instances() {
+    check_golden("instances", load("tests/golden/instances.toml"), 32, false, 0.01);
+}
+
+#[test]
+fn mechanical() {
+    check_golden("mechanical", load("tests/golden/mechanical.toml"), 32, false, 0.01);
+}
+
+#[test]
+fn primitives() {
+    check_golden("primitives", load("tests/golden/primitives.toml"), 32, false, 0.01);
+}
+
+#[test]
+fn sky() {
+    check_golden("sky", load("tests/golden/sky.toml"), 4, false, 0.001);
+}
+
+#[test]
+fn furnace() {
+    check_golden("furnace", BuiltinScene::Furnace.build(), 8, false, 0.001);
+}
+
+#[test]
+fn one_weekend() {
+    check_golden("one_weekend", BuiltinScene::OneWeekend.build(), 16, false, 0.01);
+}
+
+/// Without the denoiser the beauty pass and the lighting AOVs are accumulated alike, so the four
+/// lighting AOVs add up to it in every pixel.
+#[test]
+fn lighting_aovs_add_up_to_the_beauty() {
+    let scene = BuiltinScene::CornellBox.build();
+    let image = pollster::block_on(render_with(scene, [WIDTH, HEIGHT], 16, |renderer| {
+        renderer.denoise = false;
+        renderer.set_aovs_enabled(true);
+    }))
+    .unwrap();
+    let aov = |wanted: Aov| &image.aovs.iter().find(|(aov, _)| *aov == wanted).unwrap().1;
+    let lighting = [Aov::DirectDiffuse, Aov::IndirectDiffuse, Aov::Specular, Aov::Emission].map(aov);
+    for (i, beauty) in image.beauty.iter().enumerate() {
+        for c in 0..3 {
+            let sum: f32 = lighting.iter().map(|pixels| pixels[i][c]).sum();
+            assert!(
+                (sum - beauty[c]).abs() <= 1e-4 + 1e-4 * beauty[c].abs(),
+                "pixel {i} channel {c}: the lighting AOVs add up to {sum}, the beauty is {}",
+                beauty[c]
+            );
+        }
+    }
+    // The walls see the ceiling light directly, and that light is counted as direct.
+    let total = |pixels: &[[f32; 4]]| pixels.iter().map(|pixel| pixel[..3].iter().sum::<f32>()).sum::<f32>();
+    let (direct, indirect) = (total(lighting[0]), total(lighting[1]));
+    assert!(direct > 0.25 * (direct + indirect), "direct {direct}, indirect {indirect}");
+}
+
+/// The furnace test of `src/renderer/furnace.rs`, on the GPU: a ball of each kind of material
+/// returns its color, or its emission, under uniform light.
+#[test]
+fn materials_pass_the_furnace() {
+    let transparent = |name: &str, color: Vec4, transparency: f32, refractive_index: f32| Material {
+        transparency,
+        refractive_index,
+        ..Material::new(name, color, 1.0)
+    };
+    let mut materials = Vec::new();
+    for color in [Vec4::all(1.0), Vec4::new(0.8, 0.5, 0.2, 1.0)] {
+        materials.extend([
+            Material::new("diffuse", color, 0.0),
+            Material::new("glossy", color, 0.3),
+            Material::new("shiny", color, 0.7),
+            Material::new("mirror", color, 1.0),
+            Material { transparency: 0.4, refractive_index: 1.5, ..Material::new("translucent", color, 0.0) },
+            transparent("tinted glass", color, 0.6, 1.5),
+            transparent("glass", color, 1.0, 1.5),
+            transparent("dense glass", color, 1.0, 2.4),
+            transparent("clear", color, 1.0, 1.0),
+        ]);
+    }
+    materials.push(Material { emission: Vec4::new(2.0, 1.0, 0.5, 0.0), ..Material::new("lamp", Vec4::all(1.0), 0.0) });
+
+    for material in &materials {
+        let image = pollster::block_on(render_with(furnace_scene(material), [FURNACE_SIZE; 2], 4, |renderer| {
+            renderer.denoise = false;
+        }))
+        .unwrap();
+        let reading = FurnaceReading::new(material, &image);
+        assert!(reading.gain() < 1e-4 && reading.loss() < 1e-4, "{reading}");
+    }
+}
+
+/// Renders `scene` with `spp` samples per pixel and fails if the RMSE against the reference
+/// image `name.exr` is above `tolerance`.
+fn check_golden(name: &str, scene: Scene, spp: u32, denoise: bool, tolerance: f32) {
+    let image = pollster::block_on(render(scene, spp, denoise)).unwrap();
+    let reference_path = golden_dir().join(format!("{name}.exr"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        image.save_exr(&reference_path).unwrap();
+        return;
+    }
+
+    let reference = RenderOutput::load_exr(&reference_path)
+        .context("run with UPDATE_GOLDEN=1 to create the reference")
+        .unwrap();
+    let comparison = ImageComparison::new(&image, &reference).unwrap();
+    if comparison.rmse > tolerance || comparison.non_finite > 0 {
+        let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+        std::fs::create_dir_all(&out).unwrap();
+        let render_path = out.join(format!("{name}.exr"));
+        let error_path = out.join(format!("{name}.error.exr"));
+        image.save_exr(&render_path).unwrap();
+        comparison.error_image().save_exr(&error_path).unwrap();
+        panic!(
+            "{name} differs from its reference by more than RMSE {tolerance}:\n{comparison}\n\
+             wrote the render to {} and the error to {}",
+            render_path.display(),
+            error_path.display()
+        );
+    }
+}
+
+async fn render(scene: Scene, spp: u32, denoise: bool) -> Result<RenderOutput> {
+    render_with(scene, [WIDTH, HEIGHT], spp, |renderer| renderer.denoise = denoise).await
+}
+
+/// Renders `scene` at `width` by `height` pixels with `spp` samples per pixel, after `setup` has
+/// configured the tracer.
+async fn render_with(
+    scene: Scene,
+    [width, height]: [u32; 2],
+    spp: u32,
+    setup: impl FnOnce(&mut PathTracer),
+) -> Result<RenderOutput> {
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::from_env_or_default());
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::None,
+            force_fallback_adapter: true,
+            compatible_surface: None,
+        })
+        .await
+        .context("failed to find a fallback adapter")?;
+    // Validation errors panic, which fails the test.
+    let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor::default()).await?;
+    let mut renderer = PathTracer::new(
+        &device,
+        &queue,
+        scene,
+        width,
+        height,
+        wgpu::TextureFormat::Rgba8Unorm,
+    )?;
+    setup(&mut renderer);
+    for _ in 0..spp.div_ceil(renderer.settings.samples) {
+        renderer.render();
+        device.poll(wgpu::PollType::Wait)?;
+    }
+    renderer.read_output()
+}
+
+/// Loads a scene file from a path relative to the crate root.
+fn load(path: &str) -> Scene {
+    Scene::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join(path)).unwrap()
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}